@@ -0,0 +1,137 @@
+//! Authentication with bearer tokens or HTTP basic auth
+//!
+//! Credentials are set with Worker secrets, separately for read-only and
+//! read-write access. Without any credentials, every request is denied, unless
+//! authentication is turned off with `AUTH_DISABLED = "true"`.
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use sha2::{Digest, Sha256};
+
+/// Secrets with the credentials, each a comma separated list, of tokens or `user:password` pairs
+pub(crate) const READ_ONLY_TOKENS: &str = "READ_ONLY_TOKENS";
+pub(crate) const READ_WRITE_TOKENS: &str = "READ_WRITE_TOKENS";
+pub(crate) const READ_ONLY_USERS: &str = "READ_ONLY_USERS";
+pub(crate) const READ_WRITE_USERS: &str = "READ_WRITE_USERS";
+/// Variable that gives everyone read-write access if it is `true`
+pub(crate) const AUTH_DISABLED: &str = "AUTH_DISABLED";
+
+/// What a client may do, read-write includes read-only
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum Role {
+    ReadOnly,
+    ReadWrite,
+}
+
+/// Compare secrets in constant time, their digests have the same length
+fn secret_eq(left: &str, right: &str) -> bool {
+    let (left, right) = (Sha256::digest(left), Sha256::digest(right));
+    left.iter().zip(right.iter()).fold(0, |difference, (left, right)| difference | (left ^ right)) == 0
+}
+
+fn split_list(value: &str) -> impl Iterator<Item = String> + '_ {
+    value.split(',').map(str::trim).filter(|item| !item.is_empty()).map(String::from)
+}
+
+/// The configured tokens and users, with their roles
+#[derive(Debug, Default)]
+pub(crate) struct Credentials {
+    tokens: Vec<(String, Role)>,
+    /// `user:password` pairs
+    users: Vec<(String, Role)>,
+    /// authentication is turned off explicitly
+    disabled: bool,
+}
+
+impl Credentials {
+    /// Read the credentials from the secrets and `AUTH_DISABLED`, `secret` returns the value of one
+    pub fn from_secrets(secret: impl Fn(&str) -> Option<String>) -> Self {
+        let list = |name, role| secret(name).map(|value| split_list(&value).map(|item| (item, role)).collect::<Vec<_>>()).unwrap_or_default();
+        Self {
+            tokens: [list(READ_ONLY_TOKENS, Role::ReadOnly), list(READ_WRITE_TOKENS, Role::ReadWrite)].concat(),
+            users: [list(READ_ONLY_USERS, Role::ReadOnly), list(READ_WRITE_USERS, Role::ReadWrite)].concat(),
+            disabled: secret(AUTH_DISABLED).is_some_and(|value| value.trim().eq_ignore_ascii_case("true")),
+        }
+    }
+
+    /// Whether any credentials are set, without them no request is authenticated
+    pub fn is_configured(&self) -> bool {
+        !self.tokens.is_empty() || !self.users.is_empty()
+    }
+
+    /// The `WWW-Authenticate` challenge for unauthenticated requests
+    pub fn challenge(&self) -> &'static str {
+        if self.users.is_empty() { "Bearer" } else { "Basic realm=\"ferrobean\", charset=\"UTF-8\"" }
+    }
+
+    /// The role of a request with the given `Authorization` header, `None` if it is not authenticated
+    pub fn role(&self, authorization: Option<&str>) -> Option<Role> {
+        if self.disabled {
+            return Some(Role::ReadWrite);
+        }
+        let (scheme, value) = authorization?.trim().split_once(' ')?;
+        let value = value.trim();
+        let (candidates, presented) = if scheme.eq_ignore_ascii_case("bearer") {
+            (&self.tokens, value.to_string())
+        } else if scheme.eq_ignore_ascii_case("basic") {
+            (&self.users, String::from_utf8(STANDARD.decode(value).ok()?).ok()?)
+        } else {
+            return None;
+        };
+        // the highest role of all matches, without stopping at the first one
+        candidates
+            .iter()
+            .filter(|(secret, _)| secret_eq(secret, &presented))
+            .map(|(_, role)| *role)
+            .max()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn credentials() -> Credentials {
+        Credentials::from_secrets(|name| match name {
+            READ_ONLY_TOKENS => Some("reader-token".into()),
+            READ_WRITE_TOKENS => Some("writer-token, other-token".into()),
+            READ_WRITE_USERS => Some("alex:secret".into()),
+            _ => None,
+        })
+    }
+
+    #[test]
+    fn roles() {
+        let credentials = credentials();
+        assert_eq!(credentials.role(Some("Bearer reader-token")), Some(Role::ReadOnly));
+        assert_eq!(credentials.role(Some("bearer other-token")), Some(Role::ReadWrite));
+        assert_eq!(credentials.role(Some(&format!("Basic {}", STANDARD.encode("alex:secret")))), Some(Role::ReadWrite));
+
+        assert_eq!(credentials.role(Some(&format!("Basic {}", STANDARD.encode("alex:wrong")))), None);
+        assert_eq!(credentials.role(Some("Basic not-base64!")), None);
+        assert_eq!(credentials.role(Some("Bearer alex:secret")), None);
+        assert_eq!(credentials.role(Some("Token writer-token")), None);
+        assert_eq!(credentials.role(None), None);
+        assert_eq!(credentials.challenge(), "Basic realm=\"ferrobean\", charset=\"UTF-8\"");
+    }
+
+    #[test]
+    fn unconfigured() {
+        let credentials = Credentials::from_secrets(|_| None);
+        assert!(!credentials.is_configured());
+        assert_eq!(credentials.role(None), None);
+        assert_eq!(credentials.role(Some("Bearer ")), None);
+        assert_eq!(credentials.role(Some(&format!("Basic {}", STANDARD.encode(":")))), None);
+    }
+
+    #[test]
+    fn disabled() {
+        let credentials = Credentials::from_secrets(|name| (name == AUTH_DISABLED).then(|| "true".into()));
+        assert!(!credentials.is_configured());
+        assert_eq!(credentials.role(None), Some(Role::ReadWrite));
+        assert_eq!(credentials.role(Some("Bearer anything")), Some(Role::ReadWrite));
+
+        let credentials = Credentials::from_secrets(|name| (name == AUTH_DISABLED).then(|| "false".into()));
+        assert_eq!(credentials.role(None), None);
+    }
+}
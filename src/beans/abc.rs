@@ -1,88 +1,379 @@
 //! Abstract base classes for Beancount types
 
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::Serialize;
+use serde::ser::SerializeStruct;
+
 use crate::beans::*;
 
-trait Amount {
+#[allow(dead_code)]
+pub(crate) trait Amount {
     /// Number of units in the amount
     fn get_value(&self) -> f32;
-    fn get_currency(&self) -> &'static str;
+    fn get_currency(&self) -> &str;
 }
 
 /// an amount with date and label
-trait Cost: Amount {
-    fn get_date(&self) -> time::Date;
+#[allow(dead_code)]
+pub(crate) trait Cost: Amount {
+    fn get_date(&self) -> Option<time::Date>;
     fn get_label(&self) -> Option<String>;
 }
 
-#[derive(Debug, PartialEq)]
-pub(crate) struct AAmount(pub f32, pub &'static str);
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct AAmount(pub f32, pub String);
+
+impl Serialize for AAmount {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut amount = serializer.serialize_struct("Amount", 2)?;
+        amount.serialize_field("number", &self.0)?;
+        amount.serialize_field("currency", &self.1)?;
+        amount.end()
+    }
+}
 
 impl Amount for AAmount {
     fn get_value(&self) -> f32 {
         self.0
     }
 
-    fn get_currency(&self) -> &'static str {
-        self.1
+    fn get_currency(&self) -> &str {
+        &self.1
+    }
+}
+
+/// A cost basis: per-unit number and currency, with an optional lot date and label
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct ACost {
+    pub number: f32,
+    pub currency: String,
+    /// the lot date, if it is written
+    pub date: Option<time::Date>,
+    pub label: Option<String>,
+}
+
+impl Amount for ACost {
+    fn get_value(&self) -> f32 {
+        self.number
+    }
+
+    fn get_currency(&self) -> &str {
+        &self.currency
+    }
+}
+
+impl Cost for ACost {
+    fn get_date(&self) -> Option<time::Date> {
+        self.date
+    }
+
+    fn get_label(&self) -> Option<String> {
+        self.label.clone()
     }
 }
 
 type DiffAmount = Option<AAmount>;
 
+/// A metadata value
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(untagged)]
+pub(crate) enum MetaValue {
+    String(String),
+    Account(String),
+    Currency(String),
+    Tag(String),
+    Date(time::Date),
+    Number(f32),
+    Amount(AAmount),
+    Bool(bool),
+}
+
+/// Entry metadata: where the entry was defined plus its key-value pairs
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub(crate) struct Meta {
+    pub filename: String,
+    pub lineno: usize,
+    #[serde(flatten)]
+    pub values: BTreeMap<String, MetaValue>,
+}
+
+impl Meta {
+    pub fn new(filename: impl Into<String>, lineno: usize) -> Self {
+        Self {
+            filename: filename.into(),
+            lineno,
+            values: BTreeMap::new(),
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&MetaValue> {
+        self.values.get(key)
+    }
+}
+
 /// an Entry, must have a Date
-/// 
+///
 /// see https://beancount.github.io/docs/beancount_language_syntax.html#directives
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "t")]
 pub(crate) enum Directive {
-    // time::Date, Account
-    Open,
-    // time::Date, Account
-    Close,
-    Commodity,
-    Transactions(Transaction), // , Posting
-    
-    // time::Date, Account, Meta(String)
-    Note,
-    Balance(time::Date, String, DiffAmount),
-
-    Document,
-    // Custom(Box<dyn Entry>),
-    Budget,
+    Open(Open),
+    Close(Close),
+    Commodity(Commodity),
+    #[serde(rename = "Transaction")]
+    Transactions(Transaction),
+    Note(Note),
+    Balance(Balance),
+    Pad(Pad),
+    Document(Document),
+    Event(Event),
+    Price(Price),
+    Query(Query),
+    /// also used for budgets, see https://fava.pythonanywhere.com/example-beancount-file/help/budgets
+    Custom(Custom),
 }
 
 impl Entry for Directive {
     fn get_date(&self) -> time::Date {
-        // match self {
-        //     (date, _) => date
-        // }
+        match self {
+            Self::Open(e) => e.date,
+            Self::Close(e) => e.date,
+            Self::Commodity(e) => e.date,
+            Self::Transactions(e) => e.date,
+            Self::Note(e) => e.date,
+            Self::Balance(e) => e.date,
+            Self::Pad(e) => e.date,
+            Self::Document(e) => e.date,
+            Self::Event(e) => e.date,
+            Self::Price(e) => e.date,
+            Self::Query(e) => e.date,
+            Self::Custom(e) => e.date,
+        }
+    }
 
-        time::OffsetDateTime::now_utc().date() // temporary
+    fn get_meta(&self) -> &Meta {
+        match self {
+            Self::Open(e) => &e.meta,
+            Self::Close(e) => &e.meta,
+            Self::Commodity(e) => &e.meta,
+            Self::Transactions(e) => &e.meta,
+            Self::Note(e) => &e.meta,
+            Self::Balance(e) => &e.meta,
+            Self::Pad(e) => &e.meta,
+            Self::Document(e) => &e.meta,
+            Self::Event(e) => &e.meta,
+            Self::Price(e) => &e.meta,
+            Self::Query(e) => &e.meta,
+            Self::Custom(e) => &e.meta,
+        }
     }
 }
 
 /// required behavior for a Directive
 pub(crate) trait Entry: PartialEq + std::fmt::Debug {
     fn get_date(&self) -> time::Date;
-    fn get_meta(&self) {
-        todo!()
+    fn get_meta(&self) -> &Meta;
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct Open {
+    pub date: time::Date,
+    pub account: String,
+    pub currencies: Vec<String>,
+    pub booking: Option<String>,
+    pub meta: Meta,
+}
+
+impl Open {
+    #[cfg(test)]
+    pub fn new(date: time::Date, account: impl Into<String>) -> Self {
+        Self {
+            date,
+            account: account.into(),
+            currencies: Vec::new(),
+            booking: None,
+            meta: Meta::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct Close {
+    pub date: time::Date,
+    pub account: String,
+    pub meta: Meta,
+}
+
+impl Close {
+    #[cfg(test)]
+    pub fn new(date: time::Date, account: impl Into<String>) -> Self {
+        Self {
+            date,
+            account: account.into(),
+            meta: Meta::default(),
+        }
     }
 }
 
-#[derive(Debug, PartialEq)]
-pub(crate) struct Transaction(pub flags::Flags);
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct Commodity {
+    pub date: time::Date,
+    pub currency: String,
+    pub meta: Meta,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct Transaction {
+    pub date: time::Date,
+    pub flag: flags::Flags,
+    pub payee: Option<String>,
+    pub narration: String,
+    pub tags: BTreeSet<String>,
+    pub links: BTreeSet<String>,
+    pub postings: Vec<Posting>,
+    pub meta: Meta,
+}
 
 impl Transaction {
+    pub fn new(date: time::Date, flag: flags::Flags, narration: impl Into<String>, postings: Vec<Posting>) -> Self {
+        Self {
+            date,
+            flag,
+            payee: None,
+            narration: narration.into(),
+            tags: BTreeSet::new(),
+            links: BTreeSet::new(),
+            postings,
+            meta: Meta::default(),
+        }
+    }
+
     pub fn is_unrealized(&self) -> bool {
-        self.0 == flags::Flags::Unrealized
+        self.flag == flags::Flags::Unrealized
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct Note {
+    pub date: time::Date,
+    pub account: String,
+    pub comment: String,
+    pub meta: Meta,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct Balance {
+    pub date: time::Date,
+    pub account: String,
+    pub amount: AAmount,
+    pub tolerance: Option<f32>,
+    /// difference to the actual balance, if the check failed
+    pub diff_amount: DiffAmount,
+    pub meta: Meta,
+}
+
+impl Balance {
+    pub fn new(date: time::Date, account: impl Into<String>, amount: AAmount) -> Self {
+        Self {
+            date,
+            account: account.into(),
+            amount,
+            tolerance: None,
+            diff_amount: None,
+            meta: Meta::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct Pad {
+    pub date: time::Date,
+    pub account: String,
+    pub source_account: String,
+    pub meta: Meta,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct Document {
+    pub date: time::Date,
+    pub account: String,
+    pub filename: String,
+    pub tags: BTreeSet<String>,
+    pub links: BTreeSet<String>,
+    pub meta: Meta,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct Event {
+    pub date: time::Date,
+    pub r#type: String,
+    pub description: String,
+    pub meta: Meta,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct Price {
+    pub date: time::Date,
+    pub currency: String,
+    pub amount: AAmount,
+    pub meta: Meta,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct Query {
+    pub date: time::Date,
+    pub name: String,
+    pub query_string: String,
+    pub meta: Meta,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct Custom {
+    pub date: time::Date,
+    pub r#type: String,
+    pub values: Vec<MetaValue>,
+    pub meta: Meta,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct Posting {
+    pub account: String,
+    pub units: AAmount,
+    pub cost: Option<ACost>,
+    pub price: Option<AAmount>,
+    pub flag: Option<flags::Flags>,
+    pub meta: Meta,
+}
+
+impl Posting {
+    pub fn new(account: impl Into<String>, units: AAmount) -> Self {
+        Self {
+            account: account.into(),
+            units,
+            cost: None,
+            price: None,
+            flag: None,
+            meta: Meta::default(),
+        }
     }
 }
 
-pub(crate) struct Posting(Box<dyn Position>);
+impl Position for Posting {
+    fn get_units(&self) -> &AAmount {
+        &self.units
+    }
+
+    fn get_cost(&self) -> Option<&ACost> {
+        self.cost.as_ref()
+    }
+}
 
 /// cost and units
-trait Position {
-    fn get_units(&self) -> Box<dyn Amount>;
-    fn get_cost(&self) -> Option<Box<dyn Cost>>;
+#[allow(dead_code)]
+pub(crate) trait Position {
+    fn get_units(&self) -> &AAmount;
+    fn get_cost(&self) -> Option<&ACost>;
 }
 
 // #[cfg(test)]
@@ -105,8 +396,8 @@ trait Position {
 //                 self.0
 //             }
 //         }
-    
+
 //         let entry = Directive::Custom(Box::new(MyCustomDirective::default()));
 //         entry.get_date();
 //     }
-// }
\ No newline at end of file
+// }
@@ -0,0 +1,37 @@
+//! Account name helpers
+//!
+//! see: https://github.com/beancount/fava/blob/main/src/fava/beans/account.py
+
+/// Parent account, or `None` for a root account
+pub(crate) fn parent(account: &str) -> Option<&str> {
+    account.rsplit_once(':').map(|(parent, _)| parent)
+}
+
+/// Whether `account` is `ancestor` or one of its descendants
+///
+/// The empty account name is the root of all accounts.
+pub(crate) fn is_descendant(account: &str, ancestor: &str) -> bool {
+    ancestor.is_empty()
+        || account == ancestor
+        || account.strip_prefix(ancestor).is_some_and(|rest| rest.starts_with(':'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parents() {
+        assert_eq!(parent("Assets:Cash:Wallet"), Some("Assets:Cash"));
+        assert_eq!(parent("Assets"), None);
+    }
+
+    #[test]
+    fn descendants() {
+        assert!(is_descendant("Assets:Cash", "Assets"));
+        assert!(is_descendant("Assets:Cash", "Assets:Cash"));
+        assert!(is_descendant("Assets:Cash", ""));
+        assert!(!is_descendant("Assets:Cashback", "Assets:Cash"));
+        assert!(!is_descendant("Assets", "Assets:Cash"));
+    }
+}
@@ -7,7 +7,7 @@ use crate::Helpers;
 /// see https://beancount.github.io/docs/beancount_design_doc.html#flag
 /// note: rust does not allow string literal discriminants in an enum: https://doc.rust-lang.org/reference/items/enumerations.html#r-items.enum.discriminant.repr-rust
 ///  but we CAN use [char](https://doc.rust-lang.org/std/primitive.char.html)s as [u8](https://doc.rust-lang.org/std/primitive.u8.html) bytes 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 #[repr(u8)]
 pub(crate) enum Flags {
     Conversion = b'C',
@@ -49,6 +49,12 @@ impl TryFrom<u8> for Flags {
     }
 }
 
+impl serde::Serialize for Flags {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
 // TODO: impl From<char> for Flags
 // TODO: impl From<Flags> for char
 
@@ -76,7 +82,7 @@ impl std::str::FromStr for Flags {
 }
 
 impl Flags {
-    fn as_str(&self) -> &'static str {
+    pub fn as_str(&self) -> &'static str {
         match self {
             Self::Conversion => "C",
             Self::Merging => "M",
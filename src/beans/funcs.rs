@@ -0,0 +1,154 @@
+//! Functions on Beancount entries
+//!
+//! see: https://github.com/beancount/fava/blob/main/src/fava/beans/funcs.py
+
+use sha2::{Digest, Sha256};
+
+use crate::beans::abc::{Directive, Entry, MetaValue};
+
+/// Hash of the entry's contents, stable across reloads
+///
+/// Like Beancount's `hash_entry`, this ignores the metadata (and so the
+/// location of the entry) and the computed `diff_amount` of balance entries.
+pub(crate) fn hash_entry(entry: &Directive) -> String {
+    fn strip(value: &mut serde_json::Value) {
+        match value {
+            serde_json::Value::Object(map) => {
+                map.remove("meta");
+                map.remove("diff_amount");
+                map.values_mut().for_each(strip);
+            }
+            serde_json::Value::Array(values) => values.iter_mut().for_each(strip),
+            _ => {}
+        }
+    }
+
+    let mut value = serde_json::to_value(entry).expect("entries serialise to JSON");
+    strip(&mut value);
+    hex(&Sha256::digest(value.to_string()))
+}
+
+/// Lowercase hex representation of `bytes`
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// All accounts the entry refers to
+pub(crate) fn get_entry_accounts(entry: &Directive) -> Vec<&str> {
+    match entry {
+        Directive::Open(e) => vec![&e.account],
+        Directive::Close(e) => vec![&e.account],
+        Directive::Transactions(e) => e.postings.iter().map(|posting| posting.account.as_str()).collect(),
+        Directive::Note(e) => vec![&e.account],
+        Directive::Balance(e) => vec![&e.account],
+        Directive::Pad(e) => vec![&e.account, &e.source_account],
+        Directive::Document(e) => vec![&e.account],
+        Directive::Custom(e) => e
+            .values
+            .iter()
+            .filter_map(|value| match value {
+                MetaValue::Account(account) => Some(account.as_str()),
+                _ => None,
+            })
+            .collect(),
+        Directive::Commodity(_) | Directive::Event(_) | Directive::Price(_) | Directive::Query(_) => Vec::new(),
+    }
+}
+
+/// Name of the entry's directive type, e.g. `Transaction`
+pub(crate) fn entry_type(entry: &Directive) -> &'static str {
+    match entry {
+        Directive::Open(_) => "Open",
+        Directive::Close(_) => "Close",
+        Directive::Commodity(_) => "Commodity",
+        Directive::Transactions(_) => "Transaction",
+        Directive::Note(_) => "Note",
+        Directive::Balance(_) => "Balance",
+        Directive::Pad(_) => "Pad",
+        Directive::Document(_) => "Document",
+        Directive::Event(_) => "Event",
+        Directive::Price(_) => "Price",
+        Directive::Query(_) => "Query",
+        Directive::Custom(_) => "Custom",
+    }
+}
+
+/// Sort key of an entry, Beancount's order of entries within a ledger
+///
+/// On a single day, accounts are opened first and balances are checked before
+/// any transactions, while documents and closings come last.
+pub(crate) fn sort_key(entry: &Directive) -> (time::Date, i8, usize) {
+    let type_order = match entry {
+        Directive::Open(_) => -2,
+        Directive::Balance(_) => -1,
+        Directive::Document(_) => 1,
+        Directive::Close(_) => 2,
+        _ => 0,
+    };
+    (entry.get_date(), type_order, entry.get_meta().lineno)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::beans::abc::{AAmount, Balance, Close, Meta, Open, Posting, Transaction};
+    use crate::beans::flags::Flags;
+    use time::macros::date;
+
+    fn transaction() -> Transaction {
+        Transaction::new(
+            date!(2020-01-02),
+            Flags::Okay,
+            "Groceries",
+            vec![Posting::new("Expenses:Food", AAmount(20., "USD".into())), Posting::new("Assets:Cash", AAmount(-20., "USD".into()))],
+        )
+    }
+
+    #[test]
+    fn hash_ignores_meta() {
+        let entry = transaction();
+        let mut moved = transaction();
+        moved.meta = Meta::new("other.beancount", 42);
+        assert_eq!(hash_entry(&Directive::Transactions(entry.clone())), hash_entry(&Directive::Transactions(moved)));
+
+        let mut changed = transaction();
+        changed.narration = "Restaurant".into();
+        assert_ne!(hash_entry(&Directive::Transactions(entry.clone())), hash_entry(&Directive::Transactions(changed)));
+        assert_eq!(hash_entry(&Directive::Transactions(entry)).len(), 64);
+    }
+
+    #[test]
+    fn hash_ignores_diff_amount() {
+        let balance = Balance::new(date!(2020-01-02), "Assets:Cash", AAmount(20., "USD".into()));
+        let mut failed = balance.clone();
+        failed.diff_amount = Some(AAmount(1., "USD".into()));
+        assert_eq!(hash_entry(&Directive::Balance(balance)), hash_entry(&Directive::Balance(failed)));
+    }
+
+    #[test]
+    fn entry_accounts() {
+        assert_eq!(get_entry_accounts(&Directive::Transactions(transaction())), vec!["Expenses:Food", "Assets:Cash"]);
+        assert_eq!(get_entry_accounts(&Directive::Open(Open::new(date!(2020-01-01), "Assets:Cash"))), vec!["Assets:Cash"]);
+    }
+
+    #[test]
+    fn sorting() {
+        let mut entries = [
+            Directive::Close(Close::new(date!(2020-01-02), "Assets:Cash")),
+            Directive::Transactions(transaction()),
+            Directive::Balance(Balance::new(date!(2020-01-02), "Assets:Cash", AAmount(20., "USD".into()))),
+            Directive::Open(Open::new(date!(2020-01-02), "Assets:Cash")),
+        ];
+        entries.sort_by_key(sort_key);
+        let types: Vec<&str> = entries
+            .iter()
+            .map(|entry| match entry {
+                Directive::Open(_) => "open",
+                Directive::Balance(_) => "balance",
+                Directive::Transactions(_) => "txn",
+                _ => "close",
+            })
+            .collect();
+        assert_eq!(types, vec!["open", "balance", "txn", "close"]);
+    }
+}
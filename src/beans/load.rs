@@ -0,0 +1,381 @@
+//! Loading a ledger: the main file and its includes, padding and balance checks
+//!
+//! see: https://github.com/beancount/beancount/blob/v2/beancount/loader.py
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, hash_map};
+
+use crate::Helpers;
+use crate::beans::abc::{AAmount, Directive, Entry, Meta, Posting, Transaction};
+use crate::beans::account;
+use crate::beans::flags::Flags;
+use crate::beans::funcs::{get_entry_accounts, sort_key};
+use crate::beans::options::BeancountOptions;
+use crate::beans::parser::{self, RawPosting, parse_string};
+use crate::core::inventory::{self, CounterInventory, LotInventory};
+
+/// Beancount's tolerance of balance checks without an explicit one
+const DEFAULT_TOLERANCE: f32 = 0.005;
+
+/// Everything loaded from a ledger's files
+#[derive(Debug, Default)]
+pub(crate) struct LoadedLedger {
+    /// all entries, sorted
+    pub entries: Vec<Directive>,
+    pub errors: Vec<Helpers>,
+    pub options: BeancountOptions,
+    /// source text of every file, by path
+    pub sources: BTreeMap<String, String>,
+    /// paths of the included files that could not be read
+    pub missing: BTreeSet<String>,
+}
+
+/// An error located at an entry
+pub(crate) fn entry_error(meta: &Meta, message: impl std::fmt::Display) -> Helpers {
+    Helpers::BeancountError(format!("{}:{}: {message}", meta.filename, meta.lineno))
+}
+
+/// Path of `include`, relative to the file that includes it
+///
+/// A leading `/` is the root of the storage.
+pub(crate) fn include_path(including: &str, include: &str) -> String {
+    let mut segments: Vec<&str> = match including.rsplit_once('/') {
+        Some((directory, _)) if !include.starts_with('/') => directory.split('/').collect(),
+        _ => Vec::new(),
+    };
+    for segment in include.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            segment => segments.push(segment),
+        }
+    }
+    segments.join("/")
+}
+
+/// Load `filename` and the files it includes, reading each with `read`
+pub(crate) async fn load_file<F, Fut>(filename: &str, read: F) -> LoadedLedger
+where
+    F: Fn(String) -> Fut,
+    Fut: Future<Output = Result<String, Helpers>>,
+{
+    let mut loaded = LoadedLedger::default();
+    let mut unbooked = HashMap::new();
+    let mut queue = vec![filename.to_string()];
+    while let Some(path) = queue.pop() {
+        if loaded.sources.contains_key(&path) {
+            continue;
+        }
+        let source = match read(path.clone()).await {
+            Ok(source) => source,
+            Err(error) => {
+                loaded.errors.push(error);
+                loaded.missing.insert(path);
+                continue;
+            }
+        };
+
+        let parsed = parse_string(&source, &path);
+        // options only apply in the main file, like in Beancount
+        if path == filename {
+            for (name, value) in &parsed.options {
+                loaded.options.set(name, value);
+            }
+        }
+        queue.extend(parsed.includes.iter().rev().map(|include| include_path(&path, include)));
+        unbooked.extend(parsed.unbooked.into_iter().map(|(lineno, postings)| ((path.clone(), lineno), postings)));
+        loaded.entries.extend(parsed.entries);
+        loaded.errors.extend(parsed.errors);
+        loaded.sources.insert(path, source);
+    }
+
+    run_checks(&mut loaded.entries, unbooked, &mut loaded.errors);
+    loaded
+}
+
+/// Fill in the cost left out of a posting from the single lot it reduces
+fn fill_cost(raw: &mut RawPosting, lots: Option<&LotInventory>) -> Result<(), String> {
+    let Some(spec) = raw.cost_spec.take() else {
+        return Ok(());
+    };
+    let units = &raw.posting.units;
+    let matches: Vec<_> = lots
+        .map(LotInventory::positions)
+        .unwrap_or_default()
+        .iter()
+        .filter(|position| position.units.1 == units.1 && position.units.0.signum() != units.0.signum())
+        .filter_map(|position| position.cost.as_ref())
+        .filter(|cost| spec.matches(cost))
+        .collect();
+    match matches.as_slice() {
+        [cost] => {
+            raw.posting.cost = Some((*cost).clone());
+            Ok(())
+        }
+        [] => Err(format!("No position matches {} {} in {}", units.0, units.1, raw.posting.account)),
+        _ => Err(format!("Ambiguous matches for {} {} in {}", units.0, units.1, raw.posting.account)),
+    }
+}
+
+/// Book the transactions whose costs are left out, by the file and line they start on
+///
+/// Their costs are those of the lots they reduce, held by the entries before them.
+fn book_lots(entries: &mut [Directive], mut unbooked: HashMap<(String, usize), Vec<RawPosting>>, errors: &mut Vec<Helpers>) {
+    let mut lots: HashMap<String, LotInventory> = HashMap::new();
+    for entry in entries.iter_mut() {
+        if unbooked.is_empty() {
+            break;
+        }
+        let Directive::Transactions(transaction) = entry else {
+            continue;
+        };
+        if let Some(mut postings) = unbooked.remove(&(transaction.meta.filename.clone(), transaction.meta.lineno)) {
+            let booked = postings
+                .iter_mut()
+                .try_for_each(|raw| fill_cost(raw, lots.get(&raw.posting.account)))
+                .and_then(|()| parser::book(postings));
+            match booked {
+                Ok(postings) => transaction.postings = postings,
+                Err(message) => errors.push(entry_error(&transaction.meta, message)),
+            }
+        }
+        for posting in transaction.postings.iter().filter(|posting| posting.cost.is_some()) {
+            lots.entry(posting.account.clone()).or_default().add_position(&posting.units, posting.cost.as_ref());
+        }
+    }
+}
+
+/// Sort the entries, book left out costs, insert padding and check balances and account references
+pub(crate) fn run_checks(entries: &mut Vec<Directive>, unbooked: HashMap<(String, usize), Vec<RawPosting>>, errors: &mut Vec<Helpers>) {
+    entries.sort_by_key(sort_key);
+    book_lots(entries, unbooked, errors);
+
+    let mut lifetimes: HashMap<&str, (time::Date, Option<time::Date>)> = HashMap::new();
+    for entry in entries.iter() {
+        match entry {
+            Directive::Open(open) => match lifetimes.entry(&open.account) {
+                hash_map::Entry::Occupied(_) => errors.push(entry_error(&open.meta, format!("Duplicate open directive for {}", open.account))),
+                hash_map::Entry::Vacant(lifetime) => {
+                    lifetime.insert((open.date, None));
+                }
+            },
+            Directive::Close(close) => match lifetimes.get_mut(close.account.as_str()) {
+                Some((_, closed)) => *closed = Some(close.date),
+                None => errors.push(entry_error(&close.meta, format!("Closing unopened account {}", close.account))),
+            },
+            _ => {}
+        }
+    }
+    for entry in entries.iter().filter(|entry| !matches!(entry, Directive::Open(_) | Directive::Close(_))) {
+        let date = entry.get_date();
+        for account in get_entry_accounts(entry) {
+            let active = lifetimes.get(account).is_some_and(|(opened, closed)| *opened <= date && closed.is_none_or(|closed| date <= closed));
+            if !active {
+                errors.push(entry_error(entry.get_meta(), format!("Invalid reference to inactive account {account}")));
+            }
+        }
+    }
+
+    let mut balances: HashMap<String, CounterInventory> = HashMap::new();
+    // pending pads by account, with the currencies they have been used for
+    let mut pads = HashMap::new();
+    let mut padding = Vec::new();
+    for entry in entries.iter_mut() {
+        match entry {
+            Directive::Transactions(transaction) => {
+                for posting in &transaction.postings {
+                    inventory::add_amount(balances.entry(posting.account.clone()).or_default(), &posting.units.1, posting.units.0);
+                }
+            }
+            Directive::Pad(pad) => {
+                pads.insert(pad.account.clone(), (pad.clone(), BTreeSet::new()));
+            }
+            Directive::Balance(balance) => {
+                let currency = &balance.amount.1;
+                let accumulated: f32 = balances
+                    .iter()
+                    .filter(|(name, _)| account::is_descendant(name, &balance.account))
+                    .filter_map(|(_, inventory)| inventory.get(currency))
+                    .sum();
+                let mut diff = accumulated - balance.amount.0;
+                let tolerance = balance.tolerance.unwrap_or(DEFAULT_TOLERANCE);
+
+                if let Some((pad, padded)) = pads.get_mut(&balance.account)
+                    && padded.insert(currency.clone())
+                    && diff.abs() > tolerance
+                {
+                    let units = AAmount(-diff, currency.clone());
+                    let mut transaction = Transaction::new(
+                        pad.date,
+                        Flags::Padding,
+                        format!("(Padding inserted for Balance of {} {currency} for difference {} {currency})", balance.amount.0, units.0),
+                        vec![
+                            Posting::new(pad.account.clone(), units.clone()),
+                            Posting::new(pad.source_account.clone(), AAmount(diff, currency.clone())),
+                        ],
+                    );
+                    transaction.meta = pad.meta.clone();
+                    for posting in &transaction.postings {
+                        inventory::add_amount(balances.entry(posting.account.clone()).or_default(), &posting.units.1, posting.units.0);
+                    }
+                    padding.push(Directive::Transactions(transaction));
+                    diff = 0.;
+                }
+
+                if diff.abs() > tolerance {
+                    let direction = if diff > 0. { "too much" } else { "too little" };
+                    errors.push(entry_error(
+                        &balance.meta,
+                        format!(
+                            "Balance failed for '{}': expected {} {currency} != accumulated {accumulated} {currency} ({} {direction})",
+                            balance.account,
+                            balance.amount.0,
+                            diff.abs()
+                        ),
+                    ));
+                    balance.diff_amount = Some(AAmount(diff, currency.clone()));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    if !padding.is_empty() {
+        entries.extend(padding);
+        entries.sort_by_key(sort_key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{MemoryStorage, Storage, block_on};
+
+    fn load(files: &[(&str, &str)]) -> LoadedLedger {
+        let storage = MemoryStorage::new(files.iter().copied());
+        block_on(load_file("ledger/main.beancount", |path| {
+            let storage = storage.clone();
+            async move { storage.read(&path).await }
+        }))
+    }
+
+    #[test]
+    fn paths() {
+        assert_eq!(include_path("main.beancount", "other.beancount"), "other.beancount");
+        assert_eq!(include_path("ledger/main.beancount", "./2020/../prices.beancount"), "ledger/prices.beancount");
+        assert_eq!(include_path("ledger/main.beancount", "/shared.beancount"), "shared.beancount");
+    }
+
+    #[test]
+    fn includes() {
+        let loaded = load(&[
+            (
+                "ledger/main.beancount",
+                "option \"title\" \"Main\"\noption \"operating_currency\" \"USD\"\ninclude \"accounts.beancount\"\ninclude \"missing.beancount\"\n\n2020-01-02 * \"Coffee\"\n  Expenses:Food  3 USD\n  Assets:Cash\n",
+            ),
+            ("ledger/accounts.beancount", "option \"title\" \"Ignored\"\ninclude \"main.beancount\"\n2020-01-01 open Assets:Cash\n2020-01-01 open Expenses:Food\n"),
+        ]);
+        assert_eq!(loaded.options.title, "Main");
+        assert_eq!(loaded.options.operating_currency, vec!["USD"]);
+        assert_eq!(loaded.sources.keys().collect::<Vec<_>>(), vec!["ledger/accounts.beancount", "ledger/main.beancount"]);
+        assert_eq!(loaded.errors, vec![Helpers::FavaError("File not found: ledger/missing.beancount".into())]);
+        assert_eq!(loaded.missing, BTreeSet::from(["ledger/missing.beancount".into()]));
+        assert_eq!(loaded.entries.len(), 3);
+        assert!(matches!(loaded.entries[2], Directive::Transactions(_)));
+    }
+
+    #[test]
+    fn balances() {
+        let loaded = load(&[(
+            "ledger/main.beancount",
+            r#"2020-01-01 open Assets:Cash
+2020-01-01 open Assets:Bank
+2020-01-01 open Assets:Bank:Checking
+2020-01-01 open Equity:Opening-Balances
+2020-01-01 pad Assets:Cash Equity:Opening-Balances
+2020-01-02 balance Assets:Cash  100 USD
+2020-01-03 * "Deposit"
+  Assets:Bank:Checking  30 USD
+  Assets:Cash
+2020-01-04 balance Assets:Cash  70 USD
+2020-01-04 balance Assets:Bank  40 USD
+2020-01-05 balance Assets:Cash  70.004 USD
+2020-01-06 note Assets:Unknown "Not opened"
+"#,
+        )]);
+
+        let padding = loaded
+            .entries
+            .iter()
+            .find_map(|entry| match entry {
+                Directive::Transactions(transaction) if transaction.flag == Flags::Padding => Some(transaction),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(padding.date, time::macros::date!(2020-01-01));
+        assert_eq!(padding.postings[0].units, AAmount(100., "USD".into()));
+
+        assert_eq!(
+            loaded.errors,
+            vec![
+                Helpers::BeancountError("ledger/main.beancount:13: Invalid reference to inactive account Assets:Unknown".into()),
+                Helpers::BeancountError(
+                    "ledger/main.beancount:11: Balance failed for 'Assets:Bank': expected 40 USD != accumulated 30 USD (10 too little)".into()
+                ),
+            ]
+        );
+        let failed = loaded
+            .entries
+            .iter()
+            .filter_map(|entry| match entry {
+                Directive::Balance(balance) => balance.diff_amount.clone(),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(failed, vec![AAmount(-10., "USD".into())]);
+    }
+
+    #[test]
+    fn lots() {
+        let loaded = load(&[(
+            "ledger/main.beancount",
+            r#"2020-01-01 open Assets:Cash
+2020-01-01 open Assets:Broker
+2020-01-01 open Income:Gains
+2020-01-02 * "Buy"
+  Assets:Broker  10 STOCK {100 USD}
+  Assets:Cash
+2020-02-02 * "Sell"
+  Assets:Broker  -5 STOCK {100 USD}
+  Assets:Cash
+2020-03-02 * "Sell the rest"
+  Assets:Broker  -5 STOCK {}
+  Assets:Cash    550 USD
+  Income:Gains
+2020-03-03 * "Sell more"
+  Assets:Broker  -1 STOCK {}
+  Assets:Cash
+"#,
+        )]);
+        assert_eq!(
+            loaded.errors,
+            vec![Helpers::BeancountError("ledger/main.beancount:14: No position matches -1 STOCK in Assets:Broker".into())]
+        );
+
+        let mut lots = LotInventory::default();
+        for entry in &loaded.entries {
+            if let Directive::Transactions(transaction) = entry {
+                for posting in transaction.postings.iter().filter(|posting| posting.account == "Assets:Broker") {
+                    lots.add_position(&posting.units, posting.cost.as_ref());
+                }
+            }
+        }
+        assert!(lots.is_empty());
+
+        let Directive::Transactions(sale) = &loaded.entries[5] else { panic!("expected a transaction") };
+        let cost = sale.postings[0].cost.as_ref().unwrap();
+        assert_eq!((cost.number, cost.currency.as_str(), cost.date), (100., "USD", None));
+        assert_eq!(sale.postings[2].units, AAmount(-50., "USD".into()));
+    }
+}
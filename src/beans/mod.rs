@@ -1,4 +1,11 @@
 //! Types, functions and wrappers for Beancount
 
 pub(crate) mod abc;
-pub(crate) mod flags;
\ No newline at end of file
+pub(crate) mod account;
+pub(crate) mod flags;
+pub(crate) mod funcs;
+pub(crate) mod load;
+pub(crate) mod options;
+pub(crate) mod parser;
+pub(crate) mod prices;
+pub(crate) mod str;
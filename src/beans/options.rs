@@ -0,0 +1,68 @@
+//! Beancount options
+//!
+//! see: https://beancount.github.io/docs/beancount_options_reference.html
+
+/// The subset of Beancount's options that Fava relies on
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct BeancountOptions {
+    pub title: String,
+    pub name_assets: String,
+    pub name_liabilities: String,
+    pub name_equity: String,
+    pub name_income: String,
+    pub name_expenses: String,
+    /// relative to the equity root
+    pub account_current_earnings: String,
+    /// relative to the equity root
+    pub account_current_conversions: String,
+    pub operating_currency: Vec<String>,
+    /// folders with documents, relative to the main file
+    pub documents: Vec<String>,
+}
+
+impl Default for BeancountOptions {
+    fn default() -> Self {
+        Self {
+            title: "Beancount".into(),
+            name_assets: "Assets".into(),
+            name_liabilities: "Liabilities".into(),
+            name_equity: "Equity".into(),
+            name_income: "Income".into(),
+            name_expenses: "Expenses".into(),
+            account_current_earnings: "Earnings:Current".into(),
+            account_current_conversions: "Conversions:Current".into(),
+            operating_currency: Vec::new(),
+            documents: Vec::new(),
+        }
+    }
+}
+
+impl BeancountOptions {
+    /// Set the option from an `option` directive, ignoring options Fava doesn't rely on
+    pub fn set(&mut self, name: &str, value: &str) {
+        let value = value.to_string();
+        match name {
+            "title" => self.title = value,
+            "name_assets" => self.name_assets = value,
+            "name_liabilities" => self.name_liabilities = value,
+            "name_equity" => self.name_equity = value,
+            "name_income" => self.name_income = value,
+            "name_expenses" => self.name_expenses = value,
+            "account_current_earnings" => self.account_current_earnings = value,
+            "account_current_conversions" => self.account_current_conversions = value,
+            "operating_currency" => self.operating_currency.push(value),
+            "documents" => self.documents.push(value),
+            _ => {}
+        }
+    }
+
+    /// Full name of the account that net profit is carried into
+    pub fn current_earnings(&self) -> String {
+        format!("{}:{}", self.name_equity, self.account_current_earnings)
+    }
+
+    /// Full name of the account that balances currency conversions
+    pub fn current_conversions(&self) -> String {
+        format!("{}:{}", self.name_equity, self.account_current_conversions)
+    }
+}
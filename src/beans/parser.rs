@@ -0,0 +1,886 @@
+//! Parser for Beancount files
+//!
+//! Parses a single file, line by line. Includes are resolved and entries are
+//! booked by the loader.
+//!
+//! see: https://beancount.github.io/docs/beancount_language_syntax.html
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::Helpers;
+use crate::beans::abc::*;
+use crate::beans::flags::Flags;
+
+/// Entries and directives of a single file
+#[derive(Debug, Default)]
+pub(crate) struct ParsedFile {
+    pub entries: Vec<Directive>,
+    pub errors: Vec<Helpers>,
+    /// `option` directives, in order
+    pub options: Vec<(String, String)>,
+    /// `include` paths, as written
+    pub includes: Vec<String>,
+    /// postings of transactions with costs left out, by the line of the transaction,
+    /// to be booked by the loader against the lots they reduce
+    pub unbooked: BTreeMap<usize, Vec<RawPosting>>,
+}
+
+/// A posting that is yet to be booked
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct RawPosting {
+    pub posting: Posting,
+    /// units are left out, to be interpolated
+    pub auto: bool,
+    /// a cost without its number or currency, to be filled in from the lot it reduces
+    pub cost_spec: Option<CostSpec>,
+}
+
+/// A cost as written, the components that are left out match any lot
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct CostSpec {
+    pub number: Option<f32>,
+    pub currency: Option<String>,
+    pub date: Option<time::Date>,
+    pub label: Option<String>,
+}
+
+impl CostSpec {
+    /// The cost, if its number and currency are written
+    fn complete(&self) -> Option<ACost> {
+        Some(ACost {
+            number: self.number?,
+            currency: self.currency.clone()?,
+            date: self.date,
+            label: self.label.clone(),
+        })
+    }
+
+    /// Whether a lot held at `cost` has the components that are written
+    pub fn matches(&self, cost: &ACost) -> bool {
+        self.number.is_none_or(|number| number == cost.number)
+            && self.currency.as_ref().is_none_or(|currency| *currency == cost.currency)
+            && self.date.is_none_or(|date| cost.date == Some(date))
+            && self.label.as_ref().is_none_or(|label| cost.label.as_ref() == Some(label))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token<'a> {
+    Str(String),
+    Word(&'a str),
+    Punct(&'static str),
+}
+
+const PUNCTUATION: [&str; 8] = ["{{", "}}", "@@", "{", "}", "@", ",", "~"];
+
+/// Split a line into tokens, dropping comments
+fn tokenize(line: &str) -> Result<Vec<Token<'_>>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = line.trim_start();
+    while let Some(c) = rest.chars().next() {
+        if c.is_whitespace() {
+            rest = rest.trim_start();
+        } else if c == ';' {
+            break;
+        } else if c == '"' {
+            let mut string = String::new();
+            let mut chars = rest[1..].char_indices();
+            let end = loop {
+                match chars.next() {
+                    Some((i, '"')) => break i + 2,
+                    Some((_, '\\')) => string.extend(chars.next().map(|(_, c)| c)),
+                    Some((_, c)) => string.push(c),
+                    None => return Err("Unterminated string".into()),
+                }
+            };
+            tokens.push(Token::Str(string));
+            rest = &rest[end..];
+        } else if let Some(punct) = PUNCTUATION.iter().find(|punct| rest.starts_with(**punct)) {
+            tokens.push(Token::Punct(punct));
+            rest = &rest[punct.len()..];
+        } else {
+            // commas separate currencies, but group the digits of numbers
+            let bytes = rest.as_bytes();
+            let end = rest
+                .char_indices()
+                .find(|&(i, c)| {
+                    let grouping = c == ',' && i > 0 && bytes[i - 1].is_ascii_digit() && bytes.get(i + 1).is_some_and(u8::is_ascii_digit);
+                    (c.is_whitespace() || "{}@,~;\"".contains(c)) && !grouping
+                })
+                .map_or(rest.len(), |(i, _)| i);
+            tokens.push(Token::Word(&rest[..end]));
+            rest = &rest[end..];
+        }
+    }
+    Ok(tokens)
+}
+
+pub(crate) fn parse_date(word: &str) -> Option<time::Date> {
+    let mut parts = word.split(['-', '/']);
+    let year = parts.next()?.parse().ok()?;
+    let month: u8 = parts.next()?.parse().ok()?;
+    let day = parts.next()?.parse().ok()?;
+    if parts.next().is_some() || word.len() != 10 {
+        return None;
+    }
+    time::Date::from_calendar_date(year, month.try_into().ok()?, day).ok()
+}
+
+pub(crate) fn is_account(word: &str) -> bool {
+    let mut components = word.split(':');
+    let root = components.next().unwrap_or_default();
+    word.contains(':')
+        && root.starts_with(|c: char| c.is_uppercase())
+        && word.split(':').all(|component| {
+            component.starts_with(|c: char| c.is_uppercase() || c.is_ascii_digit())
+                && component.chars().all(|c| c.is_alphanumeric() || c == '-')
+        })
+}
+
+pub(crate) fn is_currency(word: &str) -> bool {
+    word.starts_with(|c: char| c.is_ascii_uppercase())
+        && word.len() <= 24
+        && word.chars().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || "'._-".contains(c))
+}
+
+/// A tag or link, without its `#` or `^`
+pub(crate) fn is_tag_or_link(word: &str) -> bool {
+    !word.is_empty() && word.chars().all(|c| c.is_ascii_alphanumeric() || "-_/.".contains(c))
+}
+
+/// A metadata key, without its colon
+pub(crate) fn is_key(word: &str) -> bool {
+    word.starts_with(|c: char| c.is_ascii_lowercase()) && word.chars().all(|c| c.is_ascii_alphanumeric() || "-_".contains(c))
+}
+
+pub(crate) fn parse_number(word: &str) -> Option<f32> {
+    if !word.starts_with(|c: char| c.is_ascii_digit() || "+-.".contains(c)) || !word.contains(|c: char| c.is_ascii_digit()) {
+        return None;
+    }
+    word.replace(',', "").parse().ok()
+}
+
+/// A cursor over the tokens of a line
+struct Tokens<'t, 'a> {
+    tokens: &'t [Token<'a>],
+    position: usize,
+}
+
+impl<'t, 'a> Tokens<'t, 'a> {
+    fn new(tokens: &'t [Token<'a>]) -> Self {
+        Self { tokens, position: 0 }
+    }
+
+    fn peek(&self) -> Option<&'t Token<'a>> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<&'t Token<'a>> {
+        let token = self.peek();
+        self.position += 1;
+        token
+    }
+
+    fn peek_word(&self) -> Option<&'a str> {
+        match self.peek() {
+            Some(Token::Word(word)) => Some(word),
+            _ => None,
+        }
+    }
+
+    fn next_if_punct(&mut self, punct: &str) -> bool {
+        let found = matches!(self.peek(), Some(Token::Punct(p)) if *p == punct);
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+
+    fn word(&mut self, expected: &str) -> Result<&'a str, String> {
+        match self.next() {
+            Some(Token::Word(word)) => Ok(word),
+            _ => Err(format!("Expected {expected}")),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        match self.next() {
+            Some(Token::Str(string)) => Ok(string.clone()),
+            _ => Err("Expected a string".into()),
+        }
+    }
+
+    fn account(&mut self) -> Result<String, String> {
+        let word = self.word("an account")?;
+        if is_account(word) { Ok(word.to_string()) } else { Err(format!("Invalid account: {word}")) }
+    }
+
+    fn currency(&mut self) -> Result<String, String> {
+        let word = self.word("a currency")?;
+        if is_currency(word) { Ok(word.to_string()) } else { Err(format!("Invalid currency: {word}")) }
+    }
+
+    fn number(&mut self) -> Result<f32, String> {
+        let word = self.word("a number")?;
+        parse_number(word).ok_or_else(|| format!("Invalid number: {word}"))
+    }
+
+    fn amount(&mut self) -> Result<AAmount, String> {
+        Ok(AAmount(self.number()?, self.currency()?))
+    }
+
+    /// Tags and links up to the end of the line
+    fn tags_and_links(&mut self, tags: &mut BTreeSet<String>, links: &mut BTreeSet<String>) -> Result<(), String> {
+        while let Some(token) = self.next() {
+            match token {
+                Token::Word(word) if word.starts_with('#') && is_tag_or_link(&word[1..]) => tags.insert(word[1..].to_string()),
+                Token::Word(word) if word.starts_with('^') && is_tag_or_link(&word[1..]) => links.insert(word[1..].to_string()),
+                _ => return Err("Expected a tag or link".into()),
+            };
+        }
+        Ok(())
+    }
+
+    fn end(&self) -> Result<(), String> {
+        if self.position >= self.tokens.len() { Ok(()) } else { Err("Unexpected trailing tokens".into()) }
+    }
+
+    /// A metadata or `custom` value
+    fn value(&mut self) -> Result<MetaValue, String> {
+        match self.next() {
+            Some(Token::Str(string)) => Ok(MetaValue::String(string.clone())),
+            Some(Token::Word(word)) => {
+                let word = *word;
+                if let Some(date) = parse_date(word) {
+                    Ok(MetaValue::Date(date))
+                } else if let Some(number) = parse_number(word) {
+                    match self.peek_word().filter(|word| is_currency(word)) {
+                        Some(currency) => {
+                            self.position += 1;
+                            Ok(MetaValue::Amount(AAmount(number, currency.to_string())))
+                        }
+                        None => Ok(MetaValue::Number(number)),
+                    }
+                } else if word == "TRUE" || word == "FALSE" {
+                    Ok(MetaValue::Bool(word == "TRUE"))
+                } else if is_account(word) {
+                    Ok(MetaValue::Account(word.to_string()))
+                } else if is_currency(word) {
+                    Ok(MetaValue::Currency(word.to_string()))
+                } else if let Some(tag) = word.strip_prefix('#') {
+                    Ok(MetaValue::Tag(tag.to_string()))
+                } else {
+                    Err(format!("Invalid value: {word}"))
+                }
+            }
+            _ => Err("Expected a value".into()),
+        }
+    }
+}
+
+/// The entry being parsed, which indented lines add to
+struct Current {
+    entry: Directive,
+    postings: Vec<RawPosting>,
+}
+
+/// Parse a Beancount file
+pub(crate) fn parse_string(source: &str, filename: &str) -> ParsedFile {
+    let mut parsed = ParsedFile::default();
+    let mut current: Option<Current> = None;
+    let mut tags = BTreeSet::new();
+
+    for (index, line) in source.lines().enumerate() {
+        let lineno = index + 1;
+        let error = |message: String| Helpers::BeancountError(format!("{filename}:{lineno}: {message}"));
+        let tokens = match tokenize(line) {
+            Ok(tokens) => tokens,
+            Err(message) => {
+                parsed.errors.push(error(message));
+                continue;
+            }
+        };
+        if tokens.is_empty() {
+            continue;
+        }
+
+        if line.starts_with(char::is_whitespace) {
+            let Some(current) = current.as_mut() else {
+                parsed.errors.push(error("Unexpected indented line".into()));
+                continue;
+            };
+            if let Err(message) = parse_indented(current, &tokens, filename, lineno) {
+                parsed.errors.push(error(message));
+            }
+            continue;
+        }
+
+        finish(&mut parsed, current.take());
+        if line.starts_with('*') {
+            // org-mode heading
+            continue;
+        }
+        let mut cursor = Tokens::new(&tokens);
+        let result = match cursor.word("a directive") {
+            Ok("option") => cursor.string().and_then(|name| Ok((name, cursor.string()?))).and_then(|option| {
+                cursor.end()?;
+                parsed.options.push(option);
+                Ok(())
+            }),
+            Ok("include") => cursor.string().and_then(|path| {
+                cursor.end()?;
+                parsed.includes.push(path);
+                Ok(())
+            }),
+            Ok("plugin") => Ok(()),
+            Ok("pushtag") => cursor.word("a tag").map(|tag| {
+                tags.insert(tag.trim_start_matches('#').to_string());
+            }),
+            Ok("poptag") => cursor.word("a tag").and_then(|tag| {
+                if tags.remove(tag.trim_start_matches('#')) { Ok(()) } else { Err(format!("Attempting to pop absent tag: {tag}")) }
+            }),
+            Ok(word) => match parse_date(word) {
+                Some(date) => parse_directive(date, &mut cursor, Meta::new(filename, lineno), &tags).map(|entry| {
+                    current = Some(entry);
+                }),
+                None => Err(format!("Invalid syntax: {word}")),
+            },
+            Err(message) => Err(message),
+        };
+        if let Err(message) = result {
+            parsed.errors.push(error(message));
+        }
+    }
+    finish(&mut parsed, current.take());
+    parsed
+}
+
+/// Add the entry, booking the postings of transactions
+fn finish(parsed: &mut ParsedFile, current: Option<Current>) {
+    let Some(Current { mut entry, postings }) = current else {
+        return;
+    };
+    if let Directive::Transactions(transaction) = &mut entry {
+        if postings.iter().any(|raw| raw.cost_spec.is_some()) {
+            parsed.unbooked.insert(transaction.meta.lineno, postings);
+            parsed.entries.push(entry);
+            return;
+        }
+        match book(postings) {
+            Ok(postings) => transaction.postings = postings,
+            Err(message) => {
+                let meta = &transaction.meta;
+                parsed.errors.push(Helpers::BeancountError(format!("{}:{}: {message}", meta.filename, meta.lineno)));
+                transaction.postings = Vec::new();
+            }
+        }
+    }
+    parsed.entries.push(entry);
+}
+
+/// Interpolate the left out units and check that the postings balance
+///
+/// Costs that are left out have to be filled in first.
+pub(crate) fn book(postings: Vec<RawPosting>) -> Result<Vec<Posting>, String> {
+    if postings.iter().filter(|raw| raw.auto).count() > 1 {
+        return Err("Too many postings with left out amounts".into());
+    }
+    if postings.iter().any(|raw| raw.cost_spec.is_some()) {
+        return Err("Costs without a number and currency need the lots they reduce".into());
+    }
+
+    let mut residual: Vec<(String, f32)> = Vec::new();
+    for posting in postings.iter().filter(|raw| !raw.auto).map(|raw| &raw.posting) {
+        let (number, currency) = weight(posting);
+        match residual.iter_mut().find(|(c, _)| *c == currency) {
+            Some((_, sum)) => *sum += number,
+            None => residual.push((currency.to_string(), number)),
+        }
+    }
+    // f32 sums leave rounding noise, Beancount's default tolerance for two decimals
+    residual.retain(|(_, number)| number.abs() > 0.005);
+
+    let mut booked = Vec::new();
+    for raw in postings {
+        if !raw.auto {
+            booked.push(raw.posting);
+            continue;
+        }
+        for (currency, number) in residual.drain(..) {
+            let mut posting = raw.posting.clone();
+            posting.units = AAmount(-number, currency);
+            booked.push(posting);
+        }
+    }
+
+    match residual.first() {
+        Some((currency, number)) => Err(format!("Transaction does not balance: {number} {currency}")),
+        None => Ok(booked),
+    }
+}
+
+/// The amount a posting contributes to the balance of its transaction
+fn weight(posting: &Posting) -> (f32, &str) {
+    let units = &posting.units;
+    match (&posting.cost, &posting.price) {
+        (Some(cost), _) => (units.0 * cost.number, &cost.currency),
+        (None, Some(price)) => (units.0 * price.0, &price.1),
+        (None, None) => (units.0, &units.1),
+    }
+}
+
+fn parse_directive(date: time::Date, cursor: &mut Tokens, meta: Meta, tags: &BTreeSet<String>) -> Result<Current, String> {
+    let keyword = match cursor.next() {
+        Some(Token::Word(word)) => *word,
+        Some(Token::Str(_)) | None => return Err("Expected a directive".into()),
+        Some(Token::Punct(punct)) => return Err(format!("Unexpected {punct}")),
+    };
+
+    let entry = match keyword {
+        "open" => {
+            let account = cursor.account()?;
+            let mut currencies = Vec::new();
+            if cursor.peek_word().is_some() {
+                currencies.push(cursor.currency()?);
+                while cursor.next_if_punct(",") {
+                    currencies.push(cursor.currency()?);
+                }
+            }
+            let booking = match cursor.peek() {
+                Some(Token::Str(_)) => Some(cursor.string()?),
+                _ => None,
+            };
+            Directive::Open(Open {
+                date,
+                account,
+                currencies,
+                booking,
+                meta,
+            })
+        }
+        "close" => Directive::Close(Close {
+            date,
+            account: cursor.account()?,
+            meta,
+        }),
+        "commodity" => Directive::Commodity(Commodity {
+            date,
+            currency: cursor.currency()?,
+            meta,
+        }),
+        "balance" => {
+            let account = cursor.account()?;
+            let number = cursor.number()?;
+            let tolerance = if cursor.next_if_punct("~") { Some(cursor.number()?) } else { None };
+            Directive::Balance(Balance {
+                date,
+                account,
+                amount: AAmount(number, cursor.currency()?),
+                tolerance,
+                diff_amount: None,
+                meta,
+            })
+        }
+        "pad" => Directive::Pad(Pad {
+            date,
+            account: cursor.account()?,
+            source_account: cursor.account()?,
+            meta,
+        }),
+        "note" => Directive::Note(Note {
+            date,
+            account: cursor.account()?,
+            comment: cursor.string()?,
+            meta,
+        }),
+        "document" => {
+            let account = cursor.account()?;
+            let filename = cursor.string()?;
+            let mut document = Document {
+                date,
+                account,
+                filename,
+                tags: tags.clone(),
+                links: BTreeSet::new(),
+                meta,
+            };
+            cursor.tags_and_links(&mut document.tags, &mut document.links)?;
+            Directive::Document(document)
+        }
+        "event" => Directive::Event(Event {
+            date,
+            r#type: cursor.string()?,
+            description: cursor.string()?,
+            meta,
+        }),
+        "price" => Directive::Price(Price {
+            date,
+            currency: cursor.currency()?,
+            amount: cursor.amount()?,
+            meta,
+        }),
+        "query" => Directive::Query(Query {
+            date,
+            name: cursor.string()?,
+            query_string: cursor.string()?,
+            meta,
+        }),
+        "custom" => {
+            let r#type = cursor.string()?;
+            let mut values = Vec::new();
+            while cursor.peek().is_some() {
+                values.push(cursor.value()?);
+            }
+            Directive::Custom(Custom { date, r#type, values, meta })
+        }
+        flag => {
+            let flag = if flag == "txn" { Flags::Okay } else { flag.parse().map_err(|_| format!("Unknown directive: {flag}"))? };
+            let mut strings = Vec::new();
+            while let Some(Token::Str(_)) = cursor.peek() {
+                strings.push(cursor.string()?);
+            }
+            let (payee, narration) = match strings.len() {
+                0 => (None, String::new()),
+                1 => (None, strings.remove(0)),
+                2 => {
+                    let narration = strings.pop().unwrap_or_default();
+                    (strings.pop(), narration)
+                }
+                _ => return Err("Too many strings in transaction header".into()),
+            };
+            let mut transaction = Transaction {
+                date,
+                flag,
+                payee,
+                narration,
+                tags: tags.clone(),
+                links: BTreeSet::new(),
+                postings: Vec::new(),
+                meta,
+            };
+            cursor.tags_and_links(&mut transaction.tags, &mut transaction.links)?;
+            Directive::Transactions(transaction)
+        }
+    };
+    cursor.end()?;
+    Ok(Current {
+        entry,
+        postings: Vec::new(),
+    })
+}
+
+/// A metadata line or a posting of the current entry
+fn parse_indented(current: &mut Current, tokens: &[Token], filename: &str, lineno: usize) -> Result<(), String> {
+    let mut cursor = Tokens::new(tokens);
+
+    if let Some(key) = cursor.peek_word().and_then(|word| word.strip_suffix(':'))
+        && is_key(key)
+    {
+        cursor.next();
+        if cursor.peek().is_none() {
+            return Ok(());
+        }
+        let value = cursor.value()?;
+        cursor.end()?;
+        let meta = match current.postings.last_mut() {
+            Some(raw) => &mut raw.posting.meta,
+            None => entry_meta_mut(&mut current.entry),
+        };
+        meta.values.insert(key.to_string(), value);
+        return Ok(());
+    }
+
+    if !matches!(current.entry, Directive::Transactions(_)) {
+        return Err("Postings are only allowed in transactions".into());
+    }
+
+    let mut flag = None;
+    if let Some(word) = cursor.peek_word()
+        && word.len() == 1
+    {
+        flag = Some(word.parse::<Flags>().map_err(|_| format!("Invalid posting flag: {word}"))?);
+        cursor.next();
+    }
+    let account = cursor.account()?;
+    let mut posting = Posting::new(account, AAmount(0., String::new()));
+    posting.flag = flag;
+    posting.meta = Meta::new(filename, lineno);
+
+    if cursor.peek().is_none() {
+        current.postings.push(RawPosting {
+            posting,
+            auto: true,
+            cost_spec: None,
+        });
+        return Ok(());
+    }
+
+    let cost_spec = posting_amount(&mut cursor, &mut posting)?;
+    current.postings.push(RawPosting {
+        posting,
+        auto: false,
+        cost_spec,
+    });
+    Ok(())
+}
+
+/// The units, cost and price of a posting, up to the end of the line
+///
+/// Returns the cost if its number or currency is left out, the posting only
+/// gets complete costs.
+fn posting_amount(cursor: &mut Tokens, posting: &mut Posting) -> Result<Option<CostSpec>, String> {
+    posting.units = cursor.amount()?;
+    let units = posting.units.0;
+
+    let mut cost_spec = None;
+    let total_cost = cursor.next_if_punct("{{");
+    if total_cost || cursor.next_if_punct("{") {
+        let mut spec = parse_cost(cursor, if total_cost { "}}" } else { "}" })?;
+        if total_cost && units != 0. {
+            spec.number = spec.number.map(|number| number / units.abs());
+        }
+        match spec.complete() {
+            Some(cost) => posting.cost = Some(cost),
+            None => cost_spec = Some(spec),
+        }
+    }
+
+    let total_price = cursor.next_if_punct("@@");
+    if total_price || cursor.next_if_punct("@") {
+        let mut price = cursor.amount()?;
+        if total_price && units != 0. {
+            price.0 /= units.abs();
+        }
+        posting.price = Some(price);
+    }
+    cursor.end()?;
+    Ok(cost_spec)
+}
+
+/// A posting to `account` with the amount written as in a file, like `10 STOCK {100 USD} @ 110 USD`
+///
+/// An empty amount is left out, to be interpolated.
+pub(crate) fn parse_posting(account: String, amount: &str) -> Result<RawPosting, String> {
+    let mut posting = Posting::new(account, AAmount(0., String::new()));
+    let tokens = tokenize(amount)?;
+    if tokens.is_empty() {
+        return Ok(RawPosting {
+            posting,
+            auto: true,
+            cost_spec: None,
+        });
+    }
+    let cost_spec = posting_amount(&mut Tokens::new(&tokens), &mut posting)?;
+    Ok(RawPosting {
+        posting,
+        auto: false,
+        cost_spec,
+    })
+}
+
+/// The components of a cost specification up to `close`
+fn parse_cost(cursor: &mut Tokens, close: &str) -> Result<CostSpec, String> {
+    let mut spec = CostSpec::default();
+    while !cursor.next_if_punct(close) {
+        match cursor.peek() {
+            Some(Token::Str(_)) => spec.label = Some(cursor.string()?),
+            Some(Token::Word(word)) if parse_date(word).is_some() => {
+                spec.date = parse_date(word);
+                cursor.next();
+            }
+            Some(Token::Word(word)) if is_currency(word) => spec.currency = Some(cursor.currency()?),
+            Some(Token::Word(_)) => {
+                spec.number = Some(cursor.number()?);
+                if cursor.peek_word().is_some_and(is_currency) {
+                    spec.currency = Some(cursor.currency()?);
+                }
+            }
+            Some(Token::Punct(",")) => {
+                cursor.next();
+            }
+            _ => return Err("Invalid cost specification".into()),
+        }
+    }
+    Ok(spec)
+}
+
+fn entry_meta_mut(entry: &mut Directive) -> &mut Meta {
+    match entry {
+        Directive::Open(e) => &mut e.meta,
+        Directive::Close(e) => &mut e.meta,
+        Directive::Commodity(e) => &mut e.meta,
+        Directive::Transactions(e) => &mut e.meta,
+        Directive::Note(e) => &mut e.meta,
+        Directive::Balance(e) => &mut e.meta,
+        Directive::Pad(e) => &mut e.meta,
+        Directive::Document(e) => &mut e.meta,
+        Directive::Event(e) => &mut e.meta,
+        Directive::Price(e) => &mut e.meta,
+        Directive::Query(e) => &mut e.meta,
+        Directive::Custom(e) => &mut e.meta,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::macros::date;
+
+    const SOURCE: &str = r#"
+option "title" "Example"
+include "prices.beancount"
+* Accounts
+
+2020-01-01 open Assets:Cash USD,EUR "STRICT"
+  fava-uptodate-indication: TRUE
+2020-01-01 open Expenses:Food
+2020-01-01 commodity USD
+
+pushtag #trip
+2020-01-05 * "Shop" "Groceries" #food ^receipt-1 ; a comment
+  Expenses:Food        20.00 USD
+    category: "groceries"
+  Assets:Cash
+poptag #trip
+
+2020-01-06 txn "Only narration"
+  Assets:Broker    2 STOCK {100 USD, 2020-01-02, "lot"} @ 110 USD
+  Assets:Cash   -200 USD
+
+2020-01-07 balance Assets:Cash  -220 ~ 0.01 USD
+2020-01-08 custom "budget" Expenses:Food "monthly" 100.00 USD
+"#;
+
+    fn parsed() -> ParsedFile {
+        parse_string(SOURCE, "main.beancount")
+    }
+
+    #[test]
+    fn tokens() {
+        assert_eq!(
+            tokenize(r#"  Assets:Cash -1,000.00 USD {{5 EUR}} ; comment "#).unwrap(),
+            vec![
+                Token::Word("Assets:Cash"),
+                Token::Word("-1,000.00"),
+                Token::Word("USD"),
+                Token::Punct("{{"),
+                Token::Word("5"),
+                Token::Word("EUR"),
+                Token::Punct("}}"),
+            ]
+        );
+        assert_eq!(tokenize(r#""a \"quoted\" word";"#).unwrap(), vec![Token::Str("a \"quoted\" word".into())]);
+        assert!(tokenize(r#""unterminated"#).is_err());
+    }
+
+    #[test]
+    fn words() {
+        assert_eq!(parse_date("2020-02-29"), Some(date!(2020-02-29)));
+        assert_eq!(parse_date("2021-02-29"), None);
+        assert!(is_account("Assets:US:Bank-1"));
+        assert!(!is_account("Assets"));
+        assert!(!is_account("assets:cash"));
+        assert!(is_currency("VBMPX"));
+        assert!(!is_currency("Usd"));
+        assert_eq!(parse_number("-1,000.50"), Some(-1000.5));
+        assert_eq!(parse_number("USD"), None);
+    }
+
+    #[test]
+    fn directives() {
+        let parsed = parsed();
+        assert_eq!(parsed.errors, vec![]);
+        assert_eq!(parsed.options, vec![("title".into(), "Example".into())]);
+        assert_eq!(parsed.includes, vec!["prices.beancount"]);
+        assert_eq!(parsed.entries.len(), 7);
+
+        let Directive::Open(open) = &parsed.entries[0] else { panic!("expected an open entry") };
+        assert_eq!(open.currencies, vec!["USD", "EUR"]);
+        assert_eq!(open.booking.as_deref(), Some("STRICT"));
+        assert_eq!(open.meta.lineno, 6);
+        assert_eq!(open.meta.get("fava-uptodate-indication"), Some(&MetaValue::Bool(true)));
+
+        let Directive::Balance(balance) = &parsed.entries[5] else { panic!("expected a balance entry") };
+        assert_eq!(balance.amount, AAmount(-220., "USD".into()));
+        assert_eq!(balance.tolerance, Some(0.01));
+
+        let Directive::Custom(custom) = &parsed.entries[6] else { panic!("expected a custom entry") };
+        assert_eq!(custom.values[0], MetaValue::Account("Expenses:Food".into()));
+        assert_eq!(custom.values[2], MetaValue::Amount(AAmount(100., "USD".into())));
+    }
+
+    #[test]
+    fn transactions() {
+        let parsed = parsed();
+        let Directive::Transactions(groceries) = &parsed.entries[3] else { panic!("expected a transaction") };
+        assert_eq!(groceries.payee.as_deref(), Some("Shop"));
+        assert_eq!(groceries.narration, "Groceries");
+        assert_eq!(groceries.tags, BTreeSet::from(["food".into(), "trip".into()]));
+        assert_eq!(groceries.links, BTreeSet::from(["receipt-1".into()]));
+        assert_eq!(groceries.postings[0].meta.get("category"), Some(&MetaValue::String("groceries".into())));
+        assert_eq!(groceries.postings[1].units, AAmount(-20., "USD".into()));
+        assert_eq!(groceries.postings[1].meta.lineno, 15);
+
+        let Directive::Transactions(buy) = &parsed.entries[4] else { panic!("expected a transaction") };
+        assert_eq!(buy.payee, None);
+        assert!(buy.tags.is_empty());
+        let cost = buy.postings[0].cost.as_ref().unwrap();
+        assert_eq!((cost.number, cost.date, cost.label.as_deref()), (100., Some(date!(2020-01-02)), Some("lot")));
+        assert_eq!(buy.postings[0].price, Some(AAmount(110., "USD".into())));
+    }
+
+    #[test]
+    fn errors() {
+        let parsed = parse_string(
+            "2020-01-01 * \"Unbalanced\"\n  Assets:Cash 10 USD\n  Expenses:Food 5 USD\n  oops\n2020-01-02 frobnicate\n",
+            "main.beancount",
+        );
+        assert_eq!(
+            parsed.errors,
+            vec![
+                Helpers::BeancountError("main.beancount:4: Invalid account: oops".into()),
+                Helpers::BeancountError("main.beancount:1: Transaction does not balance: 15 USD".into()),
+                Helpers::BeancountError("main.beancount:5: Unknown directive: frobnicate".into()),
+            ]
+        );
+        assert_eq!(parsed.entries.len(), 1);
+    }
+
+    #[test]
+    fn total_cost_and_price() {
+        let parsed = parse_string(
+            "2020-01-01 *\n  Assets:Broker 4 STOCK {{400 USD}}\n  Assets:Cash -400 USD\n2020-01-02 *\n  Assets:Cash -50 EUR @@ 60 USD\n  Assets:Cash\n",
+            "main.beancount",
+        );
+        assert_eq!(parsed.errors, vec![]);
+        let Directive::Transactions(buy) = &parsed.entries[0] else { panic!("expected a transaction") };
+        let cost = buy.postings[0].cost.as_ref().unwrap();
+        assert_eq!((cost.number, cost.date), (100., None));
+        let Directive::Transactions(exchange) = &parsed.entries[1] else { panic!("expected a transaction") };
+        assert_eq!(exchange.postings[0].price, Some(AAmount(1.2, "USD".into())));
+        assert!((exchange.postings[1].units.0 - 60.).abs() < 1e-3);
+    }
+
+    #[test]
+    fn cost_specs() {
+        let parsed = parse_string("2020-01-02 *\n  Assets:Broker -5 STOCK {2020-01-01}\n  Assets:Cash 500 USD\n", "main.beancount");
+        assert_eq!(parsed.errors, vec![]);
+        let Directive::Transactions(sale) = &parsed.entries[0] else { panic!("expected a transaction") };
+        // booked by the loader, once the lots are known
+        assert!(sale.postings.is_empty());
+        let spec = parsed.unbooked[&1][0].cost_spec.clone().unwrap();
+        assert_eq!(spec, CostSpec { date: Some(date!(2020-01-01)), ..CostSpec::default() });
+
+        let lot = ACost {
+            number: 100.,
+            currency: "USD".into(),
+            date: Some(date!(2020-01-01)),
+            label: Some("lot".into()),
+        };
+        assert!(spec.matches(&lot));
+        assert!(CostSpec::default().matches(&lot));
+        assert!(!CostSpec { number: Some(90.), ..CostSpec::default() }.matches(&lot));
+        assert!(!CostSpec { label: Some("other".into()), ..CostSpec::default() }.matches(&lot));
+        assert!(book(parsed.unbooked[&1].clone()).is_err());
+    }
+}
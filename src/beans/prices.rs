@@ -0,0 +1,190 @@
+//! Price database
+//!
+//! see: https://github.com/beancount/fava/blob/main/src/fava/beans/prices.py
+
+use std::collections::{BTreeSet, HashMap};
+
+use crate::beans::abc::Directive;
+
+/// A base and a quote currency
+pub(crate) type BaseQuote = (String, String);
+
+/// Dated prices for all currency pairs, including the inverse of every price directive
+#[derive(Debug, Default)]
+pub(crate) struct PriceMap {
+    prices: HashMap<BaseQuote, Vec<(time::Date, f32)>>,
+    /// the pairs as they were given, without the inverses
+    forward_pairs: BTreeSet<BaseQuote>,
+}
+
+impl PriceMap {
+    pub fn new(entries: &[Directive]) -> Self {
+        Self::from_entries(entries, false)
+    }
+
+    /// The prices of the price directives and the prices implied by postings
+    ///
+    /// A posting implies the price of its units in its price annotation, or
+    /// else in its cost, like the `implicit_prices` plugin of Beancount.
+    pub fn with_implied(entries: &[Directive]) -> Self {
+        Self::from_entries(entries, true)
+    }
+
+    fn from_entries(entries: &[Directive], implied: bool) -> Self {
+        let mut map = Self::default();
+        for entry in entries {
+            match entry {
+                Directive::Price(price) => map.insert(price.date, &price.currency, &price.amount.1, price.amount.0),
+                Directive::Transactions(transaction) if implied => {
+                    for posting in &transaction.postings {
+                        let price = match (&posting.price, &posting.cost) {
+                            (Some(price), _) => Some((price.0, &price.1)),
+                            (None, Some(cost)) => Some((cost.number, &cost.currency)),
+                            (None, None) => None,
+                        };
+                        if let Some((number, quote)) = price {
+                            map.insert(transaction.date, &posting.units.1, quote, number);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        map.sort();
+        map
+    }
+
+    /// Add a price and its inverse, call `sort` once done
+    fn insert(&mut self, date: time::Date, base: &str, quote: &str, number: f32) {
+        if number == 0. || base == quote {
+            return;
+        }
+        self.forward_pairs.insert((base.to_string(), quote.to_string()));
+        self.prices.entry((base.to_string(), quote.to_string())).or_default().push((date, number));
+        self.prices.entry((quote.to_string(), base.to_string())).or_default().push((date, 1. / number));
+    }
+
+    /// Sort prices by date, keeping only the last price on each day
+    fn sort(&mut self) {
+        for prices in self.prices.values_mut() {
+            // stable sort, so the last price directive of a day wins
+            prices.sort_by_key(|(date, _)| *date);
+            prices.reverse();
+            prices.dedup_by_key(|(date, _)| *date);
+            prices.reverse();
+        }
+    }
+
+    /// All currency pairs, sorted
+    #[allow(dead_code)]
+    pub fn pairs(&self) -> Vec<&BaseQuote> {
+        let mut pairs: Vec<&BaseQuote> = self.prices.keys().collect();
+        pairs.sort();
+        pairs
+    }
+
+    /// The currency pairs as they were given, without the inverses, sorted
+    ///
+    /// A pair that was also given inverted is listed both ways.
+    pub fn forward_pairs(&self) -> impl Iterator<Item = &BaseQuote> {
+        self.forward_pairs.iter()
+    }
+
+    /// All prices of the pair, sorted by date
+    pub fn get_all_prices(&self, base: &str, quote: &str) -> &[(time::Date, f32)] {
+        self.prices.get(&(base.to_string(), quote.to_string())).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    /// The latest price and its date on or before `date`, or the latest price overall
+    pub fn get_price_point(&self, base: &str, quote: &str, date: Option<time::Date>) -> Option<(time::Date, f32)> {
+        let prices = self.get_all_prices(base, quote);
+        match date {
+            Some(date) => prices[..prices.partition_point(|(price_date, _)| *price_date <= date)].last().copied(),
+            None => prices.last().copied(),
+        }
+    }
+
+    /// The price of one unit of `base` in `quote` on `date`, 1 for identical currencies
+    pub fn get_price(&self, base: &str, quote: &str, date: Option<time::Date>) -> Option<f32> {
+        if base == quote {
+            return Some(1.);
+        }
+        self.get_price_point(base, quote, date).map(|(_, number)| number)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::beans::abc::{AAmount, ACost, Meta, Posting, Price, Transaction};
+    use crate::beans::flags::Flags;
+    use time::macros::date;
+
+    fn price(date: time::Date, currency: &str, number: f32, quote: &str) -> Directive {
+        Directive::Price(Price {
+            date,
+            currency: currency.into(),
+            amount: AAmount(number, quote.into()),
+            meta: Meta::default(),
+        })
+    }
+
+    fn prices() -> PriceMap {
+        PriceMap::new(&[
+            price(date!(2020-03-01), "STOCK", 12., "USD"),
+            price(date!(2020-01-01), "STOCK", 10., "USD"),
+            price(date!(2020-01-01), "STOCK", 11., "USD"),
+            price(date!(2020-02-01), "EUR", 1.25, "USD"),
+        ])
+    }
+
+    #[test]
+    fn latest_price() {
+        let prices = prices();
+        assert_eq!(prices.get_price("STOCK", "USD", None), Some(12.));
+        assert_eq!(prices.get_price("STOCK", "USD", Some(date!(2020-02-15))), Some(11.));
+        assert_eq!(prices.get_price("STOCK", "USD", Some(date!(2019-12-31))), None);
+        assert_eq!(prices.get_price("USD", "USD", None), Some(1.));
+        assert_eq!(prices.get_price("STOCK", "EUR", None), None);
+    }
+
+    #[test]
+    fn inverse_prices() {
+        let prices = prices();
+        assert_eq!(prices.get_price("USD", "EUR", None), Some(0.8));
+        assert_eq!(prices.get_price_point("USD", "STOCK", Some(date!(2020-01-01))), Some((date!(2020-01-01), 1. / 11.)));
+    }
+
+    #[test]
+    fn all_prices() {
+        let prices = prices();
+        assert_eq!(prices.get_all_prices("STOCK", "USD"), &[(date!(2020-01-01), 11.), (date!(2020-03-01), 12.)]);
+        assert_eq!(prices.pairs().len(), 4);
+        assert!(prices.get_all_prices("BTC", "USD").is_empty());
+        let forward: Vec<_> = prices.forward_pairs().cloned().collect();
+        assert_eq!(forward, vec![("EUR".into(), "USD".into()), ("STOCK".into(), "USD".into())]);
+    }
+
+    #[test]
+    fn implied_prices() {
+        let mut buy = Posting::new("Assets:Broker", AAmount(2., "STOCK".into()));
+        buy.cost = Some(ACost {
+            number: 10.5,
+            currency: "USD".into(),
+            date: Some(date!(2020-01-15)),
+            label: None,
+        });
+        let mut exchange = Posting::new("Assets:Cash", AAmount(100., "EUR".into()));
+        exchange.price = Some(AAmount(1.2, "USD".into()));
+        let entries = [
+            price(date!(2020-01-01), "STOCK", 10., "USD"),
+            Directive::Transactions(Transaction::new(date!(2020-01-15), Flags::Okay, "Buy", vec![buy, exchange])),
+        ];
+
+        assert_eq!(PriceMap::new(&entries).get_all_prices("STOCK", "USD"), &[(date!(2020-01-01), 10.)]);
+        let prices = PriceMap::with_implied(&entries);
+        assert_eq!(prices.get_all_prices("STOCK", "USD"), &[(date!(2020-01-01), 10.), (date!(2020-01-15), 10.5)]);
+        assert_eq!(prices.get_price("EUR", "USD", None), Some(1.2));
+        assert_eq!(prices.forward_pairs().count(), 2);
+    }
+}
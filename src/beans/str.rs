@@ -0,0 +1,306 @@
+//! Conversion of entries to Beancount source text, and alignment of amounts
+//!
+//! see: https://github.com/beancount/fava/blob/main/src/fava/beans/str.py
+//! see: https://github.com/beancount/beancount/blob/v2/beancount/scripts/format.py
+
+use std::sync::LazyLock;
+
+use regex::Regex;
+
+use crate::beans::abc::{AAmount, ACost, Directive, Meta, MetaValue, Posting};
+
+/// A string literal, escaped
+///
+/// Literals are read a line at a time, so `string` must not contain line breaks.
+fn quote(string: &str) -> String {
+    format!("\"{}\"", string.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+pub(crate) fn amount_to_string(amount: &AAmount) -> String {
+    format!("{} {}", amount.0, amount.1)
+}
+
+fn cost_to_string(cost: &ACost) -> String {
+    let mut components = vec![format!("{} {}", cost.number, cost.currency)];
+    components.extend(cost.date.map(|date| date.to_string()));
+    components.extend(cost.label.as_deref().map(quote));
+    format!("{{{}}}", components.join(", "))
+}
+
+fn meta_value_to_string(value: &MetaValue) -> String {
+    match value {
+        MetaValue::String(string) => quote(string),
+        MetaValue::Account(name) | MetaValue::Currency(name) => name.clone(),
+        MetaValue::Tag(tag) => format!("#{tag}"),
+        MetaValue::Date(date) => date.to_string(),
+        MetaValue::Number(number) => number.to_string(),
+        MetaValue::Amount(amount) => amount_to_string(amount),
+        MetaValue::Bool(true) => "TRUE".into(),
+        MetaValue::Bool(false) => "FALSE".into(),
+    }
+}
+
+/// A metadata line, indented by `indent`
+pub(crate) fn meta_line(key: &str, value: &MetaValue, indent: &str) -> String {
+    format!("{indent}{key}: {}\n", meta_value_to_string(value))
+}
+
+/// The metadata lines, indented by `indent`
+fn meta_to_string(meta: &Meta, indent: &str) -> String {
+    meta.values.iter().map(|(key, value)| meta_line(key, value, indent)).collect()
+}
+
+fn tags_and_links<'a>(tags: impl IntoIterator<Item = &'a String>, links: impl IntoIterator<Item = &'a String>) -> String {
+    let tags = tags.into_iter().map(|tag| format!(" #{tag}"));
+    let links = links.into_iter().map(|link| format!(" ^{link}"));
+    tags.chain(links).collect()
+}
+
+/// The units of a posting with its cost and price, like `10 STOCK {100 USD} @ 110 USD`
+///
+/// Empty if the units are left out.
+pub(crate) fn posting_amount_to_string(posting: &Posting) -> String {
+    if posting.units.1.is_empty() {
+        return String::new();
+    }
+    let mut amount = amount_to_string(&posting.units);
+    if let Some(cost) = &posting.cost {
+        amount.push_str(&format!(" {}", cost_to_string(cost)));
+    }
+    if let Some(price) = &posting.price {
+        amount.push_str(&format!(" @ {}", amount_to_string(price)));
+    }
+    amount
+}
+
+/// A posting line and its metadata, without the amount if it is left out
+pub(crate) fn posting_to_string(posting: &Posting) -> String {
+    let mut line = String::from("  ");
+    if let Some(flag) = posting.flag {
+        line.push_str(&format!("{} ", u8::from(flag) as char));
+    }
+    line.push_str(&posting.account);
+    if !posting.units.1.is_empty() {
+        line.push_str(&format!("  {}", posting_amount_to_string(posting)));
+    }
+    line.push('\n');
+    line + &meta_to_string(&posting.meta, "    ")
+}
+
+/// The source text of an entry, with its metadata and postings
+///
+/// Amounts are not aligned, see [`align`].
+pub(crate) fn to_string(entry: &Directive) -> String {
+    let (date, meta, header) = match entry {
+        Directive::Open(open) => {
+            let mut header = format!("open {}", open.account);
+            if !open.currencies.is_empty() {
+                header.push_str(&format!(" {}", open.currencies.join(",")));
+            }
+            if let Some(booking) = &open.booking {
+                header.push_str(&format!(" {}", quote(booking)));
+            }
+            (open.date, &open.meta, header)
+        }
+        Directive::Close(close) => (close.date, &close.meta, format!("close {}", close.account)),
+        Directive::Commodity(commodity) => (commodity.date, &commodity.meta, format!("commodity {}", commodity.currency)),
+        Directive::Transactions(transaction) => {
+            let mut header = (u8::from(transaction.flag) as char).to_string();
+            if let Some(payee) = &transaction.payee {
+                header.push_str(&format!(" {}", quote(payee)));
+            }
+            header.push_str(&format!(" {}", quote(&transaction.narration)));
+            header.push_str(&tags_and_links(&transaction.tags, &transaction.links));
+            let postings: String = transaction.postings.iter().map(posting_to_string).collect();
+            let text = format!("{} {header}\n{}{postings}", transaction.date, meta_to_string(&transaction.meta, "  "));
+            return text;
+        }
+        Directive::Note(note) => (note.date, &note.meta, format!("note {} {}", note.account, quote(&note.comment))),
+        Directive::Balance(balance) => {
+            let amount = match balance.tolerance {
+                Some(tolerance) => format!("{} ~ {tolerance} {}", balance.amount.0, balance.amount.1),
+                None => amount_to_string(&balance.amount),
+            };
+            (balance.date, &balance.meta, format!("balance {}  {amount}", balance.account))
+        }
+        Directive::Pad(pad) => (pad.date, &pad.meta, format!("pad {} {}", pad.account, pad.source_account)),
+        Directive::Document(document) => (
+            document.date,
+            &document.meta,
+            format!(
+                "document {} {}{}",
+                document.account,
+                quote(&document.filename),
+                tags_and_links(&document.tags, &document.links)
+            ),
+        ),
+        Directive::Event(event) => (event.date, &event.meta, format!("event {} {}", quote(&event.r#type), quote(&event.description))),
+        Directive::Price(price) => (price.date, &price.meta, format!("price {}  {}", price.currency, amount_to_string(&price.amount))),
+        Directive::Query(query) => (query.date, &query.meta, format!("query {} {}", quote(&query.name), quote(&query.query_string))),
+        Directive::Custom(custom) => {
+            let values: String = custom.values.iter().map(|value| format!(" {}", meta_value_to_string(value))).collect();
+            (custom.date, &custom.meta, format!("custom {}{values}", quote(&custom.r#type)))
+        }
+    };
+    format!("{date} {header}\n{}", meta_to_string(meta, "  "))
+}
+
+/// A line with an amount: the text before it, the number and the currency with the rest of the line
+static AMOUNT_LINE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"^([^";]*?\S)\s+([-+]?\d[\d,]*)(\.\d*)?\s+([A-Z][A-Z0-9'._-]*(?:\s.*)?)$"#).expect("the amount regex is valid")
+});
+
+/// Column widths that align the numbers of amounts on their decimal point
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub(crate) struct Alignment {
+    prefix: usize,
+    integer: usize,
+    fraction: usize,
+}
+
+impl Alignment {
+    /// The widths that align all amounts in `text`
+    pub fn of(text: &str) -> Self {
+        text.split('\n')
+            .filter_map(|line| AMOUNT_LINE.captures(line.trim_end_matches('\r')))
+            .fold(Self::default(), |alignment, captures| {
+                let width = |index| captures.get(index).map_or(0, |capture| capture.as_str().chars().count());
+                alignment.max(Self {
+                    prefix: width(1),
+                    integer: width(2),
+                    fraction: width(3),
+                })
+            })
+    }
+
+    pub fn max(self, other: Self) -> Self {
+        Self {
+            prefix: self.prefix.max(other.prefix),
+            integer: self.integer.max(other.integer),
+            fraction: self.fraction.max(other.fraction),
+        }
+    }
+
+    /// Realign the amounts in `text`, keeping all other lines as they are
+    pub fn apply(&self, text: &str) -> String {
+        let lines: Vec<String> = text
+            .split('\n')
+            .map(|line| {
+                let (line, cr) = match line.strip_suffix('\r') {
+                    Some(line) => (line, "\r"),
+                    None => (line, ""),
+                };
+                match AMOUNT_LINE.captures(line) {
+                    Some(captures) => {
+                        let Self { prefix, integer, fraction } = *self;
+                        format!(
+                            "{:<prefix$}  {:>integer$}{:<fraction$} {}{cr}",
+                            &captures[1],
+                            &captures[2],
+                            captures.get(3).map_or("", |capture| capture.as_str()),
+                            &captures[4]
+                        )
+                    }
+                    None => format!("{line}{cr}"),
+                }
+            })
+            .collect();
+        lines.join("\n")
+    }
+}
+
+/// Align the amounts of all lines in `text` on their decimal point, like `bean-format`
+///
+/// Comments and lines without amounts are kept as they are.
+pub(crate) fn align(text: &str) -> String {
+    Alignment::of(text).apply(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::beans::abc::{Balance, Note, Transaction};
+    use crate::beans::flags::Flags;
+    use crate::beans::parser::parse_string;
+    use time::macros::date;
+
+    #[test]
+    fn transaction() {
+        let mut food = Posting::new("Expenses:Food", AAmount(20.5, "USD".into()));
+        food.meta.values.insert("category".into(), MetaValue::String("dining \"out\"".into()));
+        let mut transaction = Transaction::new(
+            date!(2020-01-02),
+            Flags::Okay,
+            "Dinner",
+            vec![food, Posting::new("Assets:Cash", AAmount(0., String::new()))],
+        );
+        transaction.payee = Some("Restaurant".into());
+        transaction.tags.insert("trip".into());
+        let entry = Directive::Transactions(transaction);
+
+        let text = to_string(&entry);
+        assert_eq!(
+            text,
+            "2020-01-02 * \"Restaurant\" \"Dinner\" #trip\n  Expenses:Food  20.5 USD\n    category: \"dining \\\"out\\\"\"\n  Assets:Cash\n"
+        );
+
+        // the text parses back to the same entry
+        let parsed = parse_string(&text, "main.beancount");
+        assert_eq!(parsed.errors, vec![]);
+        let Directive::Transactions(parsed) = &parsed.entries[0] else { panic!("expected a transaction") };
+        assert_eq!(parsed.postings[0].meta.get("category"), Some(&MetaValue::String("dining \"out\"".into())));
+        assert_eq!(parsed.postings[1].units, AAmount(-20.5, "USD".into()));
+    }
+
+    #[test]
+    fn other_entries() {
+        let balance = Balance::new(date!(2020-01-02), "Assets:Cash", AAmount(100., "USD".into()));
+        assert_eq!(to_string(&Directive::Balance(balance)), "2020-01-02 balance Assets:Cash  100 USD\n");
+
+        let note = Note {
+            date: date!(2020-01-02),
+            account: "Assets:Cash".into(),
+            comment: "Counted".into(),
+            meta: Meta::default(),
+        };
+        assert_eq!(to_string(&Directive::Note(note)), "2020-01-02 note Assets:Cash \"Counted\"\n");
+    }
+
+    #[test]
+    fn round_trip() {
+        let source = r#"2020-01-01 open Assets:Broker USD,STOCK "FIFO"
+  fava-uptodate-indication: TRUE
+2020-01-01 commodity STOCK
+  name: "A stock"
+2020-01-02 pad Assets:Broker Equity:Opening-Balances
+2020-01-03 balance Assets:Broker  100 ~ 0.01 USD
+2020-01-04 ! "Broker" "Buy" #trip ^order-1
+  Assets:Broker  2 STOCK {50 USD, 2020-01-04, "lot"}
+  ! Assets:Broker  -100 USD
+2020-01-05 document Assets:Broker "statements/2020-01.pdf" #statement
+2020-01-06 event "location" "Berlin"
+2020-01-07 price STOCK  55.5 USD
+2020-01-08 query "cash" "SELECT account"
+2020-01-09 custom "budget" Expenses:Food "monthly" 100 USD TRUE 2020-01-01
+2020-01-10 close Assets:Broker
+"#;
+        let parsed = parse_string(source, "main.beancount");
+        assert_eq!(parsed.errors, vec![]);
+        let printed: String = parsed.entries.iter().map(to_string).collect();
+        assert_eq!(printed, source);
+    }
+
+    #[test]
+    fn alignment() {
+        let source = "2020-01-04 * \"Buy\" ; 10 USD in a comment\n  Assets:Broker  2 STOCK {50 USD}\n  Assets:Cash   -100.5 USD ; paid\n  Expenses:Fees\n2020-01-07 price STOCK 1,055.25 USD\n";
+        assert_eq!(
+            align(source),
+            "2020-01-04 * \"Buy\" ; 10 USD in a comment\n  Assets:Broker             2    STOCK {50 USD}\n  Assets:Cash            -100.5  USD ; paid\n  Expenses:Fees\n2020-01-07 price STOCK  1,055.25 USD\n"
+        );
+        assert_eq!(align(&align(source)), align(source));
+
+        let entry = "2020-01-08 balance Assets:Cash  10 USD\n";
+        let aligned = Alignment::of(source).max(Alignment::of(entry)).apply(entry);
+        assert_eq!(aligned, "2020-01-08 balance Assets:Cash     10    USD\n");
+    }
+}
@@ -0,0 +1,201 @@
+//! Loaded ledgers, kept in the Worker's memory between requests
+//!
+//! A cached ledger is reused as long as the versions of its files are
+//! unchanged: its source files, the includes that were missing and the files
+//! in its documents folders.
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::rc::Rc;
+
+use serde::Serialize;
+use time::OffsetDateTime;
+
+use crate::core::FavaLedger;
+use crate::core::documents::document_folders;
+use crate::storage::{FileInfo, Storage, content_etag};
+
+/// When the ledger was loaded, and whether this request reused it
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub(crate) struct LoadInfo {
+    #[serde(with = "time::serde::rfc3339")]
+    pub loaded_at: OffsetDateTime,
+    pub cached: bool,
+}
+
+/// Versions of files by path, `None` for files that don't exist
+type Versions = BTreeMap<String, Option<String>>;
+
+struct CachedLedger {
+    /// versions of the files the ledger was loaded from
+    versions: Versions,
+    ledger: Rc<FavaLedger>,
+    loaded_at: OffsetDateTime,
+}
+
+/// The ETags in `files` of the ledger's source files, missing includes and documents
+///
+/// Sources listed without an ETag, keys written to KV from outside of the
+/// Worker, are left empty for [`versions`] to fill in.
+fn listed_versions(ledger: &FavaLedger, files: &[FileInfo]) -> Versions {
+    let folders = document_folders(&ledger.options, &ledger.filename);
+    let mut versions: Versions = ledger.sources.keys().chain(&ledger.missing_files).map(|path| (path.clone(), None)).collect();
+    for file in files {
+        let in_folder = folders
+            .iter()
+            .any(|folder| folder.is_empty() || file.path.strip_prefix(folder.as_str()).is_some_and(|rest| rest.starts_with('/')));
+        if versions.contains_key(&file.path) || in_folder {
+            versions.insert(file.path.clone(), Some(file.etag.clone()));
+        }
+    }
+    versions
+}
+
+/// The versions of the ledger's files in storage, see [`listed_versions`]
+///
+/// A source listed without an ETag is read and versioned by the hash of its contents.
+async fn versions(storage: &impl Storage, ledger: &FavaLedger, files: &[FileInfo]) -> Versions {
+    let mut versions = listed_versions(ledger, files);
+    for (path, version) in &mut versions {
+        if version.as_deref() == Some("") && ledger.sources.contains_key(path) {
+            *version = storage.get(path).await.ok().flatten().map(|file| content_etag(&file.contents));
+        }
+    }
+    versions
+}
+
+/// The versions of the files a ledger was just loaded from
+///
+/// The sources listed without an ETag are versioned by the hash of the contents
+/// that were loaded, so that a change during the load causes another one.
+fn loaded_versions(ledger: &FavaLedger, files: &[FileInfo]) -> Versions {
+    let mut versions = listed_versions(ledger, files);
+    for (path, version) in &mut versions {
+        if version.as_deref() == Some("")
+            && let Some(source) = ledger.sources.get(path)
+        {
+            *version = Some(content_etag(source.as_bytes()));
+        }
+    }
+    versions
+}
+
+/// Loaded ledgers by main file, shared by clones
+#[derive(Clone, Default)]
+pub(crate) struct LedgerCache(Rc<RefCell<HashMap<String, CachedLedger>>>);
+
+impl LedgerCache {
+    /// The ledger loaded from `filename`, reloaded only if one of its files changed
+    ///
+    /// `files` are all files in storage, listed once for every request, and
+    /// `now` is the time of a reload. If the storage couldn't be listed, the
+    /// ledger is loaded without caching it.
+    pub async fn load(&self, storage: &impl Storage, files: Option<&[FileInfo]>, filename: &str, now: OffsetDateTime) -> (Rc<FavaLedger>, LoadInfo) {
+        let Some(files) = files else {
+            self.0.borrow_mut().remove(filename);
+            let ledger = FavaLedger::load(storage, filename).await;
+            return (Rc::new(ledger), LoadInfo { loaded_at: now, cached: false });
+        };
+
+        let cached = self.0.borrow().get(filename).map(|cached| (cached.ledger.clone(), cached.versions.clone(), cached.loaded_at));
+        if let Some((ledger, cached_versions, loaded_at)) = cached
+            && versions(storage, &ledger, files).await == cached_versions
+        {
+            return (ledger, LoadInfo { loaded_at, cached: true });
+        }
+
+        // files listed before loading, so that a change during the load causes another one
+        let ledger = Rc::new(FavaLedger::load(storage, filename).await);
+        let cached = CachedLedger {
+            versions: loaded_versions(&ledger, files),
+            ledger: ledger.clone(),
+            loaded_at: now,
+        };
+        self.0.borrow_mut().insert(filename.to_string(), cached);
+        (ledger, LoadInfo { loaded_at: now, cached: false })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Helpers;
+    use crate::storage::{MemoryStorage, StoredFile, block_on};
+    use time::macros::datetime;
+
+    fn load(cache: &LedgerCache, storage: &impl Storage, now: OffsetDateTime) -> (Rc<FavaLedger>, LoadInfo) {
+        let files = block_on(storage.list()).ok();
+        block_on(cache.load(storage, files.as_deref(), "main.beancount", now))
+    }
+
+    #[test]
+    fn reload() {
+        let storage = MemoryStorage::new([
+            ("main.beancount", "option \"documents\" \"documents\"\ninclude \"accounts.beancount\"\ninclude \"prices.beancount\"\n"),
+            ("accounts.beancount", "2020-01-01 open Assets:Cash\n"),
+            ("unrelated.beancount", ""),
+        ]);
+        let cache = LedgerCache::default();
+        let first = datetime!(2020-01-01 0:00 UTC);
+        let second = datetime!(2020-01-02 0:00 UTC);
+
+        let (ledger, info) = load(&cache, &storage, first);
+        assert_eq!(info, LoadInfo { loaded_at: first, cached: false });
+        let (cached, info) = load(&cache, &storage, second);
+        assert!(Rc::ptr_eq(&ledger, &cached));
+        assert_eq!(info, LoadInfo { loaded_at: first, cached: true });
+
+        // other files don't matter, but included ones and documents do
+        block_on(storage.put("unrelated.beancount", b"; changed")).unwrap();
+        assert!(load(&cache, &storage, second).1.cached);
+        block_on(storage.put("accounts.beancount", b"2020-01-01 open Assets:Bank\n")).unwrap();
+        let (ledger, info) = load(&cache, &storage, second);
+        assert_eq!(info, LoadInfo { loaded_at: second, cached: false });
+        assert!(ledger.accounts.accounts().any(|account| account == "Assets:Bank"));
+        block_on(storage.put("documents/Assets/Bank/2020-01-02 statement.pdf", b"")).unwrap();
+        assert!(!load(&cache, &storage, second).1.cached);
+
+        // as does creating an include that was missing
+        assert!(load(&cache, &storage, second).1.cached);
+        block_on(storage.put("prices.beancount", b"2020-01-01 price EUR 1.1 USD\n")).unwrap();
+        let (ledger, info) = load(&cache, &storage, second);
+        assert!(!info.cached);
+        assert!(ledger.missing_files.is_empty());
+    }
+
+    /// Storage that lists files without ETags, like KV keys written from outside of the Worker
+    struct Unversioned(MemoryStorage);
+
+    impl Storage for Unversioned {
+        async fn list(&self) -> Result<Vec<FileInfo>, Helpers> {
+            let files = self.0.list().await?;
+            Ok(files.into_iter().map(|file| FileInfo { etag: String::new(), ..file }).collect())
+        }
+
+        async fn get(&self, path: &str) -> Result<Option<StoredFile>, Helpers> {
+            self.0.get(path).await
+        }
+
+        async fn put(&self, path: &str, contents: &[u8]) -> Result<String, Helpers> {
+            self.0.put(path, contents).await
+        }
+
+        async fn put_if_match(&self, path: &str, contents: &[u8], etag: &str) -> Result<Option<String>, Helpers> {
+            self.0.put_if_match(path, contents, etag).await
+        }
+    }
+
+    #[test]
+    fn unversioned() {
+        let storage = Unversioned(MemoryStorage::new([("main.beancount", "2020-01-01 open Assets:Cash\n")]));
+        let cache = LedgerCache::default();
+        let now = datetime!(2020-01-01 0:00 UTC);
+
+        assert!(!load(&cache, &storage, now).1.cached);
+        assert!(load(&cache, &storage, now).1.cached);
+        block_on(storage.put("main.beancount", b"2020-01-01 open Assets:Bank\n")).unwrap();
+        let (ledger, info) = load(&cache, &storage, now);
+        assert!(!info.cached);
+        assert!(ledger.accounts.accounts().any(|account| account == "Assets:Bank"));
+    }
+}
@@ -1,23 +1,27 @@
 // struct Accounts;
 
+use std::borrow::Borrow;
 use std::collections::HashMap;
 
-use crate::beans::abc::Directive;
+use crate::beans::abc::{AAmount, Balance, Directive, Entry, Meta};
+use crate::beans::funcs::{get_entry_accounts, hash_entry, sort_key};
+use crate::beans::str;
+use crate::core::tree::Tree;
 
 // impl Accounts {
-fn get_last_entry(postings: &Vec<Directive>) -> Option<&Directive> {
-    postings.into_iter()
+fn get_last_entry<D: Borrow<Directive>>(postings: &[D]) -> Option<&D> {
+    postings.iter()
         .rev()                    // Start from the end  
         .find(|entry| {          // Find first match going backwards
-            match entry {
+            match (*entry).borrow() {
                 Directive::Transactions(t) => !t.is_unrealized(),  // Keep non-unrealized
                 _ => true        // Keep all non-transaction directives
             }
         })
 }
 
-#[derive(Debug, PartialEq)]
-enum Status {
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Status {
     /// 'green':  A balance check that passed
     Pass,
     /// 'red':    A balance check that failed.
@@ -36,20 +40,24 @@ impl Status {
     }
 }
 
+impl serde::Serialize for Status {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
 /// Status of the last balance or transaction
-fn uptodate_status(postings: &Vec<Directive>) -> Option<Status> {
-    for entry in postings.into_iter().rev() {
-        match entry {
-            Directive::Balance(_, _, balance) => {
-                if balance.is_some() {
+fn uptodate_status<D: Borrow<Directive>>(postings: &[D]) -> Option<Status> {
+    for entry in postings.iter().rev() {
+        match entry.borrow() {
+            Directive::Balance(balance) => {
+                if balance.diff_amount.is_some() {
                     return Some(Status::Fail);
                 }
                 return Some(Status::Pass);
             }
-            Directive::Transactions(transaction) => {
-                if !transaction.is_unrealized() {
-                    return Some(Status::NotApplicable);
-                }
+            Directive::Transactions(transaction) if !transaction.is_unrealized() => {
+                return Some(Status::NotApplicable);
             }
             _ => {
                 // Continue to next entry for other directive types
@@ -62,103 +70,129 @@ fn uptodate_status(postings: &Vec<Directive>) -> Option<Status> {
 /// Balance directive for the given account for today
 fn balance_string(tree_node: &super::tree::TreeNode) -> String {
     let today = time::OffsetDateTime::now_utc().date();
-    let account = &tree_node.get_name();
-    let mut res = String::new();
-    
-    for (currency, number) in &tree_node.get_balance() {
-        res.push_str(&format!(
-            "{} balance {:<28} {:>15} {}\n",
-            today, account, number, currency
-        ));
-    }
-    
-    res
+    let balance = tree_node.get_balance();
+    let mut currencies: Vec<_> = balance.iter().collect();
+    currencies.sort_by_key(|(currency, _)| currency.as_str());
+
+    let directives: String = currencies
+        .into_iter()
+        .map(|(currency, number)| str::to_string(&Directive::Balance(Balance::new(today, tree_node.get_name(), AAmount(*number, currency.clone())))))
+        .collect();
+    str::align(&directives)
 }
 // }
 
 /// Date and hash of the last entry for an account
-struct LastEntry(time::Date, String);
+#[derive(Debug, PartialEq)]
+pub(crate) struct LastEntry(pub time::Date, pub String);
 
 /// Holds information about an account
 #[derive(Default)]
-struct AccountData {
+pub(crate) struct AccountData {
     /// The date on which this account is closed 
-    close_date: Option<time::Date>,
-    // The metadata of the Open entry of this account
-    // meta: 
+    pub close_date: Option<time::Date>,
+    /// The metadata of the Open entry of this account
+    pub meta: Meta,
     /// Uptodate status. Is only computed if the account has a "fava-uptodate-indication" meta attribute.
-    uptodate_status: Option<Status>,
+    pub uptodate_status: Option<Status>,
     /// Balance directive if this account has an uptodate status
-    balance_string: Option<String>,
+    pub balance_string: Option<String>,
     /// The last entry of the account (unless it is a close Entry)
-    last_entry: Option<LastEntry>,
+    pub last_entry: Option<LastEntry>,
+}
+
+/// Entries touching each account, sorted
+fn group_entries_by_account(entries: &[Directive]) -> HashMap<&str, Vec<&Directive>> {
+    let mut groups: HashMap<&str, Vec<&Directive>> = HashMap::new();
+    for entry in entries {
+        for account in get_entry_accounts(entry) {
+            let group = groups.entry(account).or_default();
+            // a transaction with several postings to one account is listed once
+            if !group.last().is_some_and(|last| std::ptr::eq(*last, entry)) {
+                group.push(entry);
+            }
+        }
+    }
+    for group in groups.values_mut() {
+        group.sort_by_key(|entry| sort_key(entry));
+    }
+    groups
 }
 
 /// Account info dictionary
 #[derive(Default)]
-struct AccountDict(HashMap<String, AccountData>);
+pub(crate) struct AccountDict(HashMap<String, AccountData>);
 
 impl AccountDict {
     const EMPTY: AccountData = AccountData {
         close_date: None,
-        // meta: HashMap::new(),  // This won't work with const - see below
+        meta: Meta {
+            filename: String::new(),
+            lineno: 0,
+            values: std::collections::BTreeMap::new(),
+        },
         uptodate_status: None,
         balance_string: None,
         last_entry: None,
     };
 
-    fn get_or_empty(&self, key: &str) -> &AccountData {
-        self.0.get(key).unwrap_or(&Self::EMPTY)
+    pub fn get_or_empty(&self, key: &str) -> &AccountData {
+        // the metadata has to be dropped, so the constant isn't promoted to a static on its own
+        static EMPTY: AccountData = AccountDict::EMPTY;
+        self.0.get(key).unwrap_or(&EMPTY)
     }
 
     fn get_or_insert(&mut self, key: String) -> &mut AccountData {
-        self.0.entry(key).or_insert_with(AccountData::default)
+        self.0.entry(key).or_default()
+    }
+
+    /// Account names, unsorted
+    pub fn accounts(&self) -> impl Iterator<Item = &str> {
+        self.0.keys().map(String::as_str)
     }
 
-    fn load_file(&mut self) {
+    pub fn load_file(&mut self, entries: &[Directive]) {
         self.0.clear();
 
-        todo!()
-        
-        // // This will need to come from your ledger/entries system
-        // let entries_by_account = group_entries_by_account(&self.ledger.all_entries);
-        // let tree = Tree::new(&self.ledger.all_entries);
-        
-        // // Process Open entries
-        // for open_entry in &self.ledger.all_entries_by_type.open {
-        //     let meta = &open_entry.meta;
-        //     let account_data = self.get_or_insert(open_entry.account.clone());
-        //     account_data.meta = meta.clone();
-
-        //     let txn_postings = &entries_by_account[&open_entry.account];
-        //     let last = get_last_entry(txn_postings);
-            
-        //     if let Some(last_entry) = last {
-        //         if !matches!(last_entry, Directive::Close) {
-        //             account_data.last_entry = Some(LastEntry {
-        //                 date: last_entry.get_date(), // You'll need to implement this
-        //                 entry_hash: hash_entry(last_entry), // You'll need to implement this
-        //             });
-        //         }
-        //     }
-            
-        //     if meta.get("fava-uptodate-indication").is_some() {
-        //         account_data.uptodate_status = uptodate_status(txn_postings);
-        //         if account_data.uptodate_status != Some(Status::Pass) {
-        //             if let Some(tree_node) = tree.get(&open_entry.account) {
-        //                 account_data.balance_string = Some(balance_string(tree_node));
-        //             }
-        //         }
-        //     }
-        // }
-        
-        // // Process Close entries
-        // for close_entry in &self.ledger.all_entries_by_type.close {
-        //     self.get_or_insert(close_entry.account.clone()).close_date = Some(close_entry.date);
-        // }
+        let entries_by_account = group_entries_by_account(entries);
+        let tree = Tree::new(entries);
+
+        // Process Open entries
+        for open_entry in entries.iter().filter_map(|entry| match entry {
+            Directive::Open(open) => Some(open),
+            _ => None,
+        }) {
+            let meta = &open_entry.meta;
+            let account_data = self.get_or_insert(open_entry.account.clone());
+            account_data.meta = meta.clone();
+
+            let txn_postings = &entries_by_account[open_entry.account.as_str()];
+            if let Some(last_entry) = get_last_entry(txn_postings)
+                && !matches!(last_entry, Directive::Close(_))
+            {
+                account_data.last_entry = Some(LastEntry(last_entry.get_date(), hash_entry(last_entry)));
+            }
+
+            if meta.get("fava-uptodate-indication").is_some() {
+                account_data.uptodate_status = uptodate_status(txn_postings);
+                if account_data.uptodate_status != Some(Status::Pass)
+                    && let Some(tree_node) = tree.get(&open_entry.account)
+                {
+                    account_data.balance_string = Some(balance_string(tree_node));
+                }
+            }
+        }
+
+        // Process Close entries
+        for entry in entries {
+            if let Directive::Close(close_entry) = entry {
+                self.get_or_insert(close_entry.account.clone()).close_date = Some(close_entry.date);
+            }
+        }
     }
 
-    fn all_balance_directives(&self) -> String {
+    #[allow(dead_code)]
+    pub fn all_balance_directives(&self) -> String {
         let mut result = String::new();
         for account_details in self.0.values() {
             if let Some(balance_string) = &account_details.balance_string {
@@ -175,7 +209,19 @@ mod tests {
 
     use super::*;
 
-    use crate::{beans::{abc::{AAmount, Directive, Transaction}, flags::Flags}, core::tree::TreeNode};
+    use crate::{beans::{abc::{AAmount, Balance, Close, Directive, MetaValue, Open, Posting, Transaction}, flags::Flags}, core::tree::TreeNode};
+
+    fn today() -> time::Date {
+        time::OffsetDateTime::now_utc().date()
+    }
+
+    fn open() -> Directive {
+        Directive::Open(Open::new(today(), "Assets:Checking"))
+    }
+
+    fn unrealized() -> Directive {
+        Directive::Transactions(Transaction::new(today(), Flags::Unrealized, "", vec![]))
+    }
 
     #[test]
     fn empty_list() {
@@ -185,28 +231,30 @@ mod tests {
 
     #[test]
     fn single_directive() {
-        assert!(get_last_entry(&vec![Directive::Open]).is_some());
-        assert!(uptodate_status(&vec![Directive::Open]).is_none());
+        assert!(get_last_entry(&[open()]).is_some());
+        assert!(uptodate_status(&[open()]).is_none());
     }
 
     #[test]
     fn with_unrealized() {
-        let entries = vec![Directive::Open, Directive::Transactions(Transaction(Flags::Unrealized))];
+        let entries = vec![open(), unrealized()];
 
-        assert_eq!(get_last_entry(&entries), Some(&Directive::Open));
+        assert_eq!(get_last_entry(&entries), Some(&open()));
         assert_eq!(uptodate_status(&entries), None);
     }
 
     #[test]
     fn with_balance() {
-        let entries = vec![Directive::Open, Directive::Balance(time::OffsetDateTime::now_utc().date(), "Checking".into(), None)];
+        let entries = vec![open(), Directive::Balance(Balance::new(today(), "Assets:Checking", AAmount(100., "USD".into())))];
 
         assert_eq!(uptodate_status(&entries), Some(Status::Pass));
     }
 
     #[test]
     fn with_diff_balance() {
-        let entries = vec![Directive::Open, Directive::Balance(time::OffsetDateTime::now_utc().date(), "Checking".into(), Some(AAmount(100., "USD")))];
+        let mut balance = Balance::new(today(), "Assets:Checking", AAmount(100., "USD".into()));
+        balance.diff_amount = Some(AAmount(100., "USD".into()));
+        let entries = vec![open(), Directive::Balance(balance)];
         
         assert_eq!(uptodate_status(&entries), Some(Status::Fail));
     }
@@ -214,12 +262,12 @@ mod tests {
     #[test]
     fn multiple_valid_entries() {
         let entries = vec![
-            Directive::Open,                                           // First valid
-            Directive::Transactions(Transaction(Flags::Unrealized)),   // Unrealized (filtered out)
-            Directive::Close,                                          // Last valid
+            open(),                                                    // First valid
+            unrealized(),                                              // Unrealized (filtered out)
+            Directive::Close(Close::new(today(), "Assets:Checking")),  // Last valid
         ];
 
-        assert_eq!(get_last_entry(&entries), Some(&Directive::Close));
+        assert_eq!(get_last_entry(&entries), Some(&entries[2]));
         assert_eq!(uptodate_status(&entries), None);
     }
 
@@ -233,7 +281,7 @@ mod tests {
         
         let result = balance_string(&tree_node);
         let today = time::OffsetDateTime::now_utc().date();
-        let expected = format!("{} balance Assets:Cash                          1234.56 USD\n", today);
+        let expected = format!("{} balance Assets:Cash  1234.56 USD\n", today);
         
         assert_eq!(result, expected);
     }
@@ -250,14 +298,12 @@ mod tests {
         
         let result = balance_string(&tree_node);
         let today = time::OffsetDateTime::now_utc().date();
-        
-        dbg!(&result);
 
-        // Since HashMap iteration order isn't guaranteed, check that all expected lines are present
-        // assert!(result.contains(&format!("{} balance Assets:Checking                          1000.00 USD\n", today)));
-        // assert!(result.contains(&format!("{} balance Assets:Checking               500.75 EUR\n", today)));
-        // assert!(result.contains(&format!("{} balance Assets:Checking               250.50 GBP\n", today)));
-        assert_eq!(result.lines().count(), 3);
+        // sorted by currency, with the numbers aligned on their decimal point
+        let expected = format!(
+            "{today} balance Assets:Checking   500.75 EUR\n{today} balance Assets:Checking   250.5  GBP\n{today} balance Assets:Checking  1000    USD\n"
+        );
+        assert_eq!(result, expected);
     }
 
     #[test]
@@ -281,7 +327,7 @@ mod tests {
         
         let result = balance_string(&tree_node);
         let today = time::OffsetDateTime::now_utc().date();
-        let expected = format!("{} balance Liabilities:CreditCard               -500.25 USD\n", today);
+        let expected = format!("{} balance Liabilities:CreditCard  -500.25 USD\n", today);
         
         assert_eq!(result, expected);
     }
@@ -296,8 +342,7 @@ mod tests {
         
         let result = balance_string(&tree_node);
         let today = time::OffsetDateTime::now_utc().date();
-        // Account name longer than 28 chars should still work (just won't align perfectly)
-        let expected = format!("{} balance Assets:Investment:RetirementAccount:401k             100 USD\n", today);
+        let expected = format!("{} balance Assets:Investment:RetirementAccount:401k  100 USD\n", today);
         
         assert_eq!(result, expected);
     }
@@ -312,8 +357,44 @@ mod tests {
         
         let result = balance_string(&tree_node);
         let today = time::OffsetDateTime::now_utc().date();
-        let expected = format!("{} balance Assets:Test                                0 USD\n", today);
+        let expected = format!("{} balance Assets:Test  0 USD\n", today);
         
         assert_eq!(result, expected);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn load_file() {
+        let mut checking = Open::new(today(), "Assets:Checking");
+        checking.meta.values.insert("fava-uptodate-indication".into(), MetaValue::Bool(true));
+        let deposit = Directive::Transactions(Transaction::new(
+            today(),
+            Flags::Okay,
+            "Deposit",
+            vec![
+                Posting::new("Assets:Checking", AAmount(100., "USD".into())),
+                Posting::new("Assets:Savings", AAmount(-100., "USD".into())),
+            ],
+        ));
+        let entries = vec![
+            Directive::Open(checking),
+            Directive::Open(Open::new(today(), "Assets:Savings")),
+            deposit.clone(),
+            Directive::Close(Close::new(today(), "Assets:Savings")),
+        ];
+
+        let mut accounts = AccountDict::default();
+        accounts.load_file(&entries);
+
+        let checking = accounts.get_or_empty("Assets:Checking");
+        assert_eq!(checking.last_entry, Some(LastEntry(today(), hash_entry(&deposit))));
+        assert_eq!(checking.uptodate_status, Some(Status::NotApplicable));
+        assert_eq!(accounts.all_balance_directives(), format!("{} balance Assets:Checking  100 USD\n", today()));
+
+        let savings = accounts.get_or_empty("Assets:Savings");
+        assert_eq!(savings.close_date, Some(today()));
+        assert!(savings.last_entry.is_none());
+        assert!(savings.uptodate_status.is_none());
+
+        assert!(accounts.get_or_empty("Assets:Unknown").last_entry.is_none());
+    }
+}
@@ -0,0 +1,421 @@
+//! Accounts, payees, tags, links, currencies and narrations, ranked for autocompletion
+//!
+//! see: https://github.com/beancount/fava/blob/main/src/fava/core/attributes.py
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::Helpers;
+use crate::beans::abc::{Directive, Entry, Transaction};
+use crate::beans::funcs::{get_entry_accounts, hash_entry, hex};
+use crate::storage::Storage;
+use crate::util::ranking::ExponentialDecayRanker;
+
+/// A kind of value to complete
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Attribute {
+    Accounts,
+    Currencies,
+    Links,
+    Narrations,
+    Payees,
+    Tags,
+}
+
+impl std::str::FromStr for Attribute {
+    type Err = Helpers;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "accounts" => Ok(Self::Accounts),
+            "currencies" => Ok(Self::Currencies),
+            "links" => Ok(Self::Links),
+            "narrations" => Ok(Self::Narrations),
+            "payees" => Ok(Self::Payees),
+            "tags" => Ok(Self::Tags),
+            invalid_str => Err(Helpers::FavaError(format!("Invalid attribute: {invalid_str}"))),
+        }
+    }
+}
+
+/// How often the values of one attribute were used on every date
+#[derive(Default)]
+struct Uses<'a>(BTreeMap<(&'a str, time::Date), u32>);
+
+impl<'a> Uses<'a> {
+    fn add(&mut self, value: &'a str, date: time::Date) {
+        if !value.is_empty() {
+            *self.0.entry((value, date)).or_default() += 1;
+        }
+    }
+
+    /// A ranker of all values, ties sorted by name
+    fn rank(self) -> ExponentialDecayRanker {
+        let values: BTreeSet<&str> = self.0.keys().map(|(value, _)| *value).collect();
+        let mut ranker = ExponentialDecayRanker::new_with_list(values.into_iter().map(String::from).collect());
+        for ((value, date), count) in self.0 {
+            ranker.update_weighted(value, date, count.into());
+        }
+        ranker
+    }
+}
+
+/// Add the likes of `other` to `ranker`, keeping the ties sorted by name
+fn merge(ranker: &mut ExponentialDecayRanker, other: &ExponentialDecayRanker) -> Result<(), Helpers> {
+    let values: BTreeSet<String> = ranker.sort().into_iter().chain(other.sort()).collect();
+    let mut merged = ExponentialDecayRanker::new_with_list(values.into_iter().collect());
+    merged.merge(ranker)?;
+    merged.merge(other)?;
+    *ranker = merged;
+    Ok(())
+}
+
+/// Path of the stored rankings of the ledger with the main file `filename`
+pub(crate) fn rankings_path(filename: &str) -> String {
+    format!(".rankings/{filename}.json")
+}
+
+/// The rankers of every attribute, stored so that a load only counts the entries added since
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub(crate) struct Rankings {
+    /// number of entries counted
+    counted: usize,
+    /// digest of the hashes of the counted entries
+    digest: String,
+    accounts: ExponentialDecayRanker,
+    currencies: ExponentialDecayRanker,
+    links: ExponentialDecayRanker,
+    narrations: ExponentialDecayRanker,
+    payees: ExponentialDecayRanker,
+    tags: ExponentialDecayRanker,
+    /// the accounts of the postings of every payee
+    payee_accounts: BTreeMap<String, ExponentialDecayRanker>,
+}
+
+impl Rankings {
+    /// Rank the values used by `entries`, every use counts on the date of its entry
+    fn new(entries: &[Directive]) -> Self {
+        let (mut accounts, mut currencies, mut links, mut narrations, mut payees, mut tags) = Default::default();
+        let mut payee_accounts: BTreeMap<&str, Uses> = BTreeMap::new();
+        for entry in entries {
+            let date = entry.get_date();
+            for account in get_entry_accounts(entry) {
+                Uses::add(&mut accounts, account, date);
+            }
+            match entry {
+                Directive::Open(open) => {
+                    for currency in &open.currencies {
+                        Uses::add(&mut currencies, currency, date);
+                    }
+                }
+                Directive::Commodity(commodity) => Uses::add(&mut currencies, &commodity.currency, date),
+                Directive::Transactions(transaction) => {
+                    Uses::add(&mut payees, transaction.payee.as_deref().unwrap_or_default(), date);
+                    if let Some(payee) = transaction.payee.as_deref().filter(|payee| !payee.is_empty()) {
+                        let uses = payee_accounts.entry(payee).or_default();
+                        for posting in &transaction.postings {
+                            uses.add(&posting.account, date);
+                        }
+                    }
+                    Uses::add(&mut narrations, &transaction.narration, date);
+                    for tag in &transaction.tags {
+                        Uses::add(&mut tags, tag, date);
+                    }
+                    for link in &transaction.links {
+                        Uses::add(&mut links, link, date);
+                    }
+                    for posting in &transaction.postings {
+                        Uses::add(&mut currencies, &posting.units.1, date);
+                        if let Some(cost) = &posting.cost {
+                            Uses::add(&mut currencies, &cost.currency, date);
+                        }
+                        if let Some(price) = &posting.price {
+                            Uses::add(&mut currencies, &price.1, date);
+                        }
+                    }
+                }
+                Directive::Balance(balance) => Uses::add(&mut currencies, &balance.amount.1, date),
+                Directive::Document(document) => {
+                    for tag in &document.tags {
+                        Uses::add(&mut tags, tag, date);
+                    }
+                    for link in &document.links {
+                        Uses::add(&mut links, link, date);
+                    }
+                }
+                Directive::Price(price) => {
+                    Uses::add(&mut currencies, &price.currency, date);
+                    Uses::add(&mut currencies, &price.amount.1, date);
+                }
+                _ => {}
+            }
+        }
+        Self {
+            counted: 0,
+            digest: String::new(),
+            accounts: accounts.rank(),
+            currencies: currencies.rank(),
+            links: links.rank(),
+            narrations: narrations.rank(),
+            payees: payees.rank(),
+            tags: tags.rank(),
+            payee_accounts: payee_accounts.into_iter().map(|(payee, uses)| (payee.to_string(), uses.rank())).collect(),
+        }
+    }
+
+    /// The rankings stored at `path`, empty ones if there are none or they can't be read
+    pub async fn load(storage: &impl Storage, path: &str) -> Self {
+        let stored = storage.get(path).await.ok().flatten();
+        stored.and_then(|file| serde_json::from_slice(&file.contents).ok()).unwrap_or_default()
+    }
+
+    /// Store the rankings at `path`
+    pub async fn save(&self, storage: &impl Storage, path: &str) -> Result<(), Helpers> {
+        let json = serde_json::to_vec(self).expect("rankings serialise to JSON");
+        storage.put(path, &json).await.map(|_| ())
+    }
+
+    /// Digest of the counted entries, changed by every count that changes the rankings
+    pub fn digest(&self) -> &str {
+        &self.digest
+    }
+
+    /// Count the uses in `entries`, sorted, unless they were counted already
+    ///
+    /// If the counted entries are unchanged and only followed by added ones,
+    /// just the added ones are counted, otherwise all entries are counted
+    /// anew. Returns whether the rankings changed.
+    pub fn count(&mut self, entries: &[Directive]) -> bool {
+        let hashes: Vec<String> = entries.iter().map(hash_entry).collect();
+        let digest = |hashes: &[String]| hex(&Sha256::digest(hashes.concat()));
+        let all = digest(&hashes);
+        if self.counted == entries.len() && self.digest == all {
+            return false;
+        }
+        let prefix = self.counted <= entries.len() && self.digest == digest(&hashes[..self.counted]);
+        if !prefix || self.merge(&Self::new(&entries[self.counted..])).is_err() {
+            *self = Self::new(entries);
+        }
+        self.counted = entries.len();
+        self.digest = all;
+        true
+    }
+
+    /// Add the likes of `other`, which must have the same rates
+    fn merge(&mut self, other: &Self) -> Result<(), Helpers> {
+        merge(&mut self.accounts, &other.accounts)?;
+        merge(&mut self.currencies, &other.currencies)?;
+        merge(&mut self.links, &other.links)?;
+        merge(&mut self.narrations, &other.narrations)?;
+        merge(&mut self.payees, &other.payees)?;
+        merge(&mut self.tags, &other.tags)?;
+        for (payee, accounts) in &other.payee_accounts {
+            merge(self.payee_accounts.entry(payee.clone()).or_default(), accounts)?;
+        }
+        Ok(())
+    }
+}
+
+/// What was booked with a payee
+#[derive(Debug)]
+struct Payee {
+    /// accounts of the postings, ranked
+    accounts: Vec<String>,
+    /// the most recent transaction
+    last: Transaction,
+}
+
+/// The ranked values of every attribute, and an index of the payees
+#[derive(Debug, Default)]
+pub(crate) struct Attributes {
+    accounts: Vec<String>,
+    currencies: Vec<String>,
+    links: Vec<String>,
+    narrations: Vec<String>,
+    payees: Vec<String>,
+    tags: Vec<String>,
+    payee_index: HashMap<String, Payee>,
+}
+
+impl Attributes {
+    /// The values ranked by `rankings`, which counted `entries`
+    pub fn new(entries: &[Directive], rankings: &Rankings) -> Self {
+        let mut last_transactions: HashMap<&str, &Transaction> = HashMap::new();
+        for entry in entries {
+            if let Directive::Transactions(transaction) = entry
+                && let Some(payee) = transaction.payee.as_deref().filter(|payee| !payee.is_empty())
+            {
+                // entries are sorted, the last transaction wins
+                last_transactions.insert(payee, transaction);
+            }
+        }
+        Self {
+            accounts: rankings.accounts.sort(),
+            currencies: rankings.currencies.sort(),
+            links: rankings.links.sort(),
+            narrations: rankings.narrations.sort(),
+            payees: rankings.payees.sort(),
+            tags: rankings.tags.sort(),
+            payee_index: last_transactions
+                .into_iter()
+                .map(|(payee, last)| {
+                    let payee_data = Payee {
+                        accounts: rankings.payee_accounts.get(payee).map(ExponentialDecayRanker::sort).unwrap_or_default(),
+                        last: last.clone(),
+                    };
+                    (payee.to_string(), payee_data)
+                })
+                .collect(),
+        }
+    }
+
+    /// All accounts, those used with `payee` first
+    ///
+    /// The accounts of the payee are ranked by their uses with it, the others
+    /// keep their overall rank.
+    pub fn payee_accounts(&self, payee: &str) -> Vec<&str> {
+        let used: &[String] = self.payee_index.get(payee).map(|payee| payee.accounts.as_slice()).unwrap_or_default();
+        let mut accounts: Vec<&str> = used.iter().map(String::as_str).collect();
+        accounts.extend(self.accounts.iter().map(String::as_str).filter(|account| !used.iter().any(|other| other == account)));
+        accounts
+    }
+
+    /// The most recent transaction with `payee`
+    pub fn payee_transaction(&self, payee: &str) -> Option<&Transaction> {
+        self.payee_index.get(payee).map(|payee| &payee.last)
+    }
+
+    /// All values of the attribute, ranked
+    pub fn get(&self, attribute: Attribute) -> &[String] {
+        match attribute {
+            Attribute::Accounts => &self.accounts,
+            Attribute::Currencies => &self.currencies,
+            Attribute::Links => &self.links,
+            Attribute::Narrations => &self.narrations,
+            Attribute::Payees => &self.payees,
+            Attribute::Tags => &self.tags,
+        }
+    }
+
+    /// The values of the attribute matching `query`, ignoring case
+    ///
+    /// Values starting with the query come first, then those containing its
+    /// characters in order, like `exfood` for `Expenses:Food`. Both keep the
+    /// ranking.
+    pub fn complete(&self, attribute: Attribute, query: &str) -> Vec<&str> {
+        let query = query.to_lowercase();
+        let (mut prefixed, fuzzy): (Vec<&str>, Vec<&str>) = self
+            .get(attribute)
+            .iter()
+            .map(String::as_str)
+            .filter(|value| is_subsequence(&query, &value.to_lowercase()))
+            .partition(|value| value.to_lowercase().starts_with(&query));
+        prefixed.extend(fuzzy);
+        prefixed
+    }
+}
+
+/// Whether the characters of `query` appear in `text` in order
+fn is_subsequence(query: &str, text: &str) -> bool {
+    let mut chars = text.chars();
+    query.chars().all(|wanted| chars.any(|c| c == wanted))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::beans::abc::{AAmount, Posting, Transaction};
+    use crate::beans::flags::Flags;
+    use crate::storage::{MemoryStorage, block_on};
+    use time::macros::date;
+
+    fn attributes(entries: &[Directive]) -> Attributes {
+        let mut rankings = Rankings::default();
+        rankings.count(entries);
+        Attributes::new(entries, &rankings)
+    }
+
+    fn transaction(date: time::Date, payee: &str, account: &str) -> Directive {
+        let mut transaction = Transaction::new(
+            date,
+            Flags::Okay,
+            "",
+            vec![Posting::new(account, AAmount(10., "USD".into())), Posting::new("Assets:Cash", AAmount(-10., "USD".into()))],
+        );
+        transaction.payee = Some(payee.into());
+        Directive::Transactions(transaction)
+    }
+
+    #[test]
+    fn ranked() {
+        let entries = [
+            transaction(date!(2019-01-01), "Bakery", "Expenses:Food"),
+            transaction(date!(2019-02-01), "Bakery", "Expenses:Food"),
+            transaction(date!(2020-06-01), "Bookshop", "Expenses:Books"),
+            transaction(date!(2020-06-01), "Butcher", "Expenses:Food"),
+        ];
+        let attributes = attributes(&entries);
+        assert_eq!(attributes.get(Attribute::Payees), &["Bookshop", "Butcher", "Bakery"]);
+        assert_eq!(attributes.get(Attribute::Accounts), &["Assets:Cash", "Expenses:Food", "Expenses:Books"]);
+        assert_eq!(attributes.get(Attribute::Currencies), &["USD"]);
+        assert!(attributes.get(Attribute::Narrations).is_empty());
+
+        assert_eq!(attributes.complete(Attribute::Payees, "b"), vec!["Bookshop", "Butcher", "Bakery"]);
+        assert_eq!(attributes.complete(Attribute::Payees, "bu"), vec!["Butcher"]);
+        assert_eq!(attributes.complete(Attribute::Accounts, "exfo"), vec!["Expenses:Food"]);
+        // prefix matches come before fuzzy ones
+        assert_eq!(attributes.complete(Attribute::Accounts, "e"), vec!["Expenses:Food", "Expenses:Books", "Assets:Cash"]);
+        assert!(attributes.complete(Attribute::Tags, "").is_empty());
+        assert!("tags".parse::<Attribute>().is_ok() && "dates".parse::<Attribute>().is_err());
+    }
+
+    #[test]
+    fn payees() {
+        let entries = [
+            transaction(date!(2019-01-01), "Bakery", "Expenses:Food"),
+            transaction(date!(2020-02-01), "Bakery", "Expenses:Books"),
+            transaction(date!(2020-06-01), "Bookshop", "Expenses:Books"),
+        ];
+        let attributes = attributes(&entries);
+        // both accounts are used with the bakery, the books more recently
+        assert_eq!(attributes.payee_accounts("Bakery"), vec!["Assets:Cash", "Expenses:Books", "Expenses:Food"]);
+        assert_eq!(attributes.payee_accounts("Bookshop"), vec!["Assets:Cash", "Expenses:Books", "Expenses:Food"]);
+        assert_eq!(attributes.payee_accounts("Unknown"), attributes.get(Attribute::Accounts));
+
+        let last = attributes.payee_transaction("Bakery").unwrap();
+        assert_eq!(last.date, date!(2020-02-01));
+        assert!(attributes.payee_transaction("Unknown").is_none());
+    }
+
+    #[test]
+    fn stored_rankings() {
+        let storage = MemoryStorage::new([]);
+        let path = rankings_path("main.beancount");
+        let mut entries = vec![
+            transaction(date!(2019-01-01), "Bakery", "Expenses:Food"),
+            transaction(date!(2020-06-01), "Bookshop", "Expenses:Books"),
+        ];
+        let mut rankings = block_on(Rankings::load(&storage, &path));
+        assert!(rankings.count(&entries));
+        assert!(!rankings.count(&entries));
+        block_on(rankings.save(&storage, &path)).unwrap();
+
+        // only the added entry is counted, and ranked like on a full count
+        entries.push(transaction(date!(2020-07-01), "Bakery", "Expenses:Food"));
+        let mut restored = block_on(Rankings::load(&storage, &path));
+        assert_eq!(restored.counted, 2);
+        assert!(restored.count(&entries));
+        let attributes = Attributes::new(&entries, &restored);
+        assert_eq!(attributes.get(Attribute::Payees), &["Bakery", "Bookshop"]);
+        assert_eq!(attributes.get(Attribute::Payees), self::attributes(&entries).get(Attribute::Payees));
+        assert_eq!(attributes.payee_accounts("Bakery"), vec!["Assets:Cash", "Expenses:Food", "Expenses:Books"]);
+
+        // a changed entry is counted anew
+        entries[0] = transaction(date!(2019-01-01), "Butcher", "Expenses:Food");
+        assert!(restored.count(&entries));
+        assert_eq!(Attributes::new(&entries, &restored).get(Attribute::Payees), &["Bakery", "Bookshop", "Butcher"]);
+        assert!(block_on(Rankings::load(&storage, "missing.json")).digest.is_empty());
+    }
+}
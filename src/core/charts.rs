@@ -0,0 +1,351 @@
+//! Data for the charts of the frontend
+//!
+//! see: https://github.com/beancount/fava/blob/main/src/fava/core/charts.py
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::Serialize;
+use time::Date;
+
+use crate::beans::abc::{Directive, Entry};
+use crate::beans::account;
+use crate::beans::prices::PriceMap;
+use crate::core::{FavaLedger, interval_balances};
+use crate::core::conversion::Conversion;
+use crate::core::filters::Filters;
+use crate::core::inventory::{self, CounterInventory, LotInventory};
+use crate::core::tree::{HierarchyOptions, SerialisedTreeNode, Tree};
+use crate::util::date::Interval;
+
+/// A balance on a date
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct DateAndBalance {
+    pub date: Date,
+    pub balance: CounterInventory,
+}
+
+/// A number on a date
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub(crate) struct DateAndValue {
+    pub date: Date,
+    pub value: f32,
+}
+
+/// The total of an interval, and the totals of the child accounts that make it up
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct IntervalTotal {
+    pub date: Date,
+    pub balance: CounterInventory,
+    pub account_balances: BTreeMap<String, CounterInventory>,
+}
+
+/// The prices of one currency pair over time
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct CommodityPrices {
+    pub base: String,
+    pub quote: String,
+    pub prices: Vec<DateAndValue>,
+}
+
+/// The numbers of one currency over time, a line of a chart
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct Series {
+    pub currency: String,
+    pub values: Vec<DateAndValue>,
+}
+
+/// The transactions matching the account filter
+fn transactions<'a>(ledger: &'a FavaLedger, filters: &'a Filters) -> impl Iterator<Item = &'a Directive> {
+    ledger
+        .entries
+        .iter()
+        .filter(|entry| filters.matches_account(entry))
+        .filter(|entry| matches!(entry, Directive::Transactions(_)))
+}
+
+/// The converted balance of `accounts` and their descendants, on the last day of every interval
+///
+/// The intervals cover the time filter, or all transactions without one.
+/// Balances include all earlier transactions, the account filter selects the
+/// transactions to include.
+pub(crate) fn interval_end_balances(
+    ledger: &FavaLedger,
+    interval: Interval,
+    conversion: &Conversion,
+    filters: &Filters,
+    accounts: &[&str],
+) -> Vec<DateAndBalance> {
+    let fye = &ledger.fava_options.fiscal_year_end;
+    interval_balances::<LotInventory>(transactions(ledger, filters), interval, fye, accounts, filters.time, true)
+        .into_iter()
+        .map(|(period, inventory)| DateAndBalance {
+            date: period.last,
+            balance: conversion.apply(&inventory, &ledger.prices, Some(period.last)),
+        })
+        .collect()
+}
+
+/// Net worth, the balance of all assets and liabilities, at the end of every interval
+pub(crate) fn net_worth(ledger: &FavaLedger, interval: Interval, conversion: &Conversion, filters: &Filters) -> Vec<DateAndBalance> {
+    let options = &ledger.options;
+    interval_end_balances(ledger, interval, conversion, filters, &[&options.name_assets, &options.name_liabilities])
+}
+
+/// Balance of `account` and its descendants at the end of every interval
+pub(crate) fn account_balance(ledger: &FavaLedger, account: &str, interval: Interval, conversion: &Conversion, filters: &Filters) -> Vec<DateAndBalance> {
+    interval_end_balances(ledger, interval, conversion, filters, &[account])
+}
+
+/// The total of the postings to `account` and its descendants in every interval, by child account
+///
+/// Every total is dated on the first day of its interval and converted on
+/// the last one. The children are those one level below `account`, postings
+/// to `account` itself are listed under it. With `invert` the signs are
+/// flipped, e.g. to show income as positive numbers.
+pub(crate) fn interval_totals(
+    ledger: &FavaLedger,
+    account: &str,
+    interval: Interval,
+    conversion: &Conversion,
+    filters: &Filters,
+    invert: bool,
+) -> Vec<IntervalTotal> {
+    let depth = if account.is_empty() { 1 } else { account.split(':').count() + 1 };
+    let fye = &ledger.fava_options.fiscal_year_end;
+    interval_balances::<BTreeMap<String, LotInventory>>(transactions(ledger, filters), interval, fye, &[account], filters.time, false)
+        .into_iter()
+        .map(|(period, balances)| {
+            let mut total = LotInventory::default();
+            let mut children: BTreeMap<String, LotInventory> = BTreeMap::new();
+            for (name, inventory) in &balances {
+                let child = name.split(':').take(depth).collect::<Vec<_>>().join(":");
+                let child = children.entry(child).or_default();
+                for position in inventory.positions() {
+                    total.add_position(&position.units, position.cost.as_ref());
+                    child.add_position(&position.units, position.cost.as_ref());
+                }
+            }
+            let date = period.last;
+            let convert = |inventory: &LotInventory| {
+                let balance = conversion.apply(inventory, &ledger.prices, Some(date));
+                if invert { inventory::negate(&balance) } else { balance }
+            };
+            IntervalTotal {
+                date: period.begin,
+                balance: convert(&total),
+                account_balances: children.iter().map(|(child, inventory)| (child.clone(), convert(inventory))).collect(),
+            }
+        })
+        .collect()
+}
+
+/// Nested balances of `account` and its descendants, for treemap and sunburst charts
+///
+/// Only the entries in the time filter count, so that income and expenses
+/// are those of the period. `None` if the account has no balances.
+pub(crate) fn hierarchy(ledger: &FavaLedger, account: &str, filters: &Filters, options: &HierarchyOptions) -> Option<SerialisedTreeNode> {
+    let entries = ledger
+        .entries
+        .iter()
+        .filter(|entry| filters.matches_account(entry))
+        .filter(|entry| filters.time.is_none_or(|time| time.contains(entry.get_date())));
+    let tree = Tree::new_filtered(entries, |name| account::is_descendant(name, account));
+    Some(tree.serialise(account)?.chart(options))
+}
+
+/// The prices of every currency pair of the price directives, in the time filter
+///
+/// With `implied` the prices implied by the costs and prices of postings are
+/// included, and their pairs too.
+pub(crate) fn commodity_prices(ledger: &FavaLedger, filters: &Filters, implied: bool) -> Vec<CommodityPrices> {
+    let implied_prices;
+    let prices = if implied {
+        implied_prices = PriceMap::with_implied(&ledger.entries);
+        &implied_prices
+    } else {
+        &ledger.prices
+    };
+    prices
+        .forward_pairs()
+        .map(|(base, quote)| CommodityPrices {
+            base: base.clone(),
+            quote: quote.clone(),
+            prices: prices
+                .get_all_prices(base, quote)
+                .iter()
+                .filter(|(date, _)| filters.time.is_none_or(|time| time.contains(*date)))
+                .map(|&(date, value)| DateAndValue { date, value })
+                .collect(),
+        })
+        .collect()
+}
+
+/// One series per currency to chart
+///
+/// These are the target currency of the conversion, or the operating
+/// currencies, or without any all currencies of the balances.
+pub(crate) fn series(ledger: &FavaLedger, conversion: &Conversion, balances: &[DateAndBalance]) -> Vec<Series> {
+    let currencies: Vec<String> = match conversion {
+        Conversion::Currency(currency) => vec![currency.clone()],
+        _ if !ledger.options.operating_currency.is_empty() => ledger.options.operating_currency.clone(),
+        _ => {
+            let currencies: BTreeSet<&String> = balances.iter().flat_map(|balance| balance.balance.keys()).collect();
+            currencies.into_iter().cloned().collect()
+        }
+    };
+    currencies
+        .into_iter()
+        .map(|currency| Series {
+            values: balances
+                .iter()
+                .map(|balance| DateAndValue {
+                    date: balance.date,
+                    value: balance.balance.get(&currency).copied().unwrap_or_default(),
+                })
+                .collect(),
+            currency,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{MemoryStorage, block_on};
+    use time::macros::date;
+
+    const SOURCE: &str = r#"option "operating_currency" "USD"
+2020-01-01 open Assets:Cash
+2020-01-01 open Assets:Broker
+2020-01-01 open Liabilities:Card
+2020-01-01 open Expenses:Food
+2020-01-01 open Equity:Opening-Balances
+2020-01-05 * "Opening"
+  Assets:Cash  1000 USD
+  Equity:Opening-Balances
+2020-01-20 * "Groceries"
+  Expenses:Food  50 USD
+  Liabilities:Card
+2020-02-10 * "Buy"
+  Assets:Broker  2 STOCK {100 USD}
+  Assets:Cash  -200 USD
+2020-03-01 price STOCK 150 USD
+2020-03-15 * "Groceries"
+  Expenses:Food  30 USD
+  Assets:Cash
+"#;
+
+    fn ledger() -> FavaLedger {
+        block_on(FavaLedger::load(&MemoryStorage::new([("main.beancount", SOURCE)]), "main.beancount"))
+    }
+
+    fn values(series: &[Series]) -> Vec<(Date, f32)> {
+        series[0].values.iter().map(|value| (value.date, value.value)).collect()
+    }
+
+    #[test]
+    fn net_worth_by_month() {
+        let ledger = ledger();
+        let at_cost = net_worth(&ledger, Interval::Month, &Conversion::AtCost, &Filters::default());
+        let series = series(&ledger, &Conversion::AtCost, &at_cost);
+        assert_eq!(series.len(), 1);
+        assert_eq!(series[0].currency, "USD");
+        assert_eq!(
+            values(&series),
+            vec![(date!(2020-01-31), 950.), (date!(2020-02-29), 950.), (date!(2020-03-15), 920.)]
+        );
+
+        // the stock gained 100 USD in March
+        let at_value = net_worth(&ledger, Interval::Month, &Conversion::AtValue, &Filters::default());
+        assert_eq!(at_value[1].balance, CounterInventory::from([("USD".into(), 750.), ("STOCK".into(), 2.)]));
+        assert_eq!(at_value[2].balance, CounterInventory::from([("USD".into(), 1020.)]));
+    }
+
+    #[test]
+    fn filtered() {
+        let ledger = ledger();
+        let filters = Filters::new(Some("2020-02 - 2020-03"), None).unwrap();
+        let balances = account_balance(&ledger, "Assets:Cash", Interval::Month, &Conversion::Units, &filters);
+        let balances: Vec<_> = balances.iter().map(|balance| (balance.date, balance.balance["USD"])).collect();
+        assert_eq!(balances, vec![(date!(2020-02-29), 800.), (date!(2020-03-31), 770.)]);
+
+        // only the transactions with food expenses
+        let filters = Filters::new(None, Some("Expenses:Food")).unwrap();
+        let balances = net_worth(&ledger, Interval::Year, &Conversion::Units, &filters);
+        assert_eq!(balances, vec![DateAndBalance { date: date!(2020-03-15), balance: CounterInventory::from([("USD".into(), -80.)]) }]);
+    }
+
+    #[test]
+    fn end_of_time() {
+        let source = format!("{SOURCE}9999-12-30 * \"Late\"\n  Expenses:Food  1 USD\n  Assets:Cash\n9999-12-31 * \"Last\"\n  Expenses:Food  1 USD\n  Assets:Cash\n");
+        let ledger = block_on(FavaLedger::load(&MemoryStorage::new([("main.beancount", source.as_str())]), "main.beancount"));
+        for interval in [Interval::Year, Interval::Quarter, Interval::Month] {
+            let balances = net_worth(&ledger, interval, &Conversion::Units, &Filters::default());
+            let last = balances.last().unwrap();
+            assert_eq!((last.date, last.balance["USD"]), (date!(9999-12-31), 718.));
+            let totals = interval_totals(&ledger, "Expenses", interval, &Conversion::Units, &Filters::default(), false);
+            assert_eq!(totals.last().unwrap().balance, CounterInventory::from([("USD".into(), 2.)]));
+        }
+    }
+
+    #[test]
+    fn expenses_by_month() {
+        let ledger = ledger();
+        let totals = interval_totals(&ledger, "Expenses", Interval::Month, &Conversion::AtCost, &Filters::default(), false);
+        let dates: Vec<Date> = totals.iter().map(|total| total.date).collect();
+        assert_eq!(dates, vec![date!(2020-01-01), date!(2020-02-01), date!(2020-03-01)]);
+        assert_eq!(totals[0].balance, CounterInventory::from([("USD".into(), 50.)]));
+        assert_eq!(totals[0].account_balances, BTreeMap::from([("Expenses:Food".into(), CounterInventory::from([("USD".into(), 50.)]))]));
+        assert!(totals[1].balance.is_empty() && totals[1].account_balances.is_empty());
+
+        // earlier transactions are left out of the filtered intervals
+        let filters = Filters::new(Some("2020-02 - 2020-03"), None).unwrap();
+        let totals = interval_totals(&ledger, "Expenses", Interval::Month, &Conversion::AtCost, &filters, false);
+        let balances: Vec<_> = totals.iter().map(|total| (total.date, total.balance.get("USD").copied())).collect();
+        assert_eq!(balances, vec![(date!(2020-02-01), None), (date!(2020-03-01), Some(30.))]);
+
+        // all accounts, by root account
+        let totals = interval_totals(&ledger, "", Interval::Year, &Conversion::Units, &Filters::default(), true);
+        assert_eq!(
+            totals[0].account_balances.keys().collect::<Vec<_>>(),
+            vec!["Assets", "Equity", "Expenses", "Liabilities"]
+        );
+        assert_eq!(totals[0].account_balances["Equity"], CounterInventory::from([("USD".into(), 1000.)]));
+        assert_eq!(totals[0].account_balances["Assets"], CounterInventory::from([("USD".into(), -770.), ("STOCK".into(), -2.)]));
+    }
+
+    #[test]
+    fn prices() {
+        let ledger = ledger();
+        let prices = commodity_prices(&ledger, &Filters::default(), false);
+        assert_eq!(
+            prices,
+            vec![CommodityPrices {
+                base: "STOCK".into(),
+                quote: "USD".into(),
+                prices: vec![DateAndValue { date: date!(2020-03-01), value: 150. }],
+            }]
+        );
+
+        // the cost of the purchase implies a price
+        let implied = commodity_prices(&ledger, &Filters::default(), true);
+        let dates: Vec<Date> = implied[0].prices.iter().map(|price| price.date).collect();
+        assert_eq!(dates, vec![date!(2020-02-10), date!(2020-03-01)]);
+        let filters = Filters::new(Some("2020-03"), None).unwrap();
+        assert_eq!(commodity_prices(&ledger, &filters, true)[0].prices.len(), 1);
+    }
+
+    #[test]
+    fn expenses_hierarchy() {
+        let ledger = ledger();
+        let options = HierarchyOptions::default();
+        let expenses = hierarchy(&ledger, "Expenses", &Filters::default(), &options).unwrap();
+        assert_eq!(expenses.balance_children, CounterInventory::from([("USD".into(), 80.)]));
+        assert_eq!(expenses.children[0].account, "Expenses:Food");
+
+        let filters = Filters::new(Some("2020-03"), None).unwrap();
+        let march = hierarchy(&ledger, "Expenses", &filters, &options).unwrap();
+        assert_eq!(march.balance_children, CounterInventory::from([("USD".into(), 30.)]));
+        assert!(hierarchy(&ledger, "Income", &Filters::default(), &options).is_none());
+    }
+}
@@ -0,0 +1,124 @@
+//! The context of an entry: its source and the balances of its accounts around it
+//!
+//! see: https://github.com/beancount/fava/blob/main/src/fava/core/__init__.py (`context`)
+
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use crate::Helpers;
+use crate::beans::abc::{Directive, Entry};
+use crate::beans::funcs::get_entry_accounts;
+use crate::core::FavaLedger;
+use crate::core::file;
+use crate::core::inventory::{APosition, LotInventory};
+use crate::storage::Storage;
+
+/// An entry with its source and the balances of its accounts just before and after it
+#[derive(Debug, Serialize)]
+pub(crate) struct EntryContext<'a> {
+    pub entry: &'a Directive,
+    pub hash: &'a str,
+    /// positions of every account of the entry before it, by account
+    pub balances_before: BTreeMap<&'a str, Vec<APosition>>,
+    /// positions of every account of the entry after it, by account
+    pub balances_after: BTreeMap<&'a str, Vec<APosition>>,
+    /// source text of the entry, `None` if it isn't in a source file
+    pub slice: Option<String>,
+    pub sha256sum: Option<String>,
+    /// errors reported at the line of the entry
+    pub errors: Vec<&'a Helpers>,
+}
+
+/// Add the postings of `entry` to the inventories of their accounts, if there is one
+fn add_postings(balances: &mut BTreeMap<&str, LotInventory>, entry: &Directive) {
+    if let Directive::Transactions(transaction) = entry {
+        for posting in &transaction.postings {
+            if let Some(inventory) = balances.get_mut(posting.account.as_str()) {
+                inventory.add_position(&posting.units, posting.cost.as_ref());
+            }
+        }
+    }
+}
+
+fn positions<'a>(balances: &BTreeMap<&'a str, LotInventory>) -> BTreeMap<&'a str, Vec<APosition>> {
+    balances.iter().map(|(account, inventory)| (*account, inventory.positions().to_vec())).collect()
+}
+
+/// The context of the entry with the hash `entry_hash`
+///
+/// Balances are those of the accounts themselves, without their descendants,
+/// including all entries sorted before the entry.
+pub(crate) async fn context<'a>(storage: &impl Storage, ledger: &'a FavaLedger, entry_hash: &'a str) -> Result<EntryContext<'a>, Helpers> {
+    let entry = ledger.get_entry(entry_hash)?;
+    let mut balances: BTreeMap<&str, LotInventory> = get_entry_accounts(entry).into_iter().map(|account| (account, LotInventory::default())).collect();
+    for earlier in ledger.entries.iter().take_while(|other| !std::ptr::eq(*other, entry)) {
+        add_postings(&mut balances, earlier);
+    }
+    let balances_before = positions(&balances);
+    add_postings(&mut balances, entry);
+
+    let (slice, sha256sum) = match file::get_entry_slice(storage, ledger, entry).await {
+        Ok((slice, sha256sum)) => (Some(slice), Some(sha256sum)),
+        Err(Helpers::NonSourceFileError(_)) => (None, None),
+        Err(error) => return Err(error),
+    };
+    let meta = entry.get_meta();
+    let location = format!("{}:{}: ", meta.filename, meta.lineno);
+    Ok(EntryContext {
+        entry,
+        hash: entry_hash,
+        balances_before,
+        balances_after: positions(&balances),
+        slice,
+        sha256sum,
+        errors: ledger.errors.iter().filter(|error| error.to_string().starts_with(&location)).collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::beans::abc::AAmount;
+    use crate::beans::funcs::hash_entry;
+    use crate::storage::{MemoryStorage, block_on};
+
+    const SOURCE: &str = "2020-01-01 open Assets:Cash
+2020-01-01 open Assets:Broker
+2020-01-02 * \"Deposit\"
+  Assets:Cash  500 USD
+  Equity:Opening
+2020-01-03 * \"Buy\"
+  Assets:Broker  2 STOCK {100 USD}
+  Assets:Cash  -200 USD
+2020-01-04 balance Assets:Cash  100 USD
+";
+
+    #[test]
+    fn balances_around_entry() {
+        let storage = MemoryStorage::new([("main.beancount", SOURCE)]);
+        let ledger = block_on(FavaLedger::load(&storage, "main.beancount"));
+        let position = |number, currency: &str| APosition {
+            units: AAmount(number, currency.into()),
+            cost: None,
+        };
+
+        let buy = ledger.entries.iter().find(|entry| entry.get_meta().lineno == 6).unwrap();
+        let hash = hash_entry(buy);
+        let around = block_on(context(&storage, &ledger, &hash)).unwrap();
+        assert_eq!(around.balances_before["Assets:Cash"], vec![position(500., "USD")]);
+        assert!(around.balances_before["Assets:Broker"].is_empty());
+        assert_eq!(around.balances_after["Assets:Cash"], vec![position(300., "USD")]);
+        assert_eq!(around.balances_after["Assets:Broker"][0].units, AAmount(2., "STOCK".into()));
+        assert_eq!(around.slice.as_deref(), Some("2020-01-03 * \"Buy\"\n  Assets:Broker  2 STOCK {100 USD}\n  Assets:Cash  -200 USD"));
+        assert!(around.errors.is_empty());
+
+        // the failed balance check is reported at its line
+        let balance = ledger.entries.iter().find(|entry| matches!(entry, Directive::Balance(_))).unwrap();
+        let hash = hash_entry(balance);
+        let around = block_on(context(&storage, &ledger, &hash)).unwrap();
+        assert_eq!(around.balances_before, around.balances_after);
+        assert_eq!(around.errors.len(), 1);
+        assert!(block_on(context(&storage, &ledger, "missing")).is_err());
+    }
+}
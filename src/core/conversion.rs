@@ -0,0 +1,142 @@
+//! Commodity conversion helpers
+//!
+//! see: https://github.com/beancount/fava/blob/main/src/fava/core/conversion.py
+
+use crate::Helpers;
+use crate::beans::abc::AAmount;
+use crate::beans::parser::is_currency;
+use crate::beans::prices::PriceMap;
+use crate::core::inventory::{self, APosition, CounterInventory, LotInventory};
+
+/// Market value of a position in its cost currency, or its units if it has no cost or price
+pub(crate) fn get_market_value(position: &APosition, prices: &PriceMap, date: Option<time::Date>) -> AAmount {
+    let units = &position.units;
+    position
+        .cost
+        .as_ref()
+        .and_then(|cost| {
+            let price = prices.get_price(&units.1, &cost.currency, date)?;
+            Some(AAmount(units.0 * price, cost.currency.clone()))
+        })
+        .unwrap_or_else(|| units.clone())
+}
+
+/// Market value of all positions of the inventory
+pub(crate) fn at_value(inventory: &LotInventory, prices: &PriceMap, date: Option<time::Date>) -> CounterInventory {
+    let mut value = CounterInventory::new();
+    for position in inventory.positions() {
+        let amount = get_market_value(position, prices, date);
+        inventory::add_amount(&mut value, &amount.1, amount.0);
+    }
+    value
+}
+
+/// How to convert an inventory to numbers, set with the `conversion` parameter
+#[derive(Debug, Clone, PartialEq, Default)]
+pub(crate) enum Conversion {
+    #[default]
+    AtCost,
+    AtValue,
+    Units,
+    /// the market value, converted to the currency where there is a price
+    Currency(String),
+}
+
+impl std::str::FromStr for Conversion {
+    type Err = Helpers;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "at_cost" => Ok(Self::AtCost),
+            "at_value" => Ok(Self::AtValue),
+            "units" => Ok(Self::Units),
+            currency if is_currency(currency) => Ok(Self::Currency(currency.to_string())),
+            invalid => Err(Helpers::FavaError(format!("Invalid conversion: {invalid}"))),
+        }
+    }
+}
+
+impl Conversion {
+    /// The inventory converted on `date`, with the latest prices if it is `None`
+    pub fn apply(&self, inventory: &LotInventory, prices: &PriceMap, date: Option<time::Date>) -> CounterInventory {
+        match self {
+            Self::AtCost => inventory.at_cost(),
+            Self::AtValue => at_value(inventory, prices, date),
+            Self::Units => inventory.units(),
+            Self::Currency(target) => {
+                let mut converted = CounterInventory::new();
+                for (currency, number) in at_value(inventory, prices, date) {
+                    match prices.get_price(&currency, target, date) {
+                        Some(price) => inventory::add_amount(&mut converted, target, number * price),
+                        None => inventory::add_amount(&mut converted, &currency, number),
+                    }
+                }
+                converted
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::beans::abc::{ACost, Directive, Meta, Price};
+    use time::macros::date;
+
+    fn prices() -> PriceMap {
+        PriceMap::new(&[Directive::Price(Price {
+            date: date!(2020-02-01),
+            currency: "STOCK".into(),
+            amount: AAmount(150., "USD".into()),
+            meta: Meta::default(),
+        })])
+    }
+
+    fn position(cost_currency: &str) -> APosition {
+        APosition {
+            units: AAmount(2., "STOCK".into()),
+            cost: Some(ACost {
+                number: 100.,
+                currency: cost_currency.into(),
+                date: Some(date!(2020-01-01)),
+                label: None,
+            }),
+        }
+    }
+
+    #[test]
+    fn market_value() {
+        let prices = prices();
+        assert_eq!(get_market_value(&position("USD"), &prices, None), AAmount(300., "USD".into()));
+        assert_eq!(get_market_value(&position("USD"), &prices, Some(date!(2020-01-15))), AAmount(2., "STOCK".into()));
+        assert_eq!(get_market_value(&position("EUR"), &prices, None), AAmount(2., "STOCK".into()));
+    }
+
+    #[test]
+    fn conversions() {
+        let mut inventory = LotInventory::default();
+        let position = position("USD");
+        inventory.add_position(&position.units, position.cost.as_ref());
+        inventory.add_position(&AAmount(10., "GBP".into()), None);
+        let prices = prices();
+
+        assert_eq!("EUR".parse(), Ok(Conversion::Currency("EUR".into())));
+        assert!("at_market".parse::<Conversion>().is_err());
+        let at_cost = Conversion::AtCost.apply(&inventory, &prices, None);
+        assert_eq!(at_cost, CounterInventory::from([("USD".into(), 200.), ("GBP".into(), 10.)]));
+        let units = Conversion::Units.apply(&inventory, &prices, None);
+        assert_eq!(units, CounterInventory::from([("STOCK".into(), 2.), ("GBP".into(), 10.)]));
+        // STOCK is worth 300 USD, but there is no price of GBP in USD
+        let converted = Conversion::Currency("USD".into()).apply(&inventory, &prices, None);
+        assert_eq!(converted, CounterInventory::from([("USD".into(), 300.), ("GBP".into(), 10.)]));
+    }
+
+    #[test]
+    fn inventory_value() {
+        let mut inventory = LotInventory::default();
+        let position = position("USD");
+        inventory.add_position(&position.units, position.cost.as_ref());
+        inventory.add_position(&AAmount(-200., "USD".into()), None);
+        assert_eq!(at_value(&inventory, &prices(), None), CounterInventory::from([("USD".into(), 100.)]));
+    }
+}
@@ -0,0 +1,288 @@
+//! Documents: files in the `documents` folders and the entries linking to them
+//!
+//! see: https://github.com/beancount/fava/blob/main/src/fava/core/documents.py
+//! see: https://github.com/beancount/beancount/blob/v2/beancount/ops/documents.py
+
+use std::collections::BTreeSet;
+
+use crate::Helpers;
+use crate::beans::abc::{Directive, Document, Entry, Meta, MetaValue};
+use crate::beans::funcs::get_entry_accounts;
+use crate::beans::load::include_path;
+use crate::beans::options::BeancountOptions;
+use crate::beans::parser::{is_account, parse_date};
+use crate::core::FavaLedger;
+use crate::storage::{Storage, Upload};
+
+/// The ledger's documents folders, as paths from the storage root
+pub(crate) fn document_folders(options: &BeancountOptions, filename: &str) -> Vec<String> {
+    options.documents.iter().map(|folder| include_path(filename, folder)).collect()
+}
+
+/// Path of the document `filename` of `account`, like `documents/Assets/Cash/2020-01-02 receipt.pdf`
+pub(crate) fn filepath_in_document_folder(folder: &str, account: &str, filename: &str) -> Result<String, Helpers> {
+    if filename.is_empty() || filename.starts_with('.') || filename.contains(|c: char| matches!(c, '/' | '\\' | '"') || c.is_control()) {
+        return Err(Helpers::FavaError(format!("Invalid document filename: {filename}")));
+    }
+    if !is_account(account) {
+        return Err(Helpers::FavaError(format!("Invalid account: {account}")));
+    }
+    let mut segments: Vec<&str> = folder.split('/').filter(|segment| !segment.is_empty()).collect();
+    segments.extend(account.split(':'));
+    segments.push(filename);
+    Ok(segments.join("/"))
+}
+
+fn basename(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+/// The document at `path` in `folder`, if it is named `YYYY-MM-DD ...` and in the folder of an open account
+fn find_document(path: &str, folder: &str, accounts: &BTreeSet<&str>) -> Option<Document> {
+    let relative = if folder.is_empty() { path } else { path.strip_prefix(folder)?.strip_prefix('/')? };
+    let (directory, name) = relative.rsplit_once('/')?;
+    let account = directory.replace('/', ":");
+    if !accounts.contains(account.as_str()) || name.chars().count() <= 11 {
+        return None;
+    }
+    let date = parse_date(name.get(..10)?)?;
+    Some(Document {
+        date,
+        account,
+        filename: path.to_string(),
+        tags: BTreeSet::new(),
+        links: BTreeSet::new(),
+        meta: Meta::new(path, 0),
+    })
+}
+
+/// Resolve the paths of `document` entries and add entries for the files in the documents folders
+///
+/// Paths of `document` entries are relative to their file. Files that a
+/// `document` entry of the same account already refers to are skipped.
+pub(crate) fn process_documents<'a>(entries: &mut Vec<Directive>, folders: &[String], paths: impl IntoIterator<Item = &'a str>) {
+    for entry in entries.iter_mut() {
+        if let Directive::Document(document) = entry {
+            document.filename = include_path(&document.meta.filename, &document.filename);
+        }
+    }
+
+    let mut found = Vec::new();
+    {
+        let accounts: BTreeSet<&str> = entries
+            .iter()
+            .filter_map(|entry| match entry {
+                Directive::Open(open) => Some(open.account.as_str()),
+                _ => None,
+            })
+            .collect();
+        let existing: BTreeSet<(&str, &str)> = entries
+            .iter()
+            .filter_map(|entry| match entry {
+                Directive::Document(document) => Some((document.account.as_str(), document.filename.as_str())),
+                _ => None,
+            })
+            .collect();
+        for path in paths {
+            if let Some(document) = folders.iter().find_map(|folder| find_document(path, folder, &accounts))
+                && !existing.contains(&(document.account.as_str(), document.filename.as_str()))
+            {
+                found.push(Directive::Document(document));
+            }
+        }
+    }
+    entries.extend(found);
+}
+
+/// Whether `path` is a document of the ledger, so that it may be downloaded
+pub(crate) fn is_document_file(ledger: &FavaLedger, path: &str) -> bool {
+    let in_folder = document_folders(&ledger.options, &ledger.filename)
+        .iter()
+        .any(|folder| folder.is_empty() || path.strip_prefix(folder.as_str()).is_some_and(|rest| rest.starts_with('/')));
+    in_folder
+        || ledger
+            .entries
+            .iter()
+            .any(|entry| matches!(entry, Directive::Document(document) if document.filename == path))
+}
+
+/// Path of the document that the `key` metadata of the entry links to
+///
+/// The value is either a path relative to the entry's file or the file name
+/// of a document of one of the entry's accounts.
+pub(crate) fn statement_path(ledger: &FavaLedger, entry: &Directive, key: &str) -> Result<String, Helpers> {
+    let not_found = || Helpers::StatementNotFoundError(format!("Statement not found: {key}"));
+    let meta = entry.get_meta();
+    let Some(MetaValue::String(value)) = meta.get(key) else {
+        return Err(not_found());
+    };
+    let full_path = include_path(&meta.filename, value);
+    let accounts = get_entry_accounts(entry);
+    ledger
+        .entries
+        .iter()
+        .find_map(|other| match other {
+            Directive::Document(document)
+                if document.filename == full_path || (accounts.contains(&document.account.as_str()) && basename(&document.filename) == value) =>
+            {
+                Some(document.filename.clone())
+            }
+            _ => None,
+        })
+        .ok_or_else(not_found)
+}
+
+/// Store a document for `account`, named `YYYY-MM-DD filename` unless it already starts with `date`
+///
+/// `folder` has to be one of the `documents` options, the first one is used by
+/// default. Existing files are not overwritten.
+pub(crate) async fn add_document(
+    storage: &impl Storage,
+    ledger: &FavaLedger,
+    folder: Option<&str>,
+    account: &str,
+    date: time::Date,
+    filename: &str,
+    contents: &Upload,
+) -> Result<Document, Helpers> {
+    let folder = match folder {
+        Some(folder) if ledger.options.documents.iter().any(|option| option == folder) => folder,
+        Some(folder) => return Err(Helpers::FavaError(format!("Not a documents folder: {folder}"))),
+        None => ledger
+            .options
+            .documents
+            .first()
+            .ok_or_else(|| Helpers::FavaError("You need to set a documents folder".into()))?,
+    };
+    let prefix = date.to_string();
+    let filename = if filename.starts_with(&prefix) { filename.to_string() } else { format!("{prefix} {filename}") };
+    let path = filepath_in_document_folder(&include_path(&ledger.filename, folder), account, &filename)?;
+    if storage.get(&path).await?.is_some() {
+        return Err(Helpers::FavaError(format!("{path} already exists")));
+    }
+    storage.put_upload(&path, contents).await?;
+
+    Ok(Document {
+        date,
+        account: account.to_string(),
+        filename: path.clone(),
+        tags: BTreeSet::new(),
+        links: BTreeSet::new(),
+        meta: Meta::new(path, 0),
+    })
+}
+
+/// Content type of a document, by its extension
+pub(crate) fn content_type(path: &str) -> &'static str {
+    let extension = basename(path).rsplit_once('.').map(|(_, extension)| extension.to_ascii_lowercase());
+    match extension.as_deref() {
+        Some("pdf") => "application/pdf",
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("svg") => "image/svg+xml",
+        Some("txt" | "beancount") => "text/plain; charset=utf-8",
+        Some("csv") => "text/csv",
+        Some("html" | "htm") => "text/html; charset=utf-8",
+        Some("json") => "application/json",
+        Some("xml") => "application/xml",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Whether browsers may show a document of `content_type` in the page, rather than download it
+///
+/// HTML, SVG and XML can run scripts with the access of a signed-in user.
+pub(crate) fn is_inline(content_type: &str) -> bool {
+    matches!(content_type, "application/pdf" | "image/png" | "image/jpeg" | "image/gif" | "text/plain; charset=utf-8" | "text/csv")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{MemoryStorage, block_on};
+    use time::macros::date;
+
+    const SOURCE: &str = r#"option "documents" "documents"
+2020-01-01 open Assets:Cash
+2020-01-01 open Expenses:Food
+2020-01-02 * "Groceries"
+  statement: "2020-01-02 receipt.pdf"
+  Expenses:Food  20 USD
+  Assets:Cash
+2020-01-03 document Assets:Cash "documents/Assets/Cash/2020-01-03 statement.pdf"
+"#;
+
+    fn storage() -> MemoryStorage {
+        MemoryStorage::new([
+            ("ledger/main.beancount", SOURCE),
+            ("ledger/documents/Expenses/Food/2020-01-02 receipt.pdf", "%PDF"),
+            ("ledger/documents/Assets/Cash/2020-01-03 statement.pdf", "%PDF"),
+            ("ledger/documents/Assets/Cash/notes.txt", ""),
+            ("ledger/documents/Expenses/Unknown/2020-01-02 other.pdf", ""),
+        ])
+    }
+
+    #[test]
+    fn paths() {
+        assert_eq!(
+            filepath_in_document_folder("ledger/documents", "Assets:Cash", "2020-01-02 receipt.pdf").unwrap(),
+            "ledger/documents/Assets/Cash/2020-01-02 receipt.pdf"
+        );
+        assert!(filepath_in_document_folder("documents", "Assets:Cash", "../main.beancount").is_err());
+        assert!(filepath_in_document_folder("documents", "cash", "receipt.pdf").is_err());
+        assert!(filepath_in_document_folder("documents", "Assets:Cash", "receipt\n.pdf").is_err());
+        assert!(filepath_in_document_folder("documents", "Assets:Cash", "\"receipt\".pdf").is_err());
+        assert_eq!(content_type("documents/Assets/2020-01-02 Receipt.PDF"), "application/pdf");
+        assert_eq!(content_type("documents/Assets/README"), "application/octet-stream");
+        assert!(is_inline(content_type("receipt.pdf")));
+        assert!(!is_inline(content_type("receipt.html")));
+        assert!(!is_inline(content_type("receipt.svg")));
+    }
+
+    #[test]
+    fn documents() {
+        let storage = storage();
+        let ledger = block_on(FavaLedger::load(&storage, "ledger/main.beancount"));
+        let documents: Vec<(&str, &str)> = ledger
+            .entries
+            .iter()
+            .filter_map(|entry| match entry {
+                Directive::Document(document) => Some((document.account.as_str(), document.filename.as_str())),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            documents,
+            vec![
+                ("Expenses:Food", "ledger/documents/Expenses/Food/2020-01-02 receipt.pdf"),
+                ("Assets:Cash", "ledger/documents/Assets/Cash/2020-01-03 statement.pdf"),
+            ]
+        );
+
+        assert!(is_document_file(&ledger, "ledger/documents/Assets/Cash/notes.txt"));
+        assert!(!is_document_file(&ledger, "ledger/main.beancount"));
+
+        let transaction = ledger.entries.iter().find(|entry| matches!(entry, Directive::Transactions(_))).unwrap();
+        assert_eq!(statement_path(&ledger, transaction, "statement").unwrap(), documents[0].1);
+        assert!(matches!(statement_path(&ledger, transaction, "missing"), Err(Helpers::StatementNotFoundError(_))));
+    }
+
+    #[test]
+    fn add() {
+        let storage = storage();
+        let ledger = block_on(FavaLedger::load(&storage, "ledger/main.beancount"));
+
+        let document = block_on(add_document(&storage, &ledger, None, "Assets:Cash", date!(2020-02-01), "statement.pdf", &Upload::Bytes(b"%PDF".to_vec()))).unwrap();
+        assert_eq!(document.filename, "ledger/documents/Assets/Cash/2020-02-01 statement.pdf");
+        assert_eq!(block_on(storage.get(&document.filename)).unwrap().unwrap().contents, b"%PDF");
+
+        // the file exists now, and the name already has the date
+        let again = block_on(add_document(&storage, &ledger, None, "Assets:Cash", date!(2020-02-01), "2020-02-01 statement.pdf", &Upload::Bytes(Vec::new())));
+        assert_eq!(again, Err(Helpers::FavaError(format!("{} already exists", document.filename))));
+        assert!(block_on(add_document(&storage, &ledger, Some("other"), "Assets:Cash", date!(2020-02-01), "x.pdf", &Upload::Bytes(Vec::new()))).is_err());
+
+        let ledger = block_on(FavaLedger::load(&storage, "ledger/main.beancount"));
+        assert!(ledger.entries.contains(&Directive::Document(document)));
+    }
+}
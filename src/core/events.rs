@@ -0,0 +1,71 @@
+//! Events by type, with their history
+//!
+//! see: https://github.com/beancount/fava/blob/main/src/fava/core/__init__.py (`events`)
+
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use crate::beans::abc::{Directive, Event};
+
+/// The events of one type, like the city someone lives in
+#[derive(Debug, Serialize)]
+pub(crate) struct EventType<'a> {
+    pub r#type: &'a str,
+    /// the latest value
+    pub latest: &'a Event,
+    /// all events, oldest first
+    pub history: Vec<&'a Event>,
+}
+
+/// The events of every type, sorted by type
+pub(crate) fn event_types(entries: &[Directive]) -> Vec<EventType<'_>> {
+    let mut types: BTreeMap<&str, Vec<&Event>> = BTreeMap::new();
+    for entry in entries {
+        if let Directive::Event(event) = entry {
+            types.entry(&event.r#type).or_default().push(event);
+        }
+    }
+    types
+        .into_iter()
+        .filter_map(|(r#type, mut history)| {
+            // stable, so the last of several events on a day stays the latest
+            history.sort_by_key(|event| event.date);
+            Some(EventType {
+                r#type,
+                latest: history.last().copied()?,
+                history,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::beans::abc::Meta;
+    use time::macros::date;
+
+    fn event(date: time::Date, r#type: &str, description: &str) -> Directive {
+        Directive::Event(Event {
+            date,
+            r#type: r#type.into(),
+            description: description.into(),
+            meta: Meta::default(),
+        })
+    }
+
+    #[test]
+    fn by_type() {
+        let entries = [
+            event(date!(2021-05-01), "location", "Berlin"),
+            event(date!(2019-01-01), "location", "Paris"),
+            event(date!(2020-01-01), "employer", "Acme"),
+        ];
+        let types = event_types(&entries);
+        assert_eq!(types.iter().map(|event_type| event_type.r#type).collect::<Vec<_>>(), vec!["employer", "location"]);
+        assert_eq!(types[1].latest.description, "Berlin");
+        assert_eq!(types[1].history.iter().map(|event| event.description.as_str()).collect::<Vec<_>>(), vec!["Paris", "Berlin"]);
+        assert!(event_types(&[]).is_empty());
+    }
+}
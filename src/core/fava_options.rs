@@ -0,0 +1,91 @@
+//! Fava's options, set with `custom "fava-option"` entries
+//!
+//! see: https://github.com/beancount/fava/blob/main/src/fava/core/fava_options.py
+
+use regex::Regex;
+
+use crate::Helpers;
+use crate::beans::abc::{Directive, MetaValue};
+use crate::beans::load::entry_error;
+use crate::util::date::FiscalYearEnd;
+
+/// Where to insert new entries with an account matching `re`: before this option
+#[derive(Debug, Clone)]
+pub(crate) struct InsertEntryOption {
+    pub date: time::Date,
+    pub re: Regex,
+    pub filename: String,
+    pub lineno: usize,
+}
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct FavaOptions {
+    pub fiscal_year_end: FiscalYearEnd,
+    pub insert_entry: Vec<InsertEntryOption>,
+}
+
+impl FavaOptions {
+    /// The options set by `entries`, and the errors of invalid ones
+    pub fn new(entries: &[Directive]) -> (Self, Vec<Helpers>) {
+        let mut options = Self::default();
+        let mut errors = Vec::new();
+        for entry in entries {
+            let Directive::Custom(custom) = entry else {
+                continue;
+            };
+            if custom.r#type != "fava-option" {
+                continue;
+            }
+            let [MetaValue::String(key), MetaValue::String(value)] = custom.values.as_slice() else {
+                errors.push(entry_error(&custom.meta, "Invalid Fava option"));
+                continue;
+            };
+            let result = match key.as_str() {
+                "fiscal-year-end" => value.parse().map(|fye| options.fiscal_year_end = fye),
+                "insert-entry" => Regex::new(value)
+                    .map(|re| {
+                        options.insert_entry.push(InsertEntryOption {
+                            date: custom.date,
+                            re,
+                            filename: custom.meta.filename.clone(),
+                            lineno: custom.meta.lineno,
+                        })
+                    })
+                    .map_err(|error| Helpers::FavaError(format!("Invalid insert-entry regex: {error}"))),
+                // options of Fava's frontend are not used here
+                _ => Ok(()),
+            };
+            if let Err(error) = result {
+                errors.push(entry_error(&custom.meta, error));
+            }
+        }
+        (options, errors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::beans::parser::parse_string;
+
+    #[test]
+    fn options() {
+        let parsed = parse_string(
+            r#"2020-01-01 custom "fava-option" "fiscal-year-end" "03-31"
+2020-01-01 custom "fava-option" "insert-entry" "Expenses:.*"
+2020-01-01 custom "fava-option" "insert-entry" "("
+2020-01-01 custom "fava-option" "fiscal-year-end" "02-30"
+2020-01-01 custom "fava-option" "collapse-pattern" "Assets:.*"
+2020-01-01 custom "fava-option" 42
+"#,
+            "main.beancount",
+        );
+        let (options, errors) = FavaOptions::new(&parsed.entries);
+        assert_eq!(options.fiscal_year_end, "03-31".parse().unwrap());
+        assert_eq!(options.insert_entry.len(), 1);
+        assert_eq!(options.insert_entry[0].lineno, 2);
+        assert!(options.insert_entry[0].re.is_match("Expenses:Food"));
+        assert_eq!(errors.len(), 3);
+        assert_eq!(errors[1], Helpers::BeancountError("main.beancount:4: Invalid fiscal year end: 02-30".into()));
+    }
+}
@@ -0,0 +1,348 @@
+//! Reading and writing the source files of a ledger
+//!
+//! see: https://github.com/beancount/fava/blob/main/src/fava/core/file.py
+
+use std::ops::Range;
+
+use sha2::{Digest, Sha256};
+
+use crate::Helpers;
+use crate::beans::abc::{Directive, Entry, Meta, MetaValue};
+use crate::beans::funcs::{get_entry_accounts, hex};
+use crate::beans::parser::parse_string;
+use crate::beans::str::{self, Alignment};
+use crate::core::FavaLedger;
+use crate::core::fava_options::InsertEntryOption;
+use crate::storage::Storage;
+
+/// Hex SHA-256 of a source, to detect concurrent changes
+pub(crate) fn sha256_str(source: &str) -> String {
+    hex(&Sha256::digest(source))
+}
+
+/// Check that `path` is one of the ledger's files
+fn check_source_file(ledger: &FavaLedger, path: &str) -> Result<(), Helpers> {
+    if ledger.sources.contains_key(path) {
+        Ok(())
+    } else {
+        Err(Helpers::NonSourceFileError(format!("Not a source file of the ledger: {path}")))
+    }
+}
+
+/// The current source of one of the ledger's files and its ETag
+async fn read_source(storage: &impl Storage, ledger: &FavaLedger, path: &str) -> Result<(String, String), Helpers> {
+    check_source_file(ledger, path)?;
+    let file = storage.get(path).await?.ok_or_else(|| Helpers::FavaError(format!("File not found: {path}")))?;
+    let etag = file.etag.clone();
+    Ok((file.text()?, etag))
+}
+
+/// Write a source read with `etag`, unless the file has been written since
+async fn write_source(storage: &impl Storage, path: &str, source: &str, etag: &str) -> Result<(), Helpers> {
+    match storage.put_if_match(path, source.as_bytes(), etag).await? {
+        Some(_) => Ok(()),
+        None => Err(Helpers::ExternallyChangedError(format!("The file changed externally: {path}"))),
+    }
+}
+
+/// The current source of a file and its SHA-256
+pub(crate) async fn get_source(storage: &impl Storage, ledger: &FavaLedger, path: &str) -> Result<(String, String), Helpers> {
+    let (source, _) = read_source(storage, ledger, path).await?;
+    let sha256sum = sha256_str(&source);
+    Ok((source, sha256sum))
+}
+
+/// Write the source of a file, unless it changed since it was read with `sha256sum`
+///
+/// Returns the SHA-256 of the new source.
+pub(crate) async fn set_source(storage: &impl Storage, ledger: &FavaLedger, path: &str, source: &str, sha256sum: &str) -> Result<String, Helpers> {
+    let (current, etag) = read_source(storage, ledger, path).await?;
+    if sha256_str(&current) != sha256sum {
+        return Err(Helpers::ExternallyChangedError(format!("The file changed externally: {path}")));
+    }
+    write_source(storage, path, source, &etag).await?;
+    Ok(sha256_str(source))
+}
+
+/// Align the amounts of a file in place, unless it changed since it was read with `sha256sum`
+///
+/// Returns the SHA-256 of the formatted source.
+pub(crate) async fn format_source(storage: &impl Storage, ledger: &FavaLedger, path: &str, sha256sum: &str) -> Result<String, Helpers> {
+    let (source, _) = get_source(storage, ledger, path).await?;
+    set_source(storage, ledger, path, &str::align(&source), sha256sum).await
+}
+
+/// Indices of the lines of the entry starting on line `lineno`
+///
+/// The entry spans its first line and all indented lines that follow it, up to
+/// the first blank or unindented line.
+fn entry_lines(lines: &[&str], lineno: usize) -> Range<usize> {
+    let start = lineno.saturating_sub(1).min(lines.len());
+    let length = lines
+        .iter()
+        .skip(start + 1)
+        .take_while(|line| line.starts_with(char::is_whitespace) && !line.trim().is_empty())
+        .count();
+    start..(start + 1 + length).min(lines.len())
+}
+
+/// The source text of the entry and its SHA-256
+pub(crate) async fn get_entry_slice(storage: &impl Storage, ledger: &FavaLedger, entry: &Directive) -> Result<(String, String), Helpers> {
+    let meta = entry.get_meta();
+    let (source, _) = get_source(storage, ledger, &meta.filename).await?;
+    let lines: Vec<&str> = source.split('\n').collect();
+    let slice = lines[entry_lines(&lines, meta.lineno)].join("\n");
+    let sha256sum = sha256_str(&slice);
+    Ok((slice, sha256sum))
+}
+
+/// Replace the source text of the entry, unless it changed since it was read with `sha256sum`
+///
+/// The rest of the file is kept as is. Returns the SHA-256 of the new slice.
+pub(crate) async fn save_entry_slice(
+    storage: &impl Storage,
+    ledger: &FavaLedger,
+    entry: &Directive,
+    source_slice: &str,
+    sha256sum: &str,
+) -> Result<String, Helpers> {
+    let meta = entry.get_meta();
+    let (source, etag) = read_source(storage, ledger, &meta.filename).await?;
+    let mut lines: Vec<&str> = source.split('\n').collect();
+    let range = entry_lines(&lines, meta.lineno);
+    if sha256_str(&lines[range.clone()].join("\n")) != sha256sum {
+        return Err(Helpers::ExternallyChangedError(format!("The entry changed externally: {}:{}", meta.filename, meta.lineno)));
+    }
+    lines.splice(range, source_slice.split('\n'));
+    write_source(storage, &meta.filename, &lines.join("\n"), &etag).await?;
+    Ok(sha256_str(source_slice))
+}
+
+/// `basekey`, or the first of `basekey-2`, `basekey-3`, ... that the entry doesn't have yet
+fn next_key(basekey: &str, meta: &Meta) -> String {
+    if meta.get(basekey).is_none() {
+        return basekey.to_string();
+    }
+    (2..)
+        .map(|index| format!("{basekey}-{index}"))
+        .find(|key| meta.get(key).is_none())
+        .expect("an entry has finitely many keys")
+}
+
+/// Add a metadata line below the first line of the entry, returning the key used
+///
+/// If the entry already has `basekey`, a numbered key is used instead.
+pub(crate) async fn insert_metadata(storage: &impl Storage, ledger: &FavaLedger, entry: &Directive, basekey: &str, value: &MetaValue) -> Result<String, Helpers> {
+    let meta = entry.get_meta();
+    let key = next_key(basekey, meta);
+    let (source, etag) = read_source(storage, ledger, &meta.filename).await?;
+    let line = str::meta_line(&key, value, "  ");
+    let mut lines: Vec<&str> = source.split('\n').collect();
+    let index = meta.lineno.min(lines.len());
+    lines.insert(index, line.trim_end());
+    write_source(storage, &meta.filename, &lines.join("\n"), &etag).await?;
+    Ok(key)
+}
+
+/// The file and line to insert `entry` before, `None` for the end of the default file
+///
+/// This is the latest `insert-entry` option before the entry whose regex matches
+/// one of its accounts, preferring the last postings of transactions.
+fn find_insert_position(entry: &Directive, options: &[InsertEntryOption], default_filename: &str) -> (String, Option<usize>) {
+    let mut options: Vec<&InsertEntryOption> = options.iter().filter(|option| option.date < entry.get_date()).collect();
+    options.sort_by_key(|option| std::cmp::Reverse(option.date));
+
+    for account in get_entry_accounts(entry).into_iter().rev() {
+        // like Python's `re.match`, the regex has to match at the start
+        if let Some(option) = options.iter().find(|option| option.re.find(account).is_some_and(|found| found.start() == 0)) {
+            return (option.filename.clone(), Some(option.lineno));
+        }
+    }
+    (default_filename.to_string(), None)
+}
+
+/// Insert the entry into the ledger's source, returning the file and line it starts on
+pub(crate) async fn insert_entry(storage: &impl Storage, ledger: &FavaLedger, entry: &Directive) -> Result<(String, usize), Helpers> {
+    let (filename, lineno) = find_insert_position(entry, &ledger.fava_options.insert_entry, &ledger.filename);
+    let (source, etag) = read_source(storage, ledger, &filename).await?;
+    // amounts line up with the rest of the file
+    let text = str::to_string(entry);
+    let text = Alignment::of(&source).max(Alignment::of(&text)).apply(&text);
+    // nothing but the entry is written
+    let parsed = parse_string(&text, &filename);
+    if let Some(error) = parsed.errors.into_iter().next() {
+        return Err(error);
+    }
+    if parsed.entries.len() != 1 {
+        return Err(Helpers::FavaError(format!("Not a single entry: {}", text.trim_end())));
+    }
+
+    let (source, lineno) = match lineno {
+        Some(lineno) => {
+            let mut lines: Vec<&str> = source.split('\n').collect();
+            let index = lineno.saturating_sub(1).min(lines.len());
+            // the entry and a blank line go before the option
+            lines.splice(index..index, text.trim_end().split('\n').chain([""]));
+            (lines.join("\n"), index + 1)
+        }
+        None => {
+            let existing = source.trim_end_matches('\n');
+            if existing.is_empty() {
+                (text, 1)
+            } else {
+                (format!("{existing}\n\n{text}"), existing.split('\n').count() + 2)
+            }
+        }
+    };
+    write_source(storage, &filename, &source, &etag).await?;
+    Ok((filename, lineno))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::beans::abc::{AAmount, Balance, Note};
+    use crate::storage::{MemoryStorage, block_on};
+    use time::macros::date;
+
+    #[test]
+    fn sources() {
+        let storage = MemoryStorage::new([("main.beancount", "; main\n"), ("other.beancount", "; other\n")]);
+        let ledger = block_on(FavaLedger::load(&storage, "main.beancount"));
+
+        let (source, sha256sum) = block_on(get_source(&storage, &ledger, "main.beancount")).unwrap();
+        assert_eq!(source, "; main\n");
+        assert_eq!(sha256sum, sha256_str("; main\n"));
+        assert!(matches!(
+            block_on(get_source(&storage, &ledger, "other.beancount")),
+            Err(Helpers::NonSourceFileError(_))
+        ));
+
+        let new_sha256sum = block_on(set_source(&storage, &ledger, "main.beancount", "; changed\n", &sha256sum)).unwrap();
+        assert_eq!(new_sha256sum, sha256_str("; changed\n"));
+        assert_eq!(block_on(storage.read("main.beancount")).unwrap(), "; changed\n");
+
+        // a second save based on the old version is rejected
+        assert!(matches!(
+            block_on(set_source(&storage, &ledger, "main.beancount", "; stale\n", &sha256sum)),
+            Err(Helpers::ExternallyChangedError(_))
+        ));
+        assert_eq!(block_on(storage.read("main.beancount")).unwrap(), "; changed\n");
+    }
+
+    #[test]
+    fn format() {
+        let source = "; cash\n2020-01-02 * \"Groceries\"\n  Expenses:Food  20.5 USD\n  Assets:Cash  -20.5 USD ; paid\n";
+        let storage = MemoryStorage::new([("main.beancount", source)]);
+        let ledger = block_on(FavaLedger::load(&storage, "main.beancount"));
+
+        let sha256sum = block_on(format_source(&storage, &ledger, "main.beancount", &sha256_str(source))).unwrap();
+        let formatted = "; cash\n2020-01-02 * \"Groceries\"\n  Expenses:Food   20.5 USD\n  Assets:Cash    -20.5 USD ; paid\n";
+        assert_eq!(block_on(storage.read("main.beancount")).unwrap(), formatted);
+        assert_eq!(sha256sum, sha256_str(formatted));
+        assert!(matches!(
+            block_on(format_source(&storage, &ledger, "main.beancount", &sha256_str(source))),
+            Err(Helpers::ExternallyChangedError(_))
+        ));
+    }
+
+    const SOURCE: &str = "2020-01-01 open Assets:Cash\n2020-01-01 open Expenses:Food\n\n; groceries\n2020-01-02 * \"Groceries\"\n  Expenses:Food  20 USD\n  Assets:Cash\n\n2020-01-03 close Expenses:Food\n";
+
+    #[test]
+    fn lines() {
+        let lines: Vec<&str> = SOURCE.split('\n').collect();
+        assert_eq!(entry_lines(&lines, 1), 0..1);
+        assert_eq!(entry_lines(&lines, 5), 4..7);
+        assert_eq!(entry_lines(&lines, 9), 8..9);
+    }
+
+    #[test]
+    fn entry_slices() {
+        let storage = MemoryStorage::new([("main.beancount", SOURCE)]);
+        let ledger = block_on(FavaLedger::load(&storage, "main.beancount"));
+        let entry = ledger.entries.iter().find(|entry| matches!(entry, Directive::Transactions(_))).unwrap();
+
+        let (slice, sha256sum) = block_on(get_entry_slice(&storage, &ledger, entry)).unwrap();
+        assert_eq!(slice, "2020-01-02 * \"Groceries\"\n  Expenses:Food  20 USD\n  Assets:Cash");
+
+        let changed = "2020-01-02 * \"Restaurant\"\n  Expenses:Food  25 USD\n  Assets:Cash";
+        assert_eq!(block_on(save_entry_slice(&storage, &ledger, entry, changed, &sha256sum)).unwrap(), sha256_str(changed));
+        assert_eq!(block_on(storage.read("main.beancount")).unwrap(), SOURCE.replace(&slice, changed));
+
+        // the ledger is stale, so the entry's slice no longer matches
+        assert!(matches!(
+            block_on(save_entry_slice(&storage, &ledger, entry, &slice, &sha256sum)),
+            Err(Helpers::ExternallyChangedError(_))
+        ));
+    }
+
+    #[test]
+    fn metadata() {
+        let storage = MemoryStorage::new([("main.beancount", SOURCE)]);
+        let ledger = block_on(FavaLedger::load(&storage, "main.beancount"));
+        let entry = ledger.entries.iter().find(|entry| matches!(entry, Directive::Transactions(_))).unwrap();
+
+        let value = MetaValue::String("receipt.pdf".into());
+        assert_eq!(block_on(insert_metadata(&storage, &ledger, entry, "document", &value)).unwrap(), "document");
+        let ledger = block_on(FavaLedger::load(&storage, "main.beancount"));
+        let entry = ledger.entries.iter().find(|entry| matches!(entry, Directive::Transactions(_))).unwrap();
+        assert_eq!(entry.get_meta().get("document"), Some(&value));
+
+        assert_eq!(block_on(insert_metadata(&storage, &ledger, entry, "document", &value)).unwrap(), "document-2");
+        assert!(
+            block_on(storage.read("main.beancount"))
+                .unwrap()
+                .contains("2020-01-02 * \"Groceries\"\n  document-2: \"receipt.pdf\"\n  document: \"receipt.pdf\"\n  Expenses:Food")
+        );
+    }
+
+    fn note(date: time::Date, account: &str) -> Directive {
+        Directive::Note(Note {
+            date,
+            account: account.into(),
+            comment: "Added".into(),
+            meta: Meta::default(),
+        })
+    }
+
+    #[test]
+    fn insert() {
+        let storage = MemoryStorage::new([
+            ("main.beancount", "include \"expenses.beancount\"\n2020-01-01 open Assets:Cash\n"),
+            (
+                "expenses.beancount",
+                "2020-01-01 open Expenses:Food\n\n2020-01-01 custom \"fava-option\" \"insert-entry\" \"Expenses\"\n; end\n",
+            ),
+        ]);
+        let ledger = block_on(FavaLedger::load(&storage, "main.beancount"));
+
+        let position = block_on(insert_entry(&storage, &ledger, &note(date!(2020-02-01), "Expenses:Food"))).unwrap();
+        assert_eq!(position, ("expenses.beancount".into(), 3));
+        assert_eq!(
+            block_on(storage.read("expenses.beancount")).unwrap(),
+            "2020-01-01 open Expenses:Food\n\n2020-02-01 note Expenses:Food \"Added\"\n\n2020-01-01 custom \"fava-option\" \"insert-entry\" \"Expenses\"\n; end\n"
+        );
+
+        // options only apply to later entries, and match at the start of an account
+        let earlier = block_on(insert_entry(&storage, &ledger, &note(date!(2020-01-01), "Expenses:Food"))).unwrap();
+        assert_eq!(earlier, ("main.beancount".into(), 4));
+        let unmatched = note(date!(2020-02-01), "Assets:Expenses");
+        assert_eq!(find_insert_position(&unmatched, &ledger.fava_options.insert_entry, "main.beancount"), ("main.beancount".into(), None));
+        assert_eq!(
+            block_on(storage.read("main.beancount")).unwrap(),
+            "include \"expenses.beancount\"\n2020-01-01 open Assets:Cash\n\n2020-01-01 note Expenses:Food \"Added\"\n"
+        );
+
+        let balance = Directive::Balance(Balance::new(date!(2020-03-01), "Assets:Cash", AAmount(1., "USD".into())));
+        assert_eq!(block_on(insert_entry(&storage, &ledger, &balance)).unwrap(), ("main.beancount".into(), 6));
+
+        // an entry that doesn't read back as itself is not written
+        let source = block_on(storage.read("main.beancount")).unwrap();
+        let injected = Directive::Balance(Balance::new(date!(2020-03-02), "Assets:Cash", AAmount(1., "USD\n2020-03-03 close Assets:Cash".into())));
+        assert!(block_on(insert_entry(&storage, &ledger, &injected)).is_err());
+        let Directive::Note(mut broken) = note(date!(2020-03-02), "Assets:Cash") else { unreachable!() };
+        broken.comment = "line\nbreak".into();
+        assert!(block_on(insert_entry(&storage, &ledger, &Directive::Note(broken))).is_err());
+        assert_eq!(block_on(storage.read("main.beancount")).unwrap(), source);
+    }
+}
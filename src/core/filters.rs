@@ -0,0 +1,136 @@
+//! Filters of the entries by time and account, set with the `time` and `account` parameters
+//!
+//! see: https://github.com/beancount/fava/blob/main/src/fava/core/filters.py
+
+use regex::Regex;
+use time::{Date, Month};
+
+use crate::Helpers;
+use crate::beans::abc::Directive;
+use crate::beans::account;
+use crate::beans::funcs::get_entry_accounts;
+use crate::util::date::DateRange;
+
+/// The dates of a year `2020`, quarter `2020-Q1`, month `2020-03` or day `2020-03-15`
+fn parse_period(value: &str) -> Option<DateRange> {
+    let value = value.trim();
+    let parts: Vec<&str> = value.split('-').collect();
+    let year: i32 = parts.first()?.parse().ok()?;
+    let date = |year, month: u8, day| Date::from_calendar_date(year, Month::try_from(month).ok()?, day).ok();
+    let (begin, end) = match parts.as_slice() {
+        [_] => (date(year, 1, 1)?, date(year + 1, 1, 1)?),
+        [_, quarter] if quarter.starts_with(['Q', 'q']) => {
+            let quarter: u8 = quarter[1..].parse().ok().filter(|quarter| (1..=4).contains(quarter))?;
+            let begin = date(year, quarter * 3 - 2, 1)?;
+            let end = if quarter == 4 { date(year + 1, 1, 1)? } else { date(year, quarter * 3 + 1, 1)? };
+            (begin, end)
+        }
+        [_, month] => {
+            let begin = date(year, month.parse().ok()?, 1)?;
+            let end = if begin.month() == Month::December { date(year + 1, 1, 1)? } else { date(year, begin.month() as u8 + 1, 1)? };
+            (begin, end)
+        }
+        [_, month, day] => {
+            let begin = date(year, month.parse().ok()?, day.parse().ok()?)?;
+            (begin, begin.next_day()?)
+        }
+        _ => return None,
+    };
+    Some(DateRange { begin, end })
+}
+
+/// Parse a `time` filter: a period, or a range of periods like `2020-01 - 2020-06` or `2019 to 2020`
+pub(crate) fn parse_time_filter(value: &str) -> Result<DateRange, Helpers> {
+    let invalid = || Helpers::FavaError(format!("Failed to parse date: {value}"));
+    let (first, last) = match value.split_once(" - ").or_else(|| value.split_once(" to ")) {
+        Some((first, last)) => (parse_period(first).ok_or_else(invalid)?, parse_period(last).ok_or_else(invalid)?),
+        None => {
+            let period = parse_period(value).ok_or_else(invalid)?;
+            (period, period)
+        }
+    };
+    if last.end <= first.begin {
+        return Err(invalid());
+    }
+    Ok(DateRange {
+        begin: first.begin,
+        end: last.end,
+    })
+}
+
+/// An `account` filter: the account and its descendants, or a regex matching from the start
+#[derive(Debug, Clone)]
+pub(crate) struct AccountFilter {
+    name: String,
+    re: Regex,
+}
+
+impl AccountFilter {
+    pub fn new(value: &str) -> Result<Self, Helpers> {
+        let re = Regex::new(&format!("^(?:{value})")).map_err(|error| Helpers::FavaError(format!("Invalid account filter: {error}")))?;
+        Ok(Self { name: value.to_string(), re })
+    }
+
+    pub fn matches(&self, name: &str) -> bool {
+        account::is_descendant(name, &self.name) || self.re.is_match(name)
+    }
+}
+
+/// The filters of a request
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Filters {
+    pub time: Option<DateRange>,
+    pub account: Option<AccountFilter>,
+}
+
+impl Filters {
+    /// Filters from the `time` and `account` parameters, empty ones are ignored
+    pub fn new(time: Option<&str>, account: Option<&str>) -> Result<Self, Helpers> {
+        Ok(Self {
+            time: time.filter(|time| !time.is_empty()).map(parse_time_filter).transpose()?,
+            account: account.filter(|account| !account.is_empty()).map(AccountFilter::new).transpose()?,
+        })
+    }
+
+    /// Whether the entry has an account that matches the account filter
+    ///
+    /// The time filter is not applied, as balances depend on earlier entries.
+    pub fn matches_account(&self, entry: &Directive) -> bool {
+        self.account
+            .as_ref()
+            .is_none_or(|filter| get_entry_accounts(entry).into_iter().any(|name| filter.matches(name)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::macros::date;
+
+    #[test]
+    fn time() {
+        let range = |begin, end| Ok(DateRange { begin, end });
+        assert_eq!(parse_time_filter("2020"), range(date!(2020-01-01), date!(2021-01-01)));
+        assert_eq!(parse_time_filter("2020-Q4"), range(date!(2020-10-01), date!(2021-01-01)));
+        assert_eq!(parse_time_filter("2020-02"), range(date!(2020-02-01), date!(2020-03-01)));
+        assert_eq!(parse_time_filter("2020-12-31"), range(date!(2020-12-31), date!(2021-01-01)));
+        assert_eq!(parse_time_filter("2019-11 - 2020-Q1"), range(date!(2019-11-01), date!(2020-04-01)));
+        assert_eq!(parse_time_filter("2019 to 2020"), range(date!(2019-01-01), date!(2021-01-01)));
+        assert!(parse_time_filter("2020-13").is_err());
+        assert!(parse_time_filter("2020 - 2019").is_err());
+        assert!(parse_time_filter("last year").is_err());
+    }
+
+    #[test]
+    fn accounts() {
+        let filter = AccountFilter::new("Assets:Cash").unwrap();
+        assert!(filter.matches("Assets:Cash:Wallet"));
+        assert!(!filter.matches("Expenses:Assets:Cash"));
+        let filter = AccountFilter::new(".*:Food").unwrap();
+        assert!(filter.matches("Expenses:Food:Restaurant"));
+        assert!(AccountFilter::new("(").is_err());
+
+        let filters = Filters::new(Some(""), None).unwrap();
+        assert!(filters.time.is_none() && filters.account.is_none());
+    }
+}
@@ -0,0 +1,246 @@
+//! Holdings: positions held at cost, valued at the latest prices
+//!
+//! see: https://beancount.github.io/docs/api_reference/beancount.ops.html#beancount.ops.holdings
+
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use crate::Helpers;
+use crate::beans::abc::{Directive, Entry};
+use crate::beans::prices::PriceMap;
+use crate::core::inventory::LotInventory;
+
+/// Ways to aggregate holdings
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum GroupBy {
+    Account,
+    Currency,
+    CostCurrency,
+}
+
+impl std::str::FromStr for GroupBy {
+    type Err = Helpers;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "account" => Ok(Self::Account),
+            "currency" => Ok(Self::Currency),
+            "cost_currency" => Ok(Self::CostCurrency),
+            invalid_str => Err(Helpers::FavaError(format!("Invalid holdings grouping: {invalid_str}"))),
+        }
+    }
+}
+
+/// A position held at cost
+///
+/// Fields that differ between the merged positions of an aggregated holding are `None`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct Holding {
+    pub account: Option<String>,
+    pub number: Option<f32>,
+    pub currency: Option<String>,
+    /// cost per unit
+    pub cost_number: Option<f32>,
+    pub cost_currency: String,
+    /// total cost basis
+    pub book_value: f32,
+    pub price_number: Option<f32>,
+    pub price_date: Option<time::Date>,
+    pub market_value: Option<f32>,
+    pub unrealized_gain: Option<f32>,
+    pub unrealized_gain_pct: Option<f32>,
+}
+
+impl Holding {
+    /// Fill in the unrealized gain from book and market value
+    fn with_gain(mut self) -> Self {
+        self.unrealized_gain = self.market_value.map(|market_value| market_value - self.book_value);
+        self.unrealized_gain_pct = self
+            .unrealized_gain
+            .filter(|_| self.book_value != 0.)
+            .map(|gain| gain / self.book_value.abs() * 100.);
+        self
+    }
+
+    /// Merge two holdings with the same cost currency
+    fn merge(self, other: &Holding) -> Self {
+        let same = |a: &Option<String>, b: &Option<String>| if a == b { a.clone() } else { None };
+        let currency = same(&self.currency, &other.currency);
+        let number = currency.as_ref().and_then(|_| Some(self.number? + other.number?));
+        let book_value = self.book_value + other.book_value;
+        let (price_number, price_date) = if currency.is_some() && (self.price_number, self.price_date) == (other.price_number, other.price_date) {
+            (self.price_number, self.price_date)
+        } else {
+            (None, None)
+        };
+
+        Holding {
+            account: same(&self.account, &other.account),
+            number,
+            cost_number: number.filter(|number| *number != 0.).map(|number| book_value / number),
+            currency,
+            cost_currency: self.cost_currency,
+            book_value,
+            price_number,
+            price_date,
+            market_value: self.market_value.zip(other.market_value).map(|(a, b)| a + b),
+            unrealized_gain: None,
+            unrealized_gain_pct: None,
+        }
+        .with_gain()
+    }
+}
+
+/// All positions held at cost on `date` (or at the end of the ledger), by account and currency
+pub(crate) fn get_holdings(entries: &[Directive], prices: &PriceMap, date: Option<time::Date>) -> Vec<Holding> {
+    let mut accounts: BTreeMap<&str, LotInventory> = BTreeMap::new();
+    for entry in entries.iter().filter(|entry| date.is_none_or(|date| entry.get_date() <= date)) {
+        if let Directive::Transactions(transaction) = entry {
+            for posting in &transaction.postings {
+                accounts.entry(&posting.account).or_default().add_position(&posting.units, posting.cost.as_ref());
+            }
+        }
+    }
+
+    let mut holdings = Vec::new();
+    for (account, inventory) in accounts {
+        for position in inventory.positions() {
+            let Some(cost) = &position.cost else {
+                continue;
+            };
+            let price = prices.get_price_point(&position.units.1, &cost.currency, date);
+            holdings.push(
+                Holding {
+                    account: Some(account.to_string()),
+                    number: Some(position.units.0),
+                    currency: Some(position.units.1.clone()),
+                    cost_number: Some(cost.number),
+                    cost_currency: cost.currency.clone(),
+                    book_value: position.units.0 * cost.number,
+                    price_number: price.map(|(_, number)| number),
+                    price_date: price.map(|(date, _)| date),
+                    market_value: price.map(|(_, number)| position.units.0 * number),
+                    unrealized_gain: None,
+                    unrealized_gain_pct: None,
+                }
+                .with_gain(),
+            );
+        }
+    }
+    holdings
+}
+
+/// Merge the holdings with the same `group_by` key and cost currency
+pub(crate) fn aggregate_holdings(holdings: &[Holding], group_by: GroupBy) -> Vec<Holding> {
+    let mut groups: BTreeMap<(Option<String>, String), Holding> = BTreeMap::new();
+    for holding in holdings {
+        let key = match group_by {
+            GroupBy::Account => holding.account.clone(),
+            GroupBy::Currency => holding.currency.clone(),
+            GroupBy::CostCurrency => None,
+        };
+        let key = (key, holding.cost_currency.clone());
+        let merged = match groups.remove(&key) {
+            Some(group) => group.merge(holding),
+            None => holding.clone(),
+        };
+        groups.insert(key, merged);
+    }
+    groups.into_values().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::beans::abc::{AAmount, ACost, Meta, Posting, Price, Transaction};
+    use crate::beans::flags::Flags;
+    use std::str::FromStr;
+    use time::macros::date;
+
+    fn buy(date: time::Date, account: &str, number: f32, currency: &str, cost: f32) -> Directive {
+        let mut posting = Posting::new(account, AAmount(number, currency.into()));
+        posting.cost = Some(ACost {
+            number: cost,
+            currency: "USD".into(),
+            date: Some(date),
+            label: None,
+        });
+        Directive::Transactions(Transaction::new(
+            date,
+            Flags::Okay,
+            "Buy",
+            vec![posting, Posting::new("Assets:Cash", AAmount(-number * cost, "USD".into()))],
+        ))
+    }
+
+    fn price(date: time::Date, currency: &str, number: f32) -> Directive {
+        Directive::Price(Price {
+            date,
+            currency: currency.into(),
+            amount: AAmount(number, "USD".into()),
+            meta: Meta::default(),
+        })
+    }
+
+    fn entries() -> Vec<Directive> {
+        vec![
+            buy(date!(2020-01-01), "Assets:Broker", 10., "STOCK", 100.),
+            buy(date!(2020-02-01), "Assets:Broker", 10., "STOCK", 150.),
+            buy(date!(2020-02-01), "Assets:Retirement", 4., "FUND", 50.),
+            price(date!(2020-03-01), "STOCK", 200.),
+        ]
+    }
+
+    #[test]
+    fn holdings() {
+        let entries = entries();
+        let holdings = get_holdings(&entries, &PriceMap::new(&entries), None);
+        assert_eq!(holdings.len(), 3);
+
+        let first = &holdings[0];
+        assert_eq!(first.account.as_deref(), Some("Assets:Broker"));
+        assert_eq!(first.book_value, 1000.);
+        assert_eq!(first.price_number, Some(200.));
+        assert_eq!(first.price_date, Some(date!(2020-03-01)));
+        assert_eq!(first.market_value, Some(2000.));
+        assert_eq!(first.unrealized_gain, Some(1000.));
+        assert_eq!(first.unrealized_gain_pct, Some(100.));
+
+        let fund = &holdings[2];
+        assert_eq!(fund.market_value, None);
+        assert_eq!(fund.unrealized_gain, None);
+    }
+
+    #[test]
+    fn holdings_on_date() {
+        let entries = entries();
+        let holdings = get_holdings(&entries, &PriceMap::new(&entries), Some(date!(2020-01-15)));
+        assert_eq!(holdings.len(), 1);
+        assert_eq!(holdings[0].price_number, None);
+    }
+
+    #[test]
+    fn aggregated() {
+        let entries = entries();
+        let holdings = get_holdings(&entries, &PriceMap::new(&entries), None);
+
+        let by_account = aggregate_holdings(&holdings, GroupBy::Account);
+        assert_eq!(by_account.len(), 2);
+        assert_eq!(by_account[0].number, Some(20.));
+        assert_eq!(by_account[0].cost_number, Some(125.));
+        assert_eq!(by_account[0].market_value, Some(4000.));
+        assert_eq!(by_account[0].unrealized_gain, Some(1500.));
+        assert!((by_account[0].unrealized_gain_pct.unwrap() - 60.).abs() < 1e-3);
+
+        let by_cost_currency = aggregate_holdings(&holdings, GroupBy::CostCurrency);
+        assert_eq!(by_cost_currency.len(), 1);
+        assert_eq!(by_cost_currency[0].account, None);
+        assert_eq!(by_cost_currency[0].number, None);
+        assert_eq!(by_cost_currency[0].book_value, 2700.);
+        assert_eq!(by_cost_currency[0].market_value, None);
+
+        assert_eq!(aggregate_holdings(&holdings, GroupBy::Currency).len(), 2);
+        assert!(GroupBy::from_str("payee").is_err());
+    }
+}
@@ -0,0 +1,248 @@
+//! Inventories
+
+use std::collections::HashMap;
+
+use serde::Serialize;
+
+use crate::beans::abc::{AAmount, ACost, Position};
+
+/// A lightweight inventory
+pub(crate) type CounterInventory = HashMap<String, f32>;
+
+/// Add `number` units of `currency`, dropping the currency if it nets to zero
+pub(crate) fn add_amount(inventory: &mut CounterInventory, currency: &str, number: f32) {
+    let previous = inventory.get(currency).copied().unwrap_or(0.);
+    let sum = previous + number;
+    if nets_to_zero(previous, number, sum) {
+        inventory.remove(currency);
+    } else {
+        inventory.insert(currency.to_string(), sum);
+    }
+}
+
+/// Whether `previous + number = sum` cancels out
+fn nets_to_zero(previous: f32, number: f32, sum: f32) -> bool {
+    // f32 sums of opposite amounts leave rounding noise instead of an exact zero
+    sum.abs() <= previous.abs().max(number.abs()) * 1e-6
+}
+
+/// Add all positions of `other`
+pub(crate) fn add_inventory(inventory: &mut CounterInventory, other: &CounterInventory) {
+    for (currency, number) in other {
+        add_amount(inventory, currency, *number);
+    }
+}
+
+/// The inventory with all numbers negated
+pub(crate) fn negate(inventory: &CounterInventory) -> CounterInventory {
+    inventory.iter().map(|(currency, number)| (currency.clone(), -number)).collect()
+}
+
+/// Units held at a cost basis
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct APosition {
+    pub units: AAmount,
+    pub cost: Option<ACost>,
+}
+
+impl Position for APosition {
+    fn get_units(&self) -> &AAmount {
+        &self.units
+    }
+
+    fn get_cost(&self) -> Option<&ACost> {
+        self.cost.as_ref()
+    }
+}
+
+/// A lot-aware inventory, positions are kept apart by currency and cost
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct LotInventory(Vec<APosition>);
+
+/// Whether units at `cost` reduce a lot held at `lot`
+///
+/// The date and label only have to match if `cost` has them.
+fn reduces(cost: &ACost, lot: &ACost) -> bool {
+    cost.number == lot.number
+        && cost.currency == lot.currency
+        && (cost.date.is_none() || cost.date == lot.date)
+        && (cost.label.is_none() || cost.label == lot.label)
+}
+
+impl LotInventory {
+    /// Add units at a cost
+    ///
+    /// Units at a cost first reduce the lots of the opposite sign that they match,
+    /// in the order the lots were added. What is left is merged with the lot of
+    /// the same currency and cost.
+    pub fn add_position(&mut self, units: &AAmount, cost: Option<&ACost>) {
+        let mut number = units.0;
+        if let Some(cost) = cost {
+            let mut index = 0;
+            while index < self.0.len() && number != 0. {
+                let lot = &mut self.0[index];
+                let matches = lot.units.1 == units.1 && lot.cost.as_ref().is_some_and(|lot| reduces(cost, lot));
+                if !matches || lot.units.0.signum() == number.signum() {
+                    index += 1;
+                    continue;
+                }
+                let previous = lot.units.0;
+                let sum = previous + number;
+                if nets_to_zero(previous, number, sum) {
+                    self.0.remove(index);
+                    number = 0.;
+                } else if sum.signum() == previous.signum() {
+                    lot.units.0 = sum;
+                    number = 0.;
+                } else {
+                    self.0.remove(index);
+                    number = sum;
+                }
+            }
+        }
+
+        let lot = self.0.iter().position(|position| position.units.1 == units.1 && position.cost.as_ref() == cost);
+        match lot {
+            Some(index) => {
+                let previous = self.0[index].units.0;
+                let sum = previous + number;
+                if nets_to_zero(previous, number, sum) {
+                    self.0.remove(index);
+                } else {
+                    self.0[index].units.0 = sum;
+                }
+            }
+            None if number != 0. => self.0.push(APosition {
+                units: AAmount(number, units.1.clone()),
+                cost: cost.cloned(),
+            }),
+            None => {}
+        }
+    }
+
+    pub fn positions(&self) -> &[APosition] {
+        &self.0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The units of all positions, ignoring their cost
+    pub fn units(&self) -> CounterInventory {
+        let mut inventory = CounterInventory::new();
+        for position in &self.0 {
+            add_amount(&mut inventory, &position.units.1, position.units.0);
+        }
+        inventory
+    }
+
+    /// The cost of all positions, positions without a cost count with their units
+    pub fn at_cost(&self) -> CounterInventory {
+        let mut inventory = CounterInventory::new();
+        for position in &self.0 {
+            match &position.cost {
+                Some(cost) => add_amount(&mut inventory, &cost.currency, position.units.0 * cost.number),
+                None => add_amount(&mut inventory, &position.units.1, position.units.0),
+            }
+        }
+        inventory
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::macros::date;
+
+    fn cost(number: f32) -> ACost {
+        ACost {
+            number,
+            currency: "USD".into(),
+            date: Some(date!(2020-01-01)),
+            label: None,
+        }
+    }
+
+    #[test]
+    fn add() {
+        let mut inventory = CounterInventory::new();
+        add_amount(&mut inventory, "USD", 10.);
+        add_amount(&mut inventory, "EUR", 5.);
+        add_amount(&mut inventory, "USD", 2.5);
+        assert_eq!(inventory, CounterInventory::from([("USD".into(), 12.5), ("EUR".into(), 5.)]));
+
+        add_inventory(&mut inventory, &CounterInventory::from([("EUR".into(), -5.)]));
+        assert_eq!(inventory, CounterInventory::from([("USD".into(), 12.5)]));
+    }
+
+    #[test]
+    fn drops_rounding_noise() {
+        let mut inventory = CounterInventory::new();
+        add_amount(&mut inventory, "USD", 0.1);
+        add_amount(&mut inventory, "USD", 0.2);
+        add_amount(&mut inventory, "USD", -0.3);
+        assert!(inventory.is_empty());
+    }
+
+    #[test]
+    fn negated() {
+        let inventory = CounterInventory::from([("USD".into(), 12.5), ("EUR".into(), -5.)]);
+        assert_eq!(negate(&inventory), CounterInventory::from([("USD".into(), -12.5), ("EUR".into(), 5.)]));
+    }
+
+    #[test]
+    fn lots() {
+        let mut inventory = LotInventory::default();
+        inventory.add_position(&AAmount(10., "STOCK".into()), Some(&cost(100.)));
+        inventory.add_position(&AAmount(5., "STOCK".into()), Some(&cost(120.)));
+        inventory.add_position(&AAmount(2., "STOCK".into()), Some(&cost(100.)));
+        inventory.add_position(&AAmount(-500., "USD".into()), None);
+        assert_eq!(inventory.positions().len(), 3);
+        assert_eq!(inventory.units(), CounterInventory::from([("STOCK".into(), 17.), ("USD".into(), -500.)]));
+        assert_eq!(inventory.at_cost(), CounterInventory::from([("USD".into(), 1300.)]));
+
+        inventory.add_position(&AAmount(-5., "STOCK".into()), Some(&cost(120.)));
+        inventory.add_position(&AAmount(0., "EUR".into()), None);
+        assert_eq!(inventory.positions().len(), 2);
+        assert_eq!(inventory.positions()[0].units, AAmount(12., "STOCK".into()));
+    }
+
+    #[test]
+    fn reductions() {
+        let sold = |date| ACost { date: Some(date), ..cost(100.) };
+        let mut inventory = LotInventory::default();
+        inventory.add_position(&AAmount(10., "STOCK".into()), Some(&cost(100.)));
+        inventory.add_position(&AAmount(4., "STOCK".into()), Some(&cost(120.)));
+        // a sale at the same cost reduces the lot it was bought in
+        inventory.add_position(&AAmount(-5., "STOCK".into()), Some(&ACost { date: None, ..cost(100.) }));
+        assert_eq!(
+            inventory.positions(),
+            [
+                APosition {
+                    units: AAmount(5., "STOCK".into()),
+                    cost: Some(cost(100.)),
+                },
+                APosition {
+                    units: AAmount(4., "STOCK".into()),
+                    cost: Some(cost(120.)),
+                },
+            ]
+        );
+        // but not if it names another lot date
+        inventory.add_position(&AAmount(-5., "STOCK".into()), Some(&sold(date!(2020-03-02))));
+        assert_eq!(inventory.positions().len(), 3);
+        inventory.add_position(&AAmount(5., "STOCK".into()), Some(&sold(date!(2020-03-02))));
+        inventory.add_position(&AAmount(-5., "STOCK".into()), Some(&cost(100.)));
+        assert_eq!(inventory.units(), CounterInventory::from([("STOCK".into(), 4.)]));
+
+        // a labelled sale only reduces the lot with its label
+        let labelled = ACost {
+            label: Some("other".into()),
+            ..cost(120.)
+        };
+        inventory.add_position(&AAmount(-1., "STOCK".into()), Some(&labelled));
+        assert_eq!(inventory.positions().len(), 2);
+        assert_eq!(inventory.at_cost(), CounterInventory::from([("USD".into(), 360.)]));
+    }
+}
@@ -0,0 +1,142 @@
+//! Account journal with running balances
+//!
+//! see: https://github.com/beancount/fava/blob/main/src/fava/core/__init__.py (`account_journal`)
+
+use serde::Serialize;
+
+use crate::beans::abc::{Directive, Entry};
+use crate::beans::account;
+use crate::beans::funcs::{get_entry_accounts, hash_entry, sort_key};
+use crate::core::accounts::Status;
+use crate::core::inventory::{self, CounterInventory};
+
+/// An entry in the journal of an account
+#[derive(Debug, Serialize)]
+pub(crate) struct JournalRow<'a> {
+    pub entry: &'a Directive,
+    pub hash: String,
+    /// change of the account's balance by this entry, in the account's own units
+    pub change: CounterInventory,
+    /// balance of the account after this entry
+    pub balance: CounterInventory,
+    /// whether the check passed, for balance entries
+    pub status: Option<Status>,
+    /// whether the previous row has the same date, so the date can be collapsed
+    pub same_day: bool,
+}
+
+/// All entries touching `account_name`, in order, with the running balance
+///
+/// With `with_children`, entries of descendant accounts are included and count
+/// towards the balance.
+pub(crate) fn account_journal<'a>(entries: &'a [Directive], account_name: &str, with_children: bool) -> Vec<JournalRow<'a>> {
+    let matches = |name: &str| if with_children { account::is_descendant(name, account_name) } else { name == account_name };
+
+    let mut entries: Vec<&Directive> = entries
+        .iter()
+        .filter(|entry| get_entry_accounts(entry).into_iter().any(matches))
+        .collect();
+    entries.sort_by_key(|entry| sort_key(entry));
+
+    let mut balance = CounterInventory::new();
+    let mut previous_date = None;
+    entries
+        .into_iter()
+        .map(|entry| {
+            let mut change = CounterInventory::new();
+            if let Directive::Transactions(transaction) = entry {
+                for posting in transaction.postings.iter().filter(|posting| matches(&posting.account)) {
+                    inventory::add_amount(&mut change, &posting.units.1, posting.units.0);
+                }
+            }
+            inventory::add_inventory(&mut balance, &change);
+
+            let status = match entry {
+                Directive::Balance(balance) if balance.diff_amount.is_some() => Some(Status::Fail),
+                Directive::Balance(_) => Some(Status::Pass),
+                _ => None,
+            };
+
+            let same_day = previous_date == Some(entry.get_date());
+            previous_date = Some(entry.get_date());
+
+            JournalRow {
+                entry,
+                hash: hash_entry(entry),
+                change,
+                balance: balance.clone(),
+                status,
+                same_day,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::beans::abc::{AAmount, Balance, Open, Posting, Transaction};
+    use crate::beans::flags::Flags;
+    use time::macros::date;
+
+    fn transaction(date: time::Date, account: &str, number: f32) -> Directive {
+        Directive::Transactions(Transaction::new(
+            date,
+            Flags::Okay,
+            "",
+            vec![Posting::new(account, AAmount(number, "USD".into())), Posting::new("Income:Salary", AAmount(-number, "USD".into()))],
+        ))
+    }
+
+    fn entries() -> Vec<Directive> {
+        let mut failed = Balance::new(date!(2020-01-03), "Assets:Bank:Checking", AAmount(0., "USD".into()));
+        failed.diff_amount = Some(AAmount(100., "USD".into()));
+        vec![
+            transaction(date!(2020-01-02), "Assets:Bank:Checking", 100.),
+            Directive::Open(Open::new(date!(2020-01-01), "Assets:Bank:Checking")),
+            transaction(date!(2020-01-02), "Assets:Bank:Savings", 50.),
+            Directive::Balance(failed),
+            Directive::Balance(Balance::new(date!(2020-01-03), "Assets:Bank:Savings", AAmount(50., "USD".into()))),
+            transaction(date!(2020-01-04), "Expenses:Food", 10.),
+        ]
+    }
+
+    #[test]
+    fn own_account() {
+        let entries = entries();
+        let journal = account_journal(&entries, "Assets:Bank:Checking", false);
+        assert_eq!(journal.len(), 3);
+        assert!(matches!(journal[0].entry, Directive::Open(_)));
+        assert_eq!(journal[1].change, CounterInventory::from([("USD".into(), 100.)]));
+        assert_eq!(journal[2].status, Some(Status::Fail));
+        assert!(journal[2].change.is_empty());
+        assert_eq!(journal[2].balance, CounterInventory::from([("USD".into(), 100.)]));
+    }
+
+    #[test]
+    fn with_children() {
+        let entries = entries();
+        let journal = account_journal(&entries, "Assets:Bank", true);
+        let balances: Vec<Option<f32>> = journal.iter().map(|row| row.balance.get("USD").copied()).collect();
+        assert_eq!(balances, vec![None, Some(100.), Some(150.), Some(150.), Some(150.)]);
+
+        let same_day: Vec<bool> = journal.iter().map(|row| row.same_day).collect();
+        assert_eq!(same_day, vec![false, false, true, false, true]);
+        assert_eq!(journal[4].status, Some(Status::Pass));
+
+        assert!(account_journal(&entries, "Assets:Bank", false).is_empty());
+    }
+
+    #[test]
+    fn serialised() {
+        let entries = entries();
+        let journal = account_journal(&entries, "Assets:Bank:Savings", false);
+        let json = serde_json::to_value(&journal).unwrap();
+        assert_eq!(json[0]["entry"]["t"], "Transaction");
+        assert_eq!(json[0]["entry"]["date"], "2020-01-02");
+        assert_eq!(json[0]["entry"]["flag"], "*");
+        assert_eq!(json[0]["entry"]["postings"][0]["units"]["currency"], "USD");
+        assert_eq!(json[1]["status"], "green");
+        assert_eq!(json[1]["entry"]["meta"]["lineno"], 0);
+    }
+}
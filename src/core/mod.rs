@@ -1,39 +1,321 @@
-mod accounts;
+pub(crate) mod accounts;
+pub(crate) mod attributes;
+pub(crate) mod charts;
+pub(crate) mod context;
+pub(crate) mod conversion;
+pub(crate) mod documents;
+pub(crate) mod events;
+pub(crate) mod fava_options;
+pub(crate) mod file;
+pub(crate) mod filters;
+pub(crate) mod holdings;
+pub(crate) mod inventory;
+pub(crate) mod journal;
+pub(crate) mod reports;
+pub(crate) mod statistics;
+pub(crate) mod tags;
+pub(crate) mod tree;
 
-mod inventory {
-    use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet};
 
-    /// A lightweight inventory
-    pub(crate) type CounterInventory = HashMap<String, f32>;
+use crate::Helpers;
+use crate::beans::abc::{Directive, Entry};
+use crate::beans::account;
+use crate::beans::funcs::{hash_entry, sort_key};
+use crate::beans::load;
+use crate::beans::options::BeancountOptions;
+use crate::beans::prices::PriceMap;
+use crate::core::accounts::AccountDict;
+use crate::core::attributes::{Attributes, Rankings};
+use crate::core::fava_options::FavaOptions;
+use crate::core::inventory::LotInventory;
+use crate::core::tree::Tree;
+use crate::storage::Storage;
+use crate::util::date::{DateRange, FiscalYearEnd, Interval, Period, dateranges};
+use crate::util::slugify;
 
-    #[cfg(test)]
-    mod tests {
-        use super::*;
-    }
+/// A loaded ledger and the data derived from its entries
+///
+/// see: https://github.com/beancount/fava/blob/main/src/fava/core/__init__.py
+#[derive(Default)]
+pub(crate) struct FavaLedger {
+    /// all entries, sorted
+    pub entries: Vec<Directive>,
+    pub errors: Vec<Helpers>,
+    pub options: BeancountOptions,
+    pub fava_options: FavaOptions,
+    /// path of the main file
+    pub filename: String,
+    /// source text of every file of the ledger, by path
+    pub sources: BTreeMap<String, String>,
+    /// paths of the included files that could not be read
+    pub missing_files: BTreeSet<String>,
+    pub accounts: AccountDict,
+    pub prices: PriceMap,
+    /// ranked values for autocompletion
+    pub attributes: Attributes,
+    /// balances of all accounts at the end of the ledger
+    tree: Tree,
 }
 
-mod tree {
-    use crate::core::inventory::CounterInventory;
+impl FavaLedger {
+    /// The ledger of `entries`, with `rankings` counting the entries it hasn't counted yet
+    pub fn new(
+        mut entries: Vec<Directive>,
+        mut errors: Vec<Helpers>,
+        options: BeancountOptions,
+        filename: String,
+        sources: BTreeMap<String, String>,
+        rankings: &mut Rankings,
+    ) -> Self {
+        entries.sort_by_key(sort_key);
+        let mut accounts = AccountDict::default();
+        accounts.load_file(&entries);
+        let prices = PriceMap::new(&entries);
+        let tree = Tree::new(&entries);
+        rankings.count(&entries);
+        let attributes = Attributes::new(&entries, rankings);
 
-    /// name, balance
-    pub(crate) struct TreeNode(pub String, pub CounterInventory);
+        let (fava_options, option_errors) = FavaOptions::new(&entries);
+        errors.extend(option_errors);
 
-    impl TreeNode {
-        fn new(account_name: String) -> Self {
-            Self(account_name, CounterInventory::new())
+        Self {
+            entries,
+            errors,
+            options,
+            fava_options,
+            filename,
+            sources,
+            missing_files: BTreeSet::new(),
+            accounts,
+            prices,
+            attributes,
+            tree,
         }
+    }
 
-        pub fn get_name(&self) -> String {
-            self.0.clone()
+    /// Load the ledger from `filename` and the files it includes, with the documents in its documents folders
+    ///
+    /// The rankings for autocompletion are stored, so that only entries added
+    /// since the last load are counted.
+    pub async fn load(storage: &impl Storage, filename: &str) -> Self {
+        let mut loaded = load::load_file(filename, |path| async move { storage.read(&path).await }).await;
+        let folders = documents::document_folders(&loaded.options, filename);
+        let files = if folders.is_empty() {
+            Vec::new()
+        } else {
+            storage.list().await.unwrap_or_else(|error| {
+                loaded.errors.push(error);
+                Vec::new()
+            })
+        };
+        documents::process_documents(&mut loaded.entries, &folders, files.iter().map(|file| file.path.as_str()));
+
+        let path = attributes::rankings_path(filename);
+        let mut rankings = Rankings::load(storage, &path).await;
+        let stored = rankings.digest().to_string();
+        let mut ledger = Self {
+            missing_files: loaded.missing,
+            ..Self::new(loaded.entries, loaded.errors, loaded.options, filename.to_string(), loaded.sources, &mut rankings)
+        };
+        if rankings.digest() != stored
+            && let Err(error) = rankings.save(storage, &path).await
+        {
+            ledger.errors.push(error);
         }
+        ledger
+    }
+
+    /// The entry with the given hash
+    pub fn get_entry(&self, entry_hash: &str) -> Result<&Directive, Helpers> {
+        self.entries
+            .iter()
+            .find(|entry| hash_entry(entry) == entry_hash)
+            .ok_or_else(|| Helpers::EntryNotFoundForHashError(format!("No entry found for hash {entry_hash}")))
+    }
+
+    /// URL slug of the ledger, derived from its title
+    pub fn slug(&self) -> String {
+        slugify(&self.options.title)
+    }
+
+    /// Balances of all accounts at the end of the ledger
+    pub fn root_tree(&self) -> &Tree {
+        &self.tree
+    }
+}
 
-        pub fn get_balance(&self) -> CounterInventory {
-            self.1.clone()
+/// Balances that entries are added to, one for every interval of [`interval_balances`]
+pub(crate) trait Balances: Default + Clone {
+    /// Add the postings of `entry` to accounts matching `filter`
+    fn add_entry(&mut self, entry: &Directive, filter: impl Fn(&str) -> bool);
+}
+
+impl Balances for Tree {
+    fn add_entry(&mut self, entry: &Directive, filter: impl Fn(&str) -> bool) {
+        Tree::add_entry(self, entry, filter);
+    }
+}
+
+/// The lots of all matching accounts together
+impl Balances for LotInventory {
+    fn add_entry(&mut self, entry: &Directive, filter: impl Fn(&str) -> bool) {
+        if let Directive::Transactions(transaction) = entry {
+            for posting in transaction.postings.iter().filter(|posting| filter(&posting.account)) {
+                self.add_position(&posting.units, posting.cost.as_ref());
+            }
         }
     }
+}
 
-    #[cfg(test)]
-    mod tests {
-        use super::*;
+/// The lots of every matching account
+impl Balances for BTreeMap<String, LotInventory> {
+    fn add_entry(&mut self, entry: &Directive, filter: impl Fn(&str) -> bool) {
+        if let Directive::Transactions(transaction) = entry {
+            for posting in transaction.postings.iter().filter(|posting| filter(&posting.account)) {
+                self.entry(posting.account.clone()).or_default().add_position(&posting.units, posting.cost.as_ref());
+            }
+        }
     }
-}
\ No newline at end of file
+}
+
+/// Balances of the accounts matching `accounts` for every `interval` spanned by `entries`
+///
+/// The intervals cover `time` if it is given, the last one is cut off at the
+/// last covered date. With `accumulate`, each balance is the one at the end
+/// of its interval, otherwise only the changes within it. An empty `accounts`
+/// list matches all accounts, a name matches the account and its descendants.
+pub(crate) fn interval_balances<'a, B: Balances>(
+    entries: impl IntoIterator<Item = &'a Directive>,
+    interval: Interval,
+    fye: &FiscalYearEnd,
+    accounts: &[&str],
+    time: Option<DateRange>,
+    accumulate: bool,
+) -> Vec<(Period, B)> {
+    let filter = |name: &str| accounts.is_empty() || accounts.iter().any(|ancestor| account::is_descendant(name, ancestor));
+
+    let mut entries: Vec<&Directive> = entries.into_iter().collect();
+    entries.sort_by_key(|entry| entry.get_date());
+
+    let (begin, last) = match (time, entries.first(), entries.last()) {
+        (Some(time), ..) => (time.begin, time.end.previous_day().unwrap_or(time::Date::MIN)),
+        (None, Some(first), Some(last)) => (first.get_date(), last.get_date()),
+        _ => return Vec::new(),
+    };
+
+    let mut remaining = entries.into_iter().peekable();
+    let mut balances = B::default();
+    dateranges(begin, last, interval, fye)
+        .map(|period| {
+            let period = Period {
+                last: period.last.min(last),
+                ..period
+            };
+            if !accumulate {
+                balances = B::default();
+                // earlier entries only count towards the accumulated balances
+                while remaining.next_if(|entry| entry.get_date() < period.begin).is_some() {}
+            }
+            while let Some(entry) = remaining.next_if(|entry| entry.get_date() <= period.last) {
+                balances.add_entry(entry, filter);
+            }
+            (period, balances.clone())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::beans::abc::{AAmount, Posting, Transaction};
+    use crate::beans::flags::Flags;
+    use crate::core::inventory::CounterInventory;
+    use time::macros::date;
+
+    fn groceries(date: time::Date, number: f32) -> Directive {
+        Directive::Transactions(Transaction::new(
+            date,
+            Flags::Okay,
+            "Groceries",
+            vec![
+                Posting::new("Expenses:Food", AAmount(number, "USD".into())),
+                Posting::new("Assets:Cash", AAmount(-number, "USD".into())),
+            ],
+        ))
+    }
+
+    #[test]
+    fn ledger_slug() {
+        let mut ledger = FavaLedger::default();
+        assert_eq!(ledger.slug(), "beancount");
+        ledger.options.title = "My Ledger: 2020 (Personal)".into();
+        assert_eq!(ledger.slug(), "my-ledger-2020-personal");
+    }
+
+    #[test]
+    fn load() {
+        let storage = crate::storage::MemoryStorage::new([(
+            "main.beancount",
+            "option \"title\" \"Loaded\"\n2020-01-01 custom \"fava-option\" \"fiscal-year-end\" \"03-31\"\n2020-01-01 custom \"fava-option\" \"fiscal-year-end\" \"02-30\"\n",
+        )]);
+        let ledger = crate::storage::block_on(FavaLedger::load(&storage, "main.beancount"));
+        assert_eq!(ledger.options.title, "Loaded");
+        assert_eq!(ledger.fava_options.fiscal_year_end, "03-31".parse().unwrap());
+        assert_eq!(ledger.errors, vec![Helpers::BeancountError("main.beancount:3: Invalid fiscal year end: 02-30".into())]);
+        assert!(ledger.sources.contains_key("main.beancount"));
+    }
+
+    #[test]
+    fn empty() {
+        assert!(interval_balances::<Tree>(&[], Interval::Month, &FiscalYearEnd::default(), &[], None, false).is_empty());
+    }
+
+    #[test]
+    fn changes() {
+        let entries = vec![groceries(date!(2020-03-05), 30.), groceries(date!(2020-01-10), 10.), groceries(date!(2020-01-20), 15.)];
+        let balances: Vec<(Period, Tree)> = interval_balances(&entries, Interval::Month, &FiscalYearEnd::default(), &["Expenses"], None, false);
+
+        let ranges: Vec<time::Date> = balances.iter().map(|(period, _)| period.begin).collect();
+        assert_eq!(ranges, vec![date!(2020-01-01), date!(2020-02-01), date!(2020-03-01)]);
+
+        let totals: Vec<CounterInventory> = balances.iter().map(|(_, tree)| tree.balance_children("")).collect();
+        assert_eq!(
+            totals,
+            vec![CounterInventory::from([("USD".into(), 25.)]), CounterInventory::new(), CounterInventory::from([("USD".into(), 30.)])]
+        );
+        assert!(balances[0].1.get("Assets:Cash").is_none());
+
+        // the time filter sets the intervals, changes before them are left out
+        let time = DateRange { begin: date!(2020-02-15), end: date!(2020-03-10) };
+        let balances: Vec<(Period, Tree)> = interval_balances(&entries, Interval::Month, &FiscalYearEnd::default(), &["Expenses"], Some(time), false);
+        let totals: Vec<CounterInventory> = balances.iter().map(|(_, tree)| tree.balance_children("")).collect();
+        assert_eq!(totals, vec![CounterInventory::new(), CounterInventory::from([("USD".into(), 30.)])]);
+        assert_eq!(balances[1].0, Period { begin: date!(2020-03-01), last: date!(2020-03-09) });
+    }
+
+    #[test]
+    fn cumulative() {
+        let entries = vec![groceries(date!(2020-01-10), 10.), groceries(date!(2020-03-05), 30.)];
+        let balances: Vec<(Period, Tree)> = interval_balances(&entries, Interval::Quarter, &FiscalYearEnd::default(), &["Assets:Cash"], None, true);
+        assert_eq!(balances.len(), 1);
+        assert_eq!(balances[0].0.last, date!(2020-03-05));
+
+        let balances: Vec<(Period, Tree)> = interval_balances(&entries, Interval::Month, &FiscalYearEnd::default(), &["Assets:Cash"], None, true);
+        let totals: Vec<CounterInventory> = balances.iter().map(|(_, tree)| tree.balance_children("Assets")).collect();
+        assert_eq!(
+            totals,
+            vec![
+                CounterInventory::from([("USD".into(), -10.)]),
+                CounterInventory::from([("USD".into(), -10.)]),
+                CounterInventory::from([("USD".into(), -40.)]),
+            ]
+        );
+
+        // balances include the entries before the first interval
+        let time = DateRange { begin: date!(2020-02-01), end: date!(2020-04-01) };
+        let balances: Vec<(Period, LotInventory)> = interval_balances(&entries, Interval::Month, &FiscalYearEnd::default(), &["Assets:Cash"], Some(time), true);
+        let totals: Vec<CounterInventory> = balances.iter().map(|(_, inventory)| inventory.units()).collect();
+        assert_eq!(totals, vec![CounterInventory::from([("USD".into(), -10.)]), CounterInventory::from([("USD".into(), -40.)])]);
+    }
+}
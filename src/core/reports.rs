@@ -0,0 +1,169 @@
+//! Balance sheet, income statement and trial balance
+
+use serde::Serialize;
+
+use crate::beans::options::BeancountOptions;
+use crate::core::inventory::{self, CounterInventory};
+use crate::core::tree::{SerialisedTreeNode, Tree};
+
+/// Serialise `name`, or an empty node if the tree has no such account
+fn serialise_or_empty(tree: &Tree, name: &str) -> SerialisedTreeNode {
+    tree.serialise(name).unwrap_or_else(|| SerialisedTreeNode {
+        account: name.to_string(),
+        balance: CounterInventory::new(),
+        balance_children: CounterInventory::new(),
+        children: Vec::new(),
+    })
+}
+
+/// Assets, liabilities and equity, with net profit and conversions carried into equity
+#[derive(Debug, Serialize)]
+pub(crate) struct BalanceSheet {
+    pub assets: SerialisedTreeNode,
+    pub liabilities: SerialisedTreeNode,
+    pub equity: SerialisedTreeNode,
+    pub net_profit: CounterInventory,
+    /// sum of the three sections, empty if the books balance
+    pub totals: CounterInventory,
+}
+
+impl BalanceSheet {
+    pub fn new(tree: &Tree, options: &BeancountOptions) -> Self {
+        let net_profit = tree.net_profit(options);
+        let mut tree = tree.clone();
+        tree.cap(options);
+
+        let assets = serialise_or_empty(&tree, &options.name_assets);
+        let liabilities = serialise_or_empty(&tree, &options.name_liabilities);
+        let equity = serialise_or_empty(&tree, &options.name_equity);
+
+        let mut totals = assets.balance_children.clone();
+        inventory::add_inventory(&mut totals, &liabilities.balance_children);
+        inventory::add_inventory(&mut totals, &equity.balance_children);
+
+        Self {
+            assets,
+            liabilities,
+            equity,
+            net_profit,
+            totals,
+        }
+    }
+}
+
+/// Income and expenses with inverted signs, so that income and profit are positive
+#[derive(Debug, Serialize)]
+pub(crate) struct IncomeStatement {
+    pub income: SerialisedTreeNode,
+    pub expenses: SerialisedTreeNode,
+    pub net_profit: CounterInventory,
+}
+
+impl IncomeStatement {
+    pub fn new(tree: &Tree, options: &BeancountOptions) -> Self {
+        Self {
+            income: serialise_or_empty(tree, &options.name_income).invert(),
+            expenses: serialise_or_empty(tree, &options.name_expenses).invert(),
+            net_profit: inventory::negate(&tree.net_profit(options)),
+        }
+    }
+}
+
+/// account, balance
+#[derive(Debug, Serialize)]
+pub(crate) struct TrialBalanceRow {
+    pub account: String,
+    pub balance: CounterInventory,
+}
+
+/// The balance of every account
+#[derive(Debug, Serialize)]
+pub(crate) struct TrialBalance {
+    pub rows: Vec<TrialBalanceRow>,
+    /// sum of all rows, empty if the books balance
+    pub totals: CounterInventory,
+}
+
+impl TrialBalance {
+    pub fn new(tree: &Tree) -> Self {
+        let rows: Vec<TrialBalanceRow> = tree
+            .nodes()
+            .filter(|node| !node.0.is_empty())
+            .map(|node| TrialBalanceRow {
+                account: node.get_name(),
+                balance: node.get_balance(),
+            })
+            .collect();
+
+        let mut totals = CounterInventory::new();
+        for row in &rows {
+            inventory::add_inventory(&mut totals, &row.balance);
+        }
+
+        Self { rows, totals }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::beans::abc::{AAmount, Directive, Open, Posting, Transaction};
+    use crate::beans::flags::Flags;
+    use time::macros::date;
+
+    fn transaction(narration: &str, postings: &[(&str, f32)]) -> Directive {
+        let postings = postings.iter().map(|(account, number)| Posting::new(*account, AAmount(*number, "USD".into()))).collect();
+        Directive::Transactions(Transaction::new(date!(2020-01-02), Flags::Okay, narration, postings))
+    }
+
+    fn tree() -> Tree {
+        Tree::new(&[
+            Directive::Open(Open::new(date!(2020-01-01), "Assets:Checking")),
+            Directive::Open(Open::new(date!(2020-01-01), "Liabilities:CreditCard")),
+            transaction("Salary", &[("Assets:Checking", 1000.), ("Income:Salary", -1000.)]),
+            transaction("Rent", &[("Expenses:Rent", 400.), ("Liabilities:CreditCard", -400.)]),
+            transaction("Opening", &[("Assets:Checking", 50.), ("Equity:Opening-Balances", -50.)]),
+        ])
+    }
+
+    #[test]
+    fn balance_sheet() {
+        let report = BalanceSheet::new(&tree(), &BeancountOptions::default());
+        assert_eq!(report.assets.balance_children, CounterInventory::from([("USD".into(), 1050.)]));
+        assert_eq!(report.liabilities.balance_children, CounterInventory::from([("USD".into(), -400.)]));
+        assert_eq!(report.equity.balance_children, CounterInventory::from([("USD".into(), -650.)]));
+        assert_eq!(report.net_profit, CounterInventory::from([("USD".into(), -600.)]));
+        assert!(report.totals.is_empty());
+
+        let earnings = report.equity.children.iter().find(|child| child.account == "Equity:Earnings").unwrap();
+        assert_eq!(earnings.balance_children, CounterInventory::from([("USD".into(), -600.)]));
+    }
+
+    #[test]
+    fn income_statement() {
+        let report = IncomeStatement::new(&tree(), &BeancountOptions::default());
+        assert_eq!(report.income.balance_children, CounterInventory::from([("USD".into(), 1000.)]));
+        assert_eq!(report.expenses.balance_children, CounterInventory::from([("USD".into(), -400.)]));
+        assert_eq!(report.net_profit, CounterInventory::from([("USD".into(), 600.)]));
+    }
+
+    #[test]
+    fn trial_balance() {
+        let report = TrialBalance::new(&tree());
+        let accounts: Vec<&str> = report.rows.iter().map(|row| row.account.as_str()).collect();
+        assert!(accounts.contains(&"Assets"));
+        assert!(accounts.contains(&"Expenses:Rent"));
+        assert!(report.totals.is_empty());
+
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["rows"][1]["account"], "Assets:Checking");
+        assert_eq!(json["rows"][1]["balance"]["USD"], 1050.);
+    }
+
+    #[test]
+    fn missing_roots() {
+        let report = IncomeStatement::new(&Tree::default(), &BeancountOptions::default());
+        assert_eq!(report.income.account, "Income");
+        assert!(report.income.balance_children.is_empty());
+    }
+}
@@ -0,0 +1,152 @@
+//! Ledger statistics
+//!
+//! see: https://github.com/beancount/fava/blob/main/src/fava/templates/statistics.html
+
+use std::collections::{BTreeMap, HashMap};
+
+use serde::Serialize;
+
+use crate::beans::abc::{Directive, Entry};
+use crate::beans::flags::Flags;
+use crate::beans::funcs::entry_type;
+use crate::beans::prices::PriceMap;
+use crate::core::accounts::AccountDict;
+use crate::core::conversion;
+use crate::core::inventory::{CounterInventory, LotInventory};
+
+/// The balance of an account in units, at cost and at market value
+#[derive(Debug, Serialize)]
+pub(crate) struct AccountBalance {
+    pub account: String,
+    pub units: CounterInventory,
+    pub at_cost: CounterInventory,
+    pub at_value: CounterInventory,
+}
+
+/// Counts and activity dates to spot stale or noisy accounts
+#[derive(Debug, Serialize)]
+pub(crate) struct Statistics {
+    pub entries_by_type: BTreeMap<&'static str, usize>,
+    pub transactions_by_flag: HashMap<Flags, usize>,
+    pub postings_by_account: BTreeMap<String, usize>,
+    /// date of the last entry of each open account
+    pub last_activity: BTreeMap<String, time::Date>,
+    pub date_first: Option<time::Date>,
+    pub date_last: Option<time::Date>,
+    pub balances: Vec<AccountBalance>,
+}
+
+impl Statistics {
+    pub fn new(entries: &[Directive], accounts: &AccountDict, prices: &PriceMap) -> Self {
+        let mut entries_by_type = BTreeMap::new();
+        let mut transactions_by_flag = HashMap::new();
+        let mut postings_by_account = BTreeMap::new();
+        let mut inventories: BTreeMap<&str, LotInventory> = BTreeMap::new();
+
+        for entry in entries {
+            *entries_by_type.entry(entry_type(entry)).or_default() += 1;
+            if let Directive::Transactions(transaction) = entry {
+                *transactions_by_flag.entry(transaction.flag).or_default() += 1;
+                for posting in &transaction.postings {
+                    *postings_by_account.entry(posting.account.clone()).or_default() += 1;
+                    inventories.entry(&posting.account).or_default().add_position(&posting.units, posting.cost.as_ref());
+                }
+            }
+        }
+
+        let last_activity = accounts
+            .accounts()
+            .filter_map(|account| {
+                let last_entry = accounts.get_or_empty(account).last_entry.as_ref()?;
+                Some((account.to_string(), last_entry.0))
+            })
+            .collect();
+
+        let balances = inventories
+            .into_iter()
+            .filter(|(_, inventory)| !inventory.is_empty())
+            .map(|(account, inventory)| AccountBalance {
+                account: account.to_string(),
+                units: inventory.units(),
+                at_cost: inventory.at_cost(),
+                at_value: conversion::at_value(&inventory, prices, None),
+            })
+            .collect();
+
+        Self {
+            entries_by_type,
+            transactions_by_flag,
+            postings_by_account,
+            last_activity,
+            date_first: entries.iter().map(Entry::get_date).min(),
+            date_last: entries.iter().map(Entry::get_date).max(),
+            balances,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::beans::abc::{AAmount, ACost, Meta, Open, Posting, Price, Transaction};
+    use time::macros::date;
+
+    fn entries() -> Vec<Directive> {
+        let mut stock = Posting::new("Assets:Broker", AAmount(2., "STOCK".into()));
+        stock.cost = Some(ACost {
+            number: 100.,
+            currency: "USD".into(),
+            date: Some(date!(2020-01-05)),
+            label: None,
+        });
+        vec![
+            Directive::Open(Open::new(date!(2020-01-01), "Assets:Broker")),
+            Directive::Open(Open::new(date!(2020-01-01), "Assets:Cash")),
+            Directive::Transactions(Transaction::new(
+                date!(2020-01-05),
+                Flags::Okay,
+                "Buy",
+                vec![stock, Posting::new("Assets:Cash", AAmount(-200., "USD".into()))],
+            )),
+            Directive::Transactions(Transaction::new(
+                date!(2020-02-01),
+                Flags::Warning,
+                "Withdraw",
+                vec![Posting::new("Assets:Cash", AAmount(-50., "USD".into())), Posting::new("Expenses:Misc", AAmount(50., "USD".into()))],
+            )),
+            Directive::Price(Price {
+                date: date!(2020-03-01),
+                currency: "STOCK".into(),
+                amount: AAmount(120., "USD".into()),
+                meta: Meta::default(),
+            }),
+        ]
+    }
+
+    #[test]
+    fn statistics() {
+        let entries = entries();
+        let mut accounts = AccountDict::default();
+        accounts.load_file(&entries);
+        let statistics = Statistics::new(&entries, &accounts, &PriceMap::new(&entries));
+
+        assert_eq!(statistics.entries_by_type, BTreeMap::from([("Open", 2), ("Price", 1), ("Transaction", 2)]));
+        assert_eq!(statistics.transactions_by_flag, HashMap::from([(Flags::Okay, 1), (Flags::Warning, 1)]));
+        assert_eq!(statistics.postings_by_account["Assets:Cash"], 2);
+        assert_eq!(
+            statistics.last_activity,
+            BTreeMap::from([("Assets:Broker".into(), date!(2020-01-05)), ("Assets:Cash".into(), date!(2020-02-01))])
+        );
+        assert_eq!(statistics.date_first, Some(date!(2020-01-01)));
+        assert_eq!(statistics.date_last, Some(date!(2020-03-01)));
+
+        let broker = &statistics.balances[0];
+        assert_eq!(broker.account, "Assets:Broker");
+        assert_eq!(broker.units, CounterInventory::from([("STOCK".into(), 2.)]));
+        assert_eq!(broker.at_cost, CounterInventory::from([("USD".into(), 200.)]));
+        assert_eq!(broker.at_value, CounterInventory::from([("USD".into(), 240.)]));
+
+        let json = serde_json::to_value(&statistics).unwrap();
+        assert_eq!(json["transactions_by_flag"]["!"], 1);
+    }
+}
@@ -0,0 +1,81 @@
+//! Tags and links: how often they are used, and the entries sharing a link
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::beans::abc::Directive;
+
+/// The tags and links of the entry, only transactions and documents have them
+fn tags_and_links(entry: &Directive) -> Option<(&BTreeSet<String>, &BTreeSet<String>)> {
+    match entry {
+        Directive::Transactions(transaction) => Some((&transaction.tags, &transaction.links)),
+        Directive::Document(document) => Some((&document.tags, &document.links)),
+        _ => None,
+    }
+}
+
+/// Every tag with the number of entries that have it
+pub(crate) fn tag_counts(entries: &[Directive]) -> BTreeMap<&str, usize> {
+    let mut counts = BTreeMap::new();
+    for (tags, _) in entries.iter().filter_map(tags_and_links) {
+        for tag in tags {
+            *counts.entry(tag.as_str()).or_default() += 1;
+        }
+    }
+    counts
+}
+
+/// Every link with the number of entries that have it
+pub(crate) fn link_counts(entries: &[Directive]) -> BTreeMap<&str, usize> {
+    let mut counts = BTreeMap::new();
+    for (_, links) in entries.iter().filter_map(tags_and_links) {
+        for link in links {
+            *counts.entry(link.as_str()).or_default() += 1;
+        }
+    }
+    counts
+}
+
+/// All entries with the link, like an invoice and its payments
+pub(crate) fn linked_entries<'a>(entries: &'a [Directive], link: &str) -> Vec<&'a Directive> {
+    entries
+        .iter()
+        .filter(|entry| tags_and_links(entry).is_some_and(|(_, links)| links.contains(link)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::beans::abc::{Note, Transaction};
+    use crate::beans::flags::Flags;
+    use time::macros::date;
+
+    fn transaction(narration: &str, tags: &[&str], links: &[&str]) -> Directive {
+        let mut transaction = Transaction::new(date!(2020-01-01), Flags::Okay, narration, Vec::new());
+        transaction.tags = tags.iter().map(|tag| tag.to_string()).collect();
+        transaction.links = links.iter().map(|link| link.to_string()).collect();
+        Directive::Transactions(transaction)
+    }
+
+    #[test]
+    fn counts_and_links() {
+        let entries = [
+            transaction("Invoice", &["work"], &["invoice-1"]),
+            transaction("Payment", &["work", "bank"], &["invoice-1"]),
+            transaction("Invoice", &[], &["invoice-2"]),
+            Directive::Note(Note {
+                date: date!(2020-01-02),
+                account: "Assets:Cash".into(),
+                comment: "^invoice-1".into(),
+                meta: Default::default(),
+            }),
+        ];
+        assert_eq!(tag_counts(&entries), BTreeMap::from([("bank", 1), ("work", 2)]));
+        assert_eq!(link_counts(&entries), BTreeMap::from([("invoice-1", 2), ("invoice-2", 1)]));
+
+        let linked = linked_entries(&entries, "invoice-1");
+        assert_eq!(linked.len(), 2);
+        assert!(matches!(linked[1], Directive::Transactions(transaction) if transaction.narration == "Payment"));
+        assert!(linked_entries(&entries, "invoice-3").is_empty());
+    }
+}
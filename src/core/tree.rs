@@ -0,0 +1,354 @@
+//! Account balance trees
+//!
+//! see: https://github.com/beancount/fava/blob/main/src/fava/core/tree.py
+
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use crate::beans::abc::{AAmount, Directive};
+use crate::beans::account;
+use crate::beans::options::BeancountOptions;
+use crate::core::inventory::{self, CounterInventory};
+
+/// name, balance
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct TreeNode(pub String, pub CounterInventory);
+
+impl TreeNode {
+    fn new(account_name: String) -> Self {
+        Self(account_name, CounterInventory::new())
+    }
+
+    pub fn get_name(&self) -> String {
+        self.0.clone()
+    }
+
+    pub fn get_balance(&self) -> CounterInventory {
+        self.1.clone()
+    }
+}
+
+/// Account tree
+///
+/// Nodes are keyed by account name, the root node has the empty name. Every
+/// ancestor of a node is in the tree as well.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Tree(BTreeMap<String, TreeNode>);
+
+impl Default for Tree {
+    fn default() -> Self {
+        Self(BTreeMap::from([(String::new(), TreeNode::new(String::new()))]))
+    }
+}
+
+impl Tree {
+    /// Tree of the balances of all postings in `entries`
+    pub fn new<'a>(entries: impl IntoIterator<Item = &'a Directive>) -> Self {
+        Self::new_filtered(entries, |_| true)
+    }
+
+    /// Tree of the balances of the postings in `entries` to accounts matching `filter`
+    pub fn new_filtered<'a>(entries: impl IntoIterator<Item = &'a Directive>, filter: impl Fn(&str) -> bool) -> Self {
+        let mut tree = Self::default();
+        for entry in entries {
+            tree.add_entry(entry, &filter);
+        }
+        tree
+    }
+
+    /// Add the postings of `entry` to accounts matching `filter`
+    pub fn add_entry(&mut self, entry: &Directive, filter: impl Fn(&str) -> bool) {
+        match entry {
+            Directive::Open(open) if filter(&open.account) => {
+                self.get_or_insert(&open.account);
+            }
+            Directive::Transactions(transaction) => {
+                for posting in transaction.postings.iter().filter(|posting| filter(&posting.account)) {
+                    self.add_amount(&posting.account, &posting.units);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Get the node for `name`, inserting it and its ancestors if missing
+    fn get_or_insert(&mut self, name: &str) -> &mut TreeNode {
+        if !self.0.contains_key(name) {
+            let mut ancestor = account::parent(name);
+            while let Some(name) = ancestor {
+                self.0.entry(name.to_string()).or_insert_with(|| TreeNode::new(name.to_string()));
+                ancestor = account::parent(name);
+            }
+        }
+        self.0.entry(name.to_string()).or_insert_with(|| TreeNode::new(name.to_string()))
+    }
+
+    /// Add `balance` to the balance of the node `name`
+    pub fn insert(&mut self, name: &str, balance: &CounterInventory) {
+        inventory::add_inventory(&mut self.get_or_insert(name).1, balance);
+    }
+
+    /// Add `amount` to the balance of the node `name`
+    pub fn add_amount(&mut self, name: &str, amount: &AAmount) {
+        inventory::add_amount(&mut self.get_or_insert(name).1, &amount.1, amount.0);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&TreeNode> {
+        self.0.get(name)
+    }
+
+    /// All nodes, sorted by account name
+    pub fn nodes(&self) -> impl Iterator<Item = &TreeNode> {
+        self.0.values()
+    }
+
+    /// `name` and all its descendants, sorted by account name
+    fn subtree<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a TreeNode> + 'a {
+        self.0
+            .range::<str, _>((std::ops::Bound::Included(name), std::ops::Bound::Unbounded))
+            .take_while(move |(account, _)| name.is_empty() || account.starts_with(name))
+            .filter(move |(account, _)| account::is_descendant(account, name))
+            .map(|(_, node)| node)
+    }
+
+    /// The direct children of `name`
+    pub fn children<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a TreeNode> + 'a {
+        self.subtree(name).filter(move |node| !node.0.is_empty() && account::parent(&node.0).unwrap_or("") == name)
+    }
+
+    /// Balance of `name` including all its descendants
+    pub fn balance_children(&self, name: &str) -> CounterInventory {
+        let mut balance = CounterInventory::new();
+        for node in self.subtree(name) {
+            inventory::add_inventory(&mut balance, &node.1);
+        }
+        balance
+    }
+
+    /// Net profit: the balance of all income and expense accounts
+    pub fn net_profit(&self, options: &BeancountOptions) -> CounterInventory {
+        let mut net_profit = self.balance_children(&options.name_income);
+        inventory::add_inventory(&mut net_profit, &self.balance_children(&options.name_expenses));
+        net_profit
+    }
+
+    /// Close the books: transfer net profit and conversions into equity
+    ///
+    /// Afterwards the assets, liabilities and equity accounts balance to zero.
+    pub fn cap(&mut self, options: &BeancountOptions) {
+        let conversions = inventory::negate(&self.balance_children(""));
+        self.insert(&options.current_conversions(), &conversions);
+
+        let net_profit = self.net_profit(options);
+        self.insert(&options.current_earnings(), &net_profit);
+    }
+
+    /// Nested serialisation of `name` and its descendants, if `name` is in the tree
+    pub fn serialise(&self, name: &str) -> Option<SerialisedTreeNode> {
+        let node = self.get(name)?;
+        Some(SerialisedTreeNode {
+            account: node.get_name(),
+            balance: node.get_balance(),
+            balance_children: self.balance_children(name),
+            children: self.children(name).filter_map(|child| self.serialise(&child.0)).collect(),
+        })
+    }
+}
+
+/// Options of the nested tree for a hierarchy chart
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct HierarchyOptions {
+    /// drop the nodes without a balance, including their descendants
+    pub prune_zero: bool,
+    /// the deepest account level to include, e.g. `2` for `Expenses:Food`
+    pub max_depth: Option<usize>,
+    /// only keep the balances in this currency
+    pub currency: Option<String>,
+}
+
+/// A tree node with the balances of itself and its descendants, ready to be sent as JSON
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct SerialisedTreeNode {
+    pub account: String,
+    pub balance: CounterInventory,
+    pub balance_children: CounterInventory,
+    pub children: Vec<SerialisedTreeNode>,
+}
+
+impl SerialisedTreeNode {
+    /// Flip the sign of all balances, e.g. to show income as positive numbers
+    pub fn invert(self) -> Self {
+        Self {
+            account: self.account,
+            balance: inventory::negate(&self.balance),
+            balance_children: inventory::negate(&self.balance_children),
+            children: self.children.into_iter().map(Self::invert).collect(),
+        }
+    }
+
+    /// Restrict the node and its descendants to what a hierarchy chart shows
+    ///
+    /// The balances of nodes below `max_depth` still count towards the
+    /// balance with children of their ancestors.
+    pub fn chart(self, options: &HierarchyOptions) -> Self {
+        let select = |balance: CounterInventory| match &options.currency {
+            Some(currency) => balance.into_iter().filter(|(other, _)| other == currency).collect(),
+            None => balance,
+        };
+        let depth = if self.account.is_empty() { 0 } else { self.account.split(':').count() };
+        let children = if options.max_depth.is_some_and(|max_depth| depth >= max_depth) {
+            Vec::new()
+        } else {
+            self.children
+                .into_iter()
+                .map(|child| child.chart(options))
+                .filter(|child| !options.prune_zero || !child.balance_children.is_empty())
+                .collect()
+        };
+        Self {
+            account: self.account,
+            balance: select(self.balance),
+            balance_children: select(self.balance_children),
+            children,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::beans::abc::{Open, Posting, Transaction};
+    use crate::beans::flags::Flags;
+    use time::macros::date;
+
+    fn entries() -> Vec<Directive> {
+        vec![
+            Directive::Open(Open::new(date!(2020-01-01), "Assets:Cash")),
+            Directive::Open(Open::new(date!(2020-01-01), "Expenses:Food:Groceries")),
+            Directive::Transactions(Transaction::new(
+                date!(2020-01-02),
+                Flags::Okay,
+                "Groceries",
+                vec![
+                    Posting::new("Expenses:Food:Groceries", AAmount(20., "USD".into())),
+                    Posting::new("Expenses:Food", AAmount(5., "USD".into())),
+                    Posting::new("Assets:Cash", AAmount(-25., "USD".into())),
+                ],
+            )),
+        ]
+    }
+
+    #[test]
+    fn inserts_ancestors() {
+        let tree = Tree::new(&entries());
+        let names: Vec<String> = tree.nodes().map(TreeNode::get_name).collect();
+        assert_eq!(names, vec!["", "Assets", "Assets:Cash", "Expenses", "Expenses:Food", "Expenses:Food:Groceries"]);
+    }
+
+    #[test]
+    fn balances() {
+        let tree = Tree::new(&entries());
+        assert_eq!(tree.get("Expenses:Food").unwrap().get_balance(), CounterInventory::from([("USD".into(), 5.)]));
+        assert_eq!(tree.balance_children("Expenses"), CounterInventory::from([("USD".into(), 25.)]));
+        assert_eq!(tree.balance_children("Assets"), CounterInventory::from([("USD".into(), -25.)]));
+        assert!(tree.balance_children("").is_empty());
+        assert!(tree.get("Income").is_none());
+    }
+
+    #[test]
+    fn children() {
+        let tree = Tree::new(&entries());
+        let names = |name| tree.children(name).map(TreeNode::get_name).collect::<Vec<_>>();
+        assert_eq!(names(""), vec!["Assets", "Expenses"]);
+        assert_eq!(names("Expenses"), vec!["Expenses:Food"]);
+        assert!(names("Assets:Cash").is_empty());
+    }
+
+    #[test]
+    fn filtered() {
+        let tree = Tree::new_filtered(&entries(), |name| account::is_descendant(name, "Expenses"));
+        assert!(tree.get("Assets").is_none());
+        assert_eq!(tree.balance_children(""), CounterInventory::from([("USD".into(), 25.)]));
+    }
+
+    #[test]
+    fn capped() {
+        let mut entries = entries();
+        entries.push(Directive::Transactions(Transaction::new(
+            date!(2020-01-03),
+            Flags::Okay,
+            "Exchange",
+            vec![Posting::new("Assets:Cash", AAmount(-10., "USD".into())), Posting::new("Assets:Cash", AAmount(9., "EUR".into()))],
+        )));
+        let options = BeancountOptions::default();
+        let mut tree = Tree::new(&entries);
+        assert_eq!(tree.net_profit(&options), CounterInventory::from([("USD".into(), 25.)]));
+
+        tree.cap(&options);
+        assert_eq!(
+            tree.get("Equity:Conversions:Current").unwrap().get_balance(),
+            CounterInventory::from([("USD".into(), 10.), ("EUR".into(), -9.)])
+        );
+        assert_eq!(tree.get("Equity:Earnings:Current").unwrap().get_balance(), CounterInventory::from([("USD".into(), 25.)]));
+
+        let mut total = tree.balance_children("Assets");
+        inventory::add_inventory(&mut total, &tree.balance_children("Liabilities"));
+        inventory::add_inventory(&mut total, &tree.balance_children("Equity"));
+        assert!(total.is_empty());
+    }
+
+    #[test]
+    fn serialised() {
+        let tree = Tree::new(&entries());
+        let node = tree.serialise("Expenses").unwrap();
+        assert_eq!(node.account, "Expenses");
+        assert!(node.balance.is_empty());
+        assert_eq!(node.balance_children, CounterInventory::from([("USD".into(), 25.)]));
+        assert_eq!(node.children.len(), 1);
+        assert_eq!(node.children[0].children[0].account, "Expenses:Food:Groceries");
+
+        let inverted = node.invert();
+        assert_eq!(inverted.children[0].balance, CounterInventory::from([("USD".into(), -5.)]));
+        assert!(tree.serialise("Income").is_none());
+    }
+
+    #[test]
+    fn charted() {
+        let mut entries = entries();
+        entries.push(Directive::Open(Open::new(date!(2020-01-01), "Expenses:Rent")));
+        entries.push(Directive::Transactions(Transaction::new(
+            date!(2020-01-03),
+            Flags::Okay,
+            "Bakery",
+            vec![Posting::new("Expenses:Food", AAmount(3., "EUR".into())), Posting::new("Assets:Cash", AAmount(-3., "EUR".into()))],
+        )));
+        let tree = Tree::new(&entries);
+        let chart = |options| tree.serialise("Expenses").unwrap().chart(&options);
+        let names = |node: &SerialisedTreeNode| node.children.iter().map(|child| child.account.clone()).collect::<Vec<_>>();
+
+        let all = chart(HierarchyOptions::default());
+        assert_eq!(names(&all), vec!["Expenses:Food", "Expenses:Rent"]);
+        assert_eq!(all.balance_children, CounterInventory::from([("USD".into(), 25.), ("EUR".into(), 3.)]));
+
+        let pruned = chart(HierarchyOptions {
+            prune_zero: true,
+            max_depth: Some(2),
+            currency: Some("USD".into()),
+        });
+        assert_eq!(names(&pruned), vec!["Expenses:Food"]);
+        let food = &pruned.children[0];
+        assert!(food.children.is_empty());
+        assert_eq!(food.balance, CounterInventory::from([("USD".into(), 5.)]));
+        assert_eq!(food.balance_children, CounterInventory::from([("USD".into(), 25.)]));
+
+        // a currency that only some accounts hold
+        let euros = chart(HierarchyOptions {
+            prune_zero: true,
+            max_depth: None,
+            currency: Some("EUR".into()),
+        });
+        assert_eq!(names(&euros), vec!["Expenses:Food"]);
+        assert!(euros.children[0].children.is_empty());
+    }
+}
@@ -0,0 +1,504 @@
+//! Server-rendered HTML pages
+//!
+//! `/` lists the ledgers, the pages of a ledger are under `/<slug>/`, like
+//! `/<slug>/journal/`. They are rendered with the templates in `templates/`,
+//! from the same report builders as the API, and need the same roles.
+//!
+//! see: https://github.com/beancount/fava/blob/main/src/fava/application.py
+
+use askama::Template;
+use percent_encoding::percent_decode_str;
+use worker::Method;
+
+use crate::Helpers;
+use crate::auth::Role;
+use crate::beans::abc::{Directive, Entry};
+use crate::beans::funcs::entry_type;
+use crate::beans::str::amount_to_string;
+use crate::core::FavaLedger;
+use crate::core::accounts::Status;
+use crate::core::file;
+use crate::core::inventory::CounterInventory;
+use crate::core::journal::account_journal;
+use crate::core::reports::{BalanceSheet, IncomeStatement};
+use crate::core::tree::SerialisedTreeNode;
+use crate::json_api::{ApiError, ApiRequest, authorize};
+use crate::registry::{LedgerRegistry, LedgerSummary};
+use crate::storage::Storage;
+
+/// A rendered page, or a redirect
+#[derive(Debug, PartialEq)]
+pub(crate) struct HtmlResponse {
+    pub status: u16,
+    pub body: String,
+    /// where to redirect to, relative to the site
+    pub location: Option<String>,
+}
+
+type PageResult = Result<HtmlResponse, ApiError>;
+
+fn render(status: u16, template: &impl Template) -> PageResult {
+    let body = template.render().map_err(|error| Helpers::FavaError(format!("Failed to render page: {error}")))?;
+    Ok(HtmlResponse { status, body, location: None })
+}
+
+fn redirect(location: String) -> PageResult {
+    Ok(HtmlResponse {
+        status: 303,
+        body: String::new(),
+        location: Some(location),
+    })
+}
+
+/// The amounts of an inventory, sorted by currency, one per line
+fn format_inventory(inventory: &CounterInventory) -> String {
+    let mut currencies: Vec<&String> = inventory.keys().collect();
+    currencies.sort();
+    currencies.into_iter().map(|currency| format!("{} {currency}", inventory[currency])).collect::<Vec<_>>().join("\n")
+}
+
+/// What the navigation of every ledger page shows
+struct Layout<'a> {
+    slug: &'a str,
+    title: &'a str,
+    errors: usize,
+}
+
+impl<'a> Layout<'a> {
+    fn new(slug: &'a str, ledger: &'a FavaLedger) -> Self {
+        Self {
+            slug,
+            title: &ledger.options.title,
+            errors: ledger.errors.len(),
+        }
+    }
+}
+
+/// An account of a balance tree, indented by its depth
+struct TreeRow {
+    account: String,
+    depth: usize,
+    balance: String,
+    balance_children: String,
+}
+
+/// The rows of `node` and its descendants, depth first
+fn tree_rows(node: &SerialisedTreeNode) -> Vec<TreeRow> {
+    fn add(node: &SerialisedTreeNode, depth: usize, rows: &mut Vec<TreeRow>) {
+        rows.push(TreeRow {
+            account: node.account.clone(),
+            depth,
+            balance: format_inventory(&node.balance),
+            balance_children: format_inventory(&node.balance_children),
+        });
+        for child in &node.children {
+            add(child, depth + 1, rows);
+        }
+    }
+    let mut rows = Vec::new();
+    add(node, 0, &mut rows);
+    rows
+}
+
+struct Section {
+    name: String,
+    rows: Vec<TreeRow>,
+}
+
+impl Section {
+    fn new(node: &SerialisedTreeNode) -> Self {
+        Self {
+            name: node.account.clone(),
+            rows: tree_rows(node),
+        }
+    }
+}
+
+#[derive(Template)]
+#[template(path = "ledgers.html")]
+struct LedgersPage<'a> {
+    ledgers: Vec<LedgerSummary<'a>>,
+}
+
+#[derive(Template)]
+#[template(path = "error.html")]
+struct ErrorPage {
+    status: u16,
+    message: String,
+}
+
+/// Balance sheet or income statement
+#[derive(Template)]
+#[template(path = "report.html")]
+struct ReportPage<'a> {
+    layout: Layout<'a>,
+    heading: &'static str,
+    sections: Vec<Section>,
+    totals: Vec<(&'static str, String)>,
+}
+
+/// An entry of a journal
+struct JournalEntry {
+    date: time::Date,
+    kind: &'static str,
+    flag: &'static str,
+    description: String,
+    amount: String,
+    /// account and units of every posting
+    postings: Vec<(String, String)>,
+}
+
+impl JournalEntry {
+    fn new(entry: &Directive) -> Self {
+        let (flag, amount, postings) = match entry {
+            Directive::Transactions(transaction) => {
+                let postings = transaction.postings.iter().map(|posting| (posting.account.clone(), amount_to_string(&posting.units))).collect();
+                (transaction.flag.as_str(), String::new(), postings)
+            }
+            Directive::Balance(balance) => ("", amount_to_string(&balance.amount), Vec::new()),
+            Directive::Price(price) => ("", amount_to_string(&price.amount), Vec::new()),
+            _ => ("", String::new(), Vec::new()),
+        };
+        Self {
+            date: entry.get_date(),
+            kind: entry_type(entry),
+            flag,
+            description: description(entry),
+            amount,
+            postings,
+        }
+    }
+}
+
+/// A one-line description of the entry
+fn description(entry: &Directive) -> String {
+    match entry {
+        Directive::Open(open) => open.account.clone(),
+        Directive::Close(close) => close.account.clone(),
+        Directive::Commodity(commodity) => commodity.currency.clone(),
+        Directive::Transactions(transaction) => {
+            let mut description = match &transaction.payee {
+                Some(payee) => format!("{payee} | {}", transaction.narration),
+                None => transaction.narration.clone(),
+            };
+            for tag in &transaction.tags {
+                description.push_str(&format!(" #{tag}"));
+            }
+            for link in &transaction.links {
+                description.push_str(&format!(" ^{link}"));
+            }
+            description
+        }
+        Directive::Note(note) => format!("{}: {}", note.account, note.comment),
+        Directive::Balance(balance) => balance.account.clone(),
+        Directive::Pad(pad) => format!("{} from {}", pad.account, pad.source_account),
+        Directive::Document(document) => format!("{}: {}", document.account, document.filename),
+        Directive::Event(event) => format!("{}: {}", event.r#type, event.description),
+        Directive::Price(price) => price.currency.clone(),
+        Directive::Query(query) => query.name.clone(),
+        Directive::Custom(custom) => custom.r#type.clone(),
+    }
+}
+
+#[derive(Template)]
+#[template(path = "journal.html")]
+struct JournalPage<'a> {
+    layout: Layout<'a>,
+    entries: Vec<JournalEntry>,
+}
+
+/// An entry of an account's journal, with the change of the account's balance
+struct AccountEntry {
+    date: time::Date,
+    kind: &'static str,
+    description: String,
+    change: String,
+    balance: String,
+    /// whether it is a failed balance check
+    failed: bool,
+}
+
+#[derive(Template)]
+#[template(path = "account.html")]
+struct AccountPage<'a> {
+    layout: Layout<'a>,
+    account: &'a str,
+    /// the balances of the account and its descendants
+    rows: Vec<TreeRow>,
+    entries: Vec<AccountEntry>,
+}
+
+#[derive(Template)]
+#[template(path = "errors.html")]
+struct ErrorsPage<'a> {
+    layout: Layout<'a>,
+    errors: Vec<String>,
+}
+
+#[derive(Template)]
+#[template(path = "query.html")]
+struct QueryPage<'a> {
+    layout: Layout<'a>,
+    query_string: &'a str,
+    /// name and query string of the stored queries
+    queries: Vec<(&'a str, &'a str)>,
+}
+
+#[derive(Template)]
+#[template(path = "editor.html")]
+struct EditorPage<'a> {
+    layout: Layout<'a>,
+    /// every source file, and whether it is the edited one
+    files: Vec<(&'a str, bool)>,
+    file_path: &'a str,
+    source: &'a str,
+    sha256sum: &'a str,
+    error: Option<String>,
+    read_only: bool,
+}
+
+impl<'a> EditorPage<'a> {
+    fn new(layout: Layout<'a>, ledger: &'a FavaLedger, file_path: &'a str, source: &'a str, sha256sum: &'a str, role: Option<Role>) -> Self {
+        Self {
+            layout,
+            files: ledger.sources.keys().map(|file| (file.as_str(), file == file_path)).collect(),
+            file_path,
+            source,
+            sha256sum,
+            error: None,
+            read_only: role < Some(Role::ReadWrite),
+        }
+    }
+}
+
+/// Handle a request to a page, errors are rendered as a page too
+pub(crate) async fn route(registry: &LedgerRegistry, storage: &impl Storage, request: &ApiRequest<'_>) -> HtmlResponse {
+    dispatch(registry, storage, request).await.unwrap_or_else(|ApiError { status, error }| {
+        let page = ErrorPage {
+            status,
+            message: error.to_string(),
+        };
+        render(status, &page).unwrap_or_else(|error| HtmlResponse {
+            status: 500,
+            body: error.error.to_string(),
+            location: None,
+        })
+    })
+}
+
+async fn dispatch(registry: &LedgerRegistry, storage: &impl Storage, request: &ApiRequest<'_>) -> PageResult {
+    authorize(request)?;
+    let path = request.path.trim_matches('/');
+    let (slug, page) = path.split_once('/').unwrap_or((path, ""));
+    // only the editor saves, with a form
+    if request.method != Method::Get && !(page == "editor" && request.method == Method::Post) {
+        return Err(ApiError {
+            status: 405,
+            error: Helpers::FavaError(format!("Method {} not allowed for {}", request.method, request.path)),
+        });
+    }
+    if slug.is_empty() {
+        return render(200, &LedgersPage { ledgers: registry.index() });
+    }
+    let ledger = registry.get(slug).ok_or_else(|| ApiError::not_found(format!("No such ledger: {slug}")))?;
+    let layout = Layout::new(slug, ledger);
+
+    match page {
+        "" => redirect(format!("/{slug}/income_statement/")),
+        "balance_sheet" => {
+            let report = BalanceSheet::new(ledger.root_tree(), &ledger.options);
+            let page = ReportPage {
+                layout,
+                heading: "Balance Sheet",
+                sections: [&report.assets, &report.liabilities, &report.equity].into_iter().map(Section::new).collect(),
+                totals: vec![("Net Profit", format_inventory(&report.net_profit)), ("Totals", format_inventory(&report.totals))],
+            };
+            render(200, &page)
+        }
+        "income_statement" => {
+            let report = IncomeStatement::new(ledger.root_tree(), &ledger.options);
+            let page = ReportPage {
+                layout,
+                heading: "Income Statement",
+                sections: [&report.income, &report.expenses].into_iter().map(Section::new).collect(),
+                totals: vec![("Net Profit", format_inventory(&report.net_profit))],
+            };
+            render(200, &page)
+        }
+        "journal" => render(
+            200,
+            &JournalPage {
+                layout,
+                entries: ledger.entries.iter().map(JournalEntry::new).collect(),
+            },
+        ),
+        "errors" => render(
+            200,
+            &ErrorsPage {
+                layout,
+                errors: ledger.errors.iter().map(Helpers::to_string).collect(),
+            },
+        ),
+        "query" => {
+            let queries = ledger
+                .entries
+                .iter()
+                .filter_map(|entry| match entry {
+                    Directive::Query(query) => Some((query.name.as_str(), query.query_string.as_str())),
+                    _ => None,
+                })
+                .collect();
+            let query_string = request.params.get("query_string").map(String::as_str).unwrap_or_default();
+            render(200, &QueryPage { layout, query_string, queries })
+        }
+        "editor" if request.method == Method::Post => {
+            let form: std::collections::HashMap<String, String> = url::form_urlencoded::parse(&request.body).into_owned().collect();
+            let field = |name: &str| form.get(name).map(String::as_str).ok_or_else(|| Helpers::FavaError(format!("Missing form field: {name}")));
+            let file_path = field("file_path")?;
+            // browsers submit text areas with CRLF line endings
+            let source = field("source")?.replace("\r\n", "\n");
+            let sha256sum = field("sha256sum")?;
+            match file::set_source(storage, ledger, file_path, &source, sha256sum).await {
+                Ok(_) => {
+                    let file_path: String = url::form_urlencoded::byte_serialize(file_path.as_bytes()).collect();
+                    redirect(format!("/{slug}/editor/?file_path={file_path}"))
+                }
+                Err(error) => {
+                    let ApiError { status, error } = error.into();
+                    let mut page = EditorPage::new(layout, ledger, file_path, &source, sha256sum, request.role);
+                    page.error = Some(error.to_string());
+                    render(status, &page)
+                }
+            }
+        }
+        "editor" => {
+            let file_path = request.params.get("file_path").unwrap_or(&ledger.filename);
+            let (source, sha256sum) = file::get_source(storage, ledger, file_path).await?;
+            render(200, &EditorPage::new(layout, ledger, file_path, &source, &sha256sum, request.role))
+        }
+        _ => {
+            let Some(account) = page.strip_prefix("account/") else {
+                return Err(ApiError::not_found(format!("No such page: {}", request.path)));
+            };
+            let account = percent_decode_str(account).decode_utf8_lossy();
+            let node = ledger.root_tree().serialise(&account).ok_or_else(|| ApiError::not_found(format!("No such account: {account}")))?;
+            let entries = account_journal(&ledger.entries, &account, true)
+                .into_iter()
+                .map(|row| AccountEntry {
+                    date: row.entry.get_date(),
+                    kind: entry_type(row.entry),
+                    description: description(row.entry),
+                    change: format_inventory(&row.change),
+                    balance: format_inventory(&row.balance),
+                    failed: row.status == Some(Status::Fail),
+                })
+                .collect();
+            let page = AccountPage {
+                layout,
+                account: &account,
+                rows: tree_rows(&node),
+                entries,
+            };
+            render(200, &page)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::LedgerCache;
+    use crate::core::file::sha256_str;
+    use crate::storage::{MemoryStorage, block_on};
+
+    const SOURCE: &str = r#"option "title" "Example"
+2020-01-01 open Assets:Cash
+2020-01-01 open Expenses:Food
+2020-01-02 * "Shop" "Groceries" #food
+  Expenses:Food  20 USD
+  Assets:Cash
+2020-01-03 balance Assets:Cash  0 USD
+2020-01-04 query "food" "SELECT * WHERE account ~ 'Food'"
+"#;
+
+    fn call(storage: &MemoryStorage, method: Method, path: &str, params: &[(&str, &str)], body: &str, role: Option<Role>) -> HtmlResponse {
+        let now = time::macros::datetime!(2020-01-01 0:00 UTC);
+        let registry = block_on(LedgerRegistry::load(storage, &LedgerCache::default(), &["main.beancount".to_string()], now));
+        let request = ApiRequest {
+            method,
+            path,
+            params: params.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect(),
+            body: body.as_bytes().to_vec(),
+            upload: None,
+            role,
+        };
+        block_on(route(&registry, storage, &request))
+    }
+
+    fn get(storage: &MemoryStorage, path: &str) -> HtmlResponse {
+        call(storage, Method::Get, path, &[], "", Some(Role::ReadOnly))
+    }
+
+    #[test]
+    fn pages() {
+        let storage = MemoryStorage::new([("main.beancount", SOURCE)]);
+        assert!(get(&storage, "/").body.contains(r#"<a href="/example/">Example</a> (1 errors)"#));
+        assert_eq!(get(&storage, "/example").location.as_deref(), Some("/example/income_statement/"));
+
+        let income_statement = get(&storage, "/example/income_statement/");
+        assert_eq!(income_statement.status, 200);
+        assert!(income_statement.body.contains("<h2>Expenses</h2>"));
+        assert!(income_statement.body.contains("-20 USD"));
+        assert!(get(&storage, "/example/balance_sheet/").body.contains("Assets:Cash"));
+
+        let journal = get(&storage, "/example/journal/").body;
+        assert!(journal.contains("Shop | Groceries #food"));
+        assert!(journal.contains(r#"<a href="/example/account/Expenses%3AFood/">Expenses:Food</a>"#));
+
+        let account = get(&storage, "/example/account/Assets%3ACash/");
+        assert!(account.body.contains("<h1>Assets:Cash</h1>"));
+        assert!(account.body.contains(r#"<td class="error">Assets:Cash</td>"#));
+        assert_eq!(get(&storage, "/example/account/Income/").status, 404);
+
+        assert!(get(&storage, "/example/errors/").body.contains("Balance failed for &#39;Assets:Cash&#39;"));
+        let query = get(&storage, "/example/query/").body;
+        assert!(query.contains("SELECT * WHERE account ~ &#39;Food&#39;"));
+        assert!(query.contains("/example/query/?query_string=SELECT%20%2A"));
+    }
+
+    #[test]
+    fn editor() {
+        let storage = MemoryStorage::new([("main.beancount", SOURCE)]);
+        let page = get(&storage, "/example/editor/");
+        assert!(page.body.contains(&format!(r#"value="{}""#, sha256_str(SOURCE))));
+        assert!(page.body.contains("Read-only access"));
+
+        let form = |source: &str, sha256sum: &str| {
+            url::form_urlencoded::Serializer::new(String::new())
+                .append_pair("file_path", "main.beancount")
+                .append_pair("source", source)
+                .append_pair("sha256sum", sha256sum)
+                .finish()
+        };
+        let saved = call(&storage, Method::Post, "/example/editor/", &[], &form("option \"title\" \"Example\"\r\n", &sha256_str(SOURCE)), Some(Role::ReadWrite));
+        assert_eq!(saved.location.as_deref(), Some("/example/editor/?file_path=main.beancount"));
+        assert_eq!(block_on(storage.get("main.beancount")).unwrap().unwrap().contents, b"option \"title\" \"Example\"\n");
+
+        // the file changed since the page was loaded
+        let conflict = call(&storage, Method::Post, "/example/editor/", &[], &form("", &sha256_str(SOURCE)), Some(Role::ReadWrite));
+        assert_eq!(conflict.status, 409);
+        assert!(conflict.body.contains(r#"<p class="error">"#));
+    }
+
+    #[test]
+    fn errors() {
+        let storage = MemoryStorage::new([("main.beancount", SOURCE)]);
+        assert_eq!(get(&storage, "/missing/journal/").status, 404);
+        assert_eq!(get(&storage, "/example/holdings/").status, 404);
+        assert_eq!(call(&storage, Method::Post, "/example/journal/", &[], "", Some(Role::ReadWrite)).status, 405);
+        assert_eq!(call(&storage, Method::Post, "/example/editor/", &[], "", Some(Role::ReadOnly)).status, 403);
+        let denied = call(&storage, Method::Get, "/example/journal/", &[], "", None);
+        assert_eq!(denied.status, 401);
+        assert!(denied.body.contains("Authentication required"));
+    }
+}
@@ -0,0 +1,836 @@
+//! JSON API
+//!
+//! Routes `/<slug>/api/<endpoint>` to the handlers of the ledger with that
+//! slug, and `/api/ledgers` to the index of all ledgers. All reply with
+//! `{"success": true, "data": ...}` or `{"success": false, "error": ...}`.
+//!
+//! see: https://github.com/beancount/fava/blob/main/src/fava/json_api.py
+
+use std::collections::{BTreeSet, HashMap};
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use worker::Method;
+
+use crate::Helpers;
+use crate::auth::Role;
+use crate::beans::abc::{Directive, Entry, MetaValue};
+use crate::beans::funcs::hash_entry;
+use crate::beans::parser::parse_date;
+use crate::core::FavaLedger;
+use crate::core::conversion::Conversion;
+use crate::core::filters::Filters;
+use crate::core::{charts, context, documents, events, file, tags};
+use crate::core::holdings::{self, GroupBy};
+use crate::core::journal::account_journal;
+use crate::core::reports::{BalanceSheet, IncomeStatement, TrialBalance};
+use crate::core::statistics::Statistics;
+use crate::core::tree::HierarchyOptions;
+use crate::registry::LedgerRegistry;
+use crate::serialisation;
+use crate::storage::{Storage, Upload};
+use crate::util::date::Interval;
+
+/// Query string parameters
+pub(crate) type Params = HashMap<String, String>;
+
+/// A request to the API, or to a page
+#[derive(Debug)]
+pub(crate) struct ApiRequest<'a> {
+    pub method: Method,
+    pub path: &'a str,
+    pub params: Params,
+    /// JSON body of `PUT` and `POST` requests, or a submitted form
+    pub body: Vec<u8>,
+    /// contents of an uploaded document, for the paths of [`is_upload_path`]
+    pub upload: Option<Upload>,
+    /// role of the client, `None` if it is not authenticated
+    pub role: Option<Role>,
+}
+
+impl ApiRequest<'_> {
+    pub fn param(&self, name: &str) -> Result<&str, ApiError> {
+        self.params
+            .get(name)
+            .map(String::as_str)
+            .ok_or_else(|| Helpers::FavaError(format!("Missing parameter: {name}")).into())
+    }
+
+    fn json<T: DeserializeOwned>(&self) -> Result<T, ApiError> {
+        serde_json::from_slice(&self.body).map_err(|error| Helpers::FavaError(format!("Invalid request body: {error}")).into())
+    }
+
+    /// The `interval` parameter, monthly by default
+    fn interval(&self) -> Result<Interval, ApiError> {
+        Ok(self.params.get("interval").map(|interval| interval.parse()).transpose()?.unwrap_or(Interval::Month))
+    }
+
+    /// The `conversion` parameter, at cost by default
+    fn conversion(&self) -> Result<Conversion, ApiError> {
+        Ok(self.params.get("conversion").map(|conversion| conversion.parse()).transpose()?.unwrap_or_default())
+    }
+
+    /// The `time` and `account` filters
+    fn filters(&self) -> Result<Filters, ApiError> {
+        let param = |name| self.params.get(name).map(String::as_str);
+        Ok(Filters::new(param("time"), param("account"))?)
+    }
+
+    fn text(&self) -> Result<&str, ApiError> {
+        std::str::from_utf8(&self.body).map_err(|error| Helpers::FavaError(format!("Invalid request body: {error}")).into())
+    }
+}
+
+/// Status and JSON body of a reply
+#[derive(Debug, PartialEq)]
+pub(crate) struct ApiResponse {
+    pub status: u16,
+    pub body: serde_json::Value,
+    /// a document to send instead of the JSON body
+    pub file: Option<Download>,
+}
+
+/// A document to download, streamed from the storage by the Worker
+#[derive(Debug, PartialEq)]
+pub(crate) struct Download {
+    pub path: String,
+    pub filename: String,
+    pub content_type: &'static str,
+    /// whether browsers may show it, see [`documents::is_inline`]
+    pub inline: bool,
+}
+
+/// Data of a successful reply
+#[derive(Debug)]
+enum Reply {
+    Json(serde_json::Value),
+    File(Download),
+}
+
+/// An error reply
+#[derive(Debug, PartialEq)]
+pub(crate) struct ApiError {
+    pub status: u16,
+    pub error: Helpers,
+}
+
+impl ApiError {
+    pub fn not_found(message: String) -> Self {
+        Self {
+            status: 404,
+            error: Helpers::FavaError(message),
+        }
+    }
+}
+
+impl From<Helpers> for ApiError {
+    fn from(error: Helpers) -> Self {
+        let status = match error {
+            Helpers::NonSourceFileError(_) | Helpers::EntryNotFoundForHashError(_) | Helpers::StatementNotFoundError(_) => 404,
+            Helpers::ExternallyChangedError(_) => 409,
+            Helpers::BeancountError(_) | Helpers::FavaError(_) => 400,
+        };
+        Self { status, error }
+    }
+}
+
+type ApiResult = Result<Reply, ApiError>;
+
+fn to_json(data: impl Serialize) -> ApiResult {
+    serde_json::to_value(data).map(Reply::Json).map_err(|error| Helpers::FavaError(error.to_string()).into())
+}
+
+/// Reply with the document at `path`
+fn download(path: &str) -> ApiResult {
+    let content_type = documents::content_type(path);
+    Ok(Reply::File(Download {
+        path: path.to_string(),
+        filename: path.rsplit('/').next().unwrap_or(path).to_string(),
+        content_type,
+        inline: documents::is_inline(content_type),
+    }))
+}
+
+/// The methods an endpoint accepts, `None` for unknown endpoints
+fn endpoint_methods(endpoint: &str) -> Option<&'static [Method]> {
+    match endpoint {
+        "ledger_data" | "errors" | "balance_sheet" | "income_statement" | "trial_balance" | "journal" | "account_journal"
+        | "holdings" | "statistics" | "queries" | "source_files" | "net_worth" | "account_balance" | "interval_totals"
+        | "hierarchy" | "commodities" | "autocomplete" | "payee_accounts" | "payee_transaction"
+        | "context" | "events" | "tags" | "links" | "linked_entries" => Some(&[Method::Get]),
+        "source" | "source_slice" => Some(&[Method::Get, Method::Put]),
+        "format_source" | "add_document" => Some(&[Method::Put]),
+        "document" | "statement" => Some(&[Method::Get]),
+        "add_entry" => Some(&[Method::Post]),
+        "ledgers" => Some(&[Method::Get]),
+        _ => None,
+    }
+}
+
+/// Whether the path is one of the API, `/api/...` or `/<slug>/api/...`
+pub(crate) fn is_api_path(path: &str) -> bool {
+    path.trim_start_matches('/').split('/').take(2).any(|segment| segment == "api")
+}
+
+/// Whether the request body is a document to write to the storage, rather than to read into memory
+pub(crate) fn is_upload_path(path: &str) -> bool {
+    parse_path(path).is_some_and(|(_, endpoint)| endpoint == "add_document")
+}
+
+/// Split a path into the optional ledger slug and the endpoint
+fn parse_path(path: &str) -> Option<(Option<&str>, &str)> {
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    match segments.as_slice() {
+        ["api", endpoint] => Some((None, endpoint)),
+        [slug, "api", endpoint] => Some((Some(slug), endpoint)),
+        _ => None,
+    }
+}
+
+/// Handle a request to the API
+pub(crate) async fn route(registry: &LedgerRegistry, storage: &impl Storage, request: &ApiRequest<'_>) -> ApiResponse {
+    match dispatch(registry, storage, request).await {
+        Ok(Reply::Json(data)) => ApiResponse {
+            status: 200,
+            body: serde_json::json!({ "success": true, "data": data }),
+            file: None,
+        },
+        Ok(Reply::File(file)) => ApiResponse {
+            status: 200,
+            body: serde_json::Value::Null,
+            file: Some(file),
+        },
+        Err(error) => error_response(error),
+    }
+}
+
+/// Reply with an error
+pub(crate) fn error_response(ApiError { status, error }: ApiError) -> ApiResponse {
+    ApiResponse {
+        status,
+        body: serde_json::json!({ "success": false, "error": error.to_string() }),
+        file: None,
+    }
+}
+
+/// Check that the client may make the request: `GET` requests need read-only access, all others read-write access
+pub(crate) fn authorize(request: &ApiRequest<'_>) -> Result<(), ApiError> {
+    // all endpoints and pages that change files take `PUT` or `POST` requests
+    let required = if request.method == Method::Get { Role::ReadOnly } else { Role::ReadWrite };
+    match request.role {
+        None => Err(ApiError {
+            status: 401,
+            error: Helpers::FavaError("Authentication required".into()),
+        }),
+        Some(role) if role < required => Err(ApiError {
+            status: 403,
+            error: Helpers::FavaError("Read-only access".into()),
+        }),
+        Some(_) => Ok(()),
+    }
+}
+
+async fn dispatch(registry: &LedgerRegistry, storage: &impl Storage, request: &ApiRequest<'_>) -> ApiResult {
+    authorize(request)?;
+
+    let Some((slug, endpoint)) = parse_path(request.path) else {
+        return Err(ApiError::not_found(format!("No such route: {}", request.path)));
+    };
+    let methods = endpoint_methods(endpoint).ok_or_else(|| ApiError::not_found(format!("No such endpoint: {endpoint}")))?;
+    if !methods.contains(&request.method) {
+        return Err(ApiError {
+            status: 405,
+            error: Helpers::FavaError(format!("Method {} not allowed for {endpoint}", request.method)),
+        });
+    }
+
+    let Some(slug) = slug else {
+        return match endpoint {
+            "ledgers" => to_json(registry.index()),
+            _ => Err(ApiError::not_found(format!("No such route: {}", request.path))),
+        };
+    };
+    let ledger = registry.get(slug).ok_or_else(|| ApiError::not_found(format!("No such ledger: {slug}")))?;
+
+    let params = &request.params;
+    match endpoint {
+        "ledger_data" => to_json(LedgerData::new(ledger)),
+        "errors" => to_json(&ledger.errors),
+        "balance_sheet" => to_json(BalanceSheet::new(ledger.root_tree(), &ledger.options)),
+        "income_statement" => to_json(IncomeStatement::new(ledger.root_tree(), &ledger.options)),
+        "trial_balance" => to_json(TrialBalance::new(ledger.root_tree())),
+        "journal" => to_json(&ledger.entries),
+        "account_journal" => {
+            let with_children = params.get("with_children").is_some_and(|value| value == "true");
+            to_json(account_journal(&ledger.entries, request.param("a")?, with_children))
+        }
+        "holdings" => {
+            let holdings = holdings::get_holdings(&ledger.entries, &ledger.prices, None);
+            match params.get("group_by") {
+                Some(group_by) => to_json(holdings::aggregate_holdings(&holdings, group_by.parse::<GroupBy>()?)),
+                None => to_json(holdings),
+            }
+        }
+        "net_worth" => {
+            let conversion = request.conversion()?;
+            let balances = charts::net_worth(ledger, request.interval()?, &conversion, &request.filters()?);
+            to_json(charts::series(ledger, &conversion, &balances))
+        }
+        "account_balance" => {
+            let conversion = request.conversion()?;
+            let balances = charts::account_balance(ledger, request.param("a")?, request.interval()?, &conversion, &request.filters()?);
+            to_json(charts::series(ledger, &conversion, &balances))
+        }
+        "interval_totals" => {
+            let invert = params.get("invert").is_some_and(|value| value == "true");
+            to_json(charts::interval_totals(
+                ledger,
+                request.param("a")?,
+                request.interval()?,
+                &request.conversion()?,
+                &request.filters()?,
+                invert,
+            ))
+        }
+        "commodities" => {
+            let implied = params.get("implied").is_some_and(|value| value == "true");
+            to_json(charts::commodity_prices(ledger, &request.filters()?, implied))
+        }
+        "hierarchy" => {
+            let max_depth = params
+                .get("depth")
+                .map(|depth| depth.parse().map_err(|_| Helpers::FavaError(format!("Invalid depth: {depth}"))))
+                .transpose()?;
+            let options = HierarchyOptions {
+                prune_zero: params.get("prune_zero").is_some_and(|value| value == "true"),
+                max_depth,
+                currency: params.get("currency").cloned(),
+            };
+            to_json(charts::hierarchy(ledger, request.param("a")?, &request.filters()?, &options))
+        }
+        "autocomplete" => {
+            let attribute = request.param("attribute")?.parse()?;
+            let mut values = ledger.attributes.complete(attribute, params.get("q").map(String::as_str).unwrap_or_default());
+            if let Some(limit) = params.get("limit") {
+                values.truncate(limit.parse().map_err(|_| Helpers::FavaError(format!("Invalid limit: {limit}")))?);
+            }
+            to_json(values)
+        }
+        "payee_accounts" => to_json(ledger.attributes.payee_accounts(request.param("payee")?)),
+        "payee_transaction" => {
+            let payee = request.param("payee")?;
+            let transaction = ledger
+                .attributes
+                .payee_transaction(payee)
+                .ok_or_else(|| ApiError::not_found(format!("No transaction with the payee {payee}")))?;
+            to_json(serialisation::transaction_template(transaction))
+        }
+        "context" => to_json(context::context(storage, ledger, request.param("entry_hash")?).await?),
+        "events" => to_json(events::event_types(&ledger.entries)),
+        "tags" => to_json(tags::tag_counts(&ledger.entries)),
+        "links" => to_json(tags::link_counts(&ledger.entries)),
+        "linked_entries" => to_json(tags::linked_entries(&ledger.entries, request.param("link")?)),
+        "statistics" => to_json(Statistics::new(&ledger.entries, &ledger.accounts, &ledger.prices)),
+        "queries" => to_json(
+            ledger
+                .entries
+                .iter()
+                .filter(|entry| matches!(entry, Directive::Query(_)))
+                .collect::<Vec<_>>(),
+        ),
+        "add_entry" => {
+            let entry = serialisation::deserialise(request.text()?)?;
+            let (filename, lineno) = file::insert_entry(storage, ledger, &entry).await?;
+            let reloaded = FavaLedger::load(storage, &ledger.filename).await;
+            let entry_hash = reloaded
+                .entries
+                .iter()
+                .find(|entry| entry.get_meta().filename == filename && entry.get_meta().lineno == lineno)
+                .map(hash_entry)
+                .ok_or_else(|| Helpers::FavaError(format!("The added entry failed to parse at {filename}:{lineno}")))?;
+            to_json(AddedEntry {
+                entry_hash,
+                errors: &reloaded.errors,
+            })
+        }
+        "add_document" => {
+            let date = request.param("date")?;
+            let date = parse_date(date).ok_or_else(|| Helpers::FavaError(format!("Invalid date: {date}")))?;
+            // check the entry before storing anything
+            let entry = params.get("hash").map(|entry_hash| ledger.get_entry(entry_hash)).transpose()?;
+            let folder = params.get("folder").map(String::as_str);
+            let contents = request.upload.as_ref().ok_or_else(|| Helpers::FavaError("Missing document".into()))?;
+            let document = documents::add_document(storage, ledger, folder, request.param("account")?, date, request.param("filename")?, contents).await?;
+            if let Some(entry) = entry {
+                let filename = document.filename.rsplit('/').next().unwrap_or_default();
+                file::insert_metadata(storage, ledger, entry, "document", &MetaValue::String(filename.to_string())).await?;
+            }
+            to_json(Directive::Document(document))
+        }
+        "document" => {
+            let filename = request.param("filename")?;
+            if !documents::is_document_file(ledger, filename) {
+                return Err(ApiError::not_found(format!("Not a document: {filename}")));
+            }
+            download(filename)
+        }
+        "statement" => {
+            let entry = ledger.get_entry(request.param("entry_hash")?)?;
+            download(&documents::statement_path(ledger, entry, request.param("key")?)?)
+        }
+        "source_files" => to_json(ledger.sources.keys().collect::<Vec<_>>()),
+        "source" if request.method == Method::Put => {
+            let SourceFile { file_path, source, sha256sum } = request.json()?;
+            let sha256sum = file::set_source(storage, ledger, &file_path, &source, &sha256sum).await?;
+            let reloaded = FavaLedger::load(storage, &ledger.filename).await;
+            to_json(SavedSource {
+                sha256sum,
+                errors: &reloaded.errors,
+            })
+        }
+        "format_source" => {
+            let FormatSource { file_path, sha256sum } = request.json()?;
+            let sha256sum = file::format_source(storage, ledger, &file_path, &sha256sum).await?;
+            let reloaded = FavaLedger::load(storage, &ledger.filename).await;
+            to_json(SavedSource {
+                sha256sum,
+                errors: &reloaded.errors,
+            })
+        }
+        "source_slice" if request.method == Method::Put => {
+            let SourceSlice { entry_hash, source, sha256sum } = request.json()?;
+            let entry = ledger.get_entry(&entry_hash)?;
+            let sha256sum = file::save_entry_slice(storage, ledger, entry, &source, &sha256sum).await?;
+            let reloaded = FavaLedger::load(storage, &ledger.filename).await;
+            to_json(SavedSource {
+                sha256sum,
+                errors: &reloaded.errors,
+            })
+        }
+        "source_slice" => {
+            let entry_hash = request.param("entry_hash")?;
+            let (source, sha256sum) = file::get_entry_slice(storage, ledger, ledger.get_entry(entry_hash)?).await?;
+            to_json(SourceSlice {
+                entry_hash: entry_hash.to_string(),
+                source,
+                sha256sum,
+            })
+        }
+        "source" => {
+            let file_path = params.get("filename").unwrap_or(&ledger.filename);
+            let (source, sha256sum) = file::get_source(storage, ledger, file_path).await?;
+            to_json(SourceFile {
+                file_path: file_path.clone(),
+                source,
+                sha256sum,
+            })
+        }
+        _ => Err(ApiError::not_found(format!("No such endpoint: {endpoint}"))),
+    }
+}
+
+/// A source file and the SHA-256 of the version it is based on
+#[derive(Debug, Serialize, Deserialize)]
+struct SourceFile {
+    file_path: String,
+    source: String,
+    sha256sum: String,
+}
+
+/// A source file to format, and the SHA-256 of the version it is based on
+#[derive(Debug, Deserialize)]
+struct FormatSource {
+    file_path: String,
+    sha256sum: String,
+}
+
+/// The source text of an entry and the SHA-256 of the version it is based on
+#[derive(Debug, Serialize, Deserialize)]
+struct SourceSlice {
+    entry_hash: String,
+    source: String,
+    sha256sum: String,
+}
+
+/// Reply to saving a source file or slice
+#[derive(Debug, Serialize)]
+struct SavedSource<'a> {
+    sha256sum: String,
+    /// errors of the ledger after the change
+    errors: &'a [Helpers],
+}
+
+/// Reply to adding an entry
+#[derive(Debug, Serialize)]
+struct AddedEntry<'a> {
+    entry_hash: String,
+    /// errors of the ledger after the change
+    errors: &'a [Helpers],
+}
+
+/// Ledger-wide data for the frontend
+#[derive(Debug, Serialize)]
+struct LedgerData<'a> {
+    title: &'a str,
+    operating_currency: &'a [String],
+    accounts: Vec<&'a str>,
+    currencies: BTreeSet<&'a str>,
+    payees: BTreeSet<&'a str>,
+    tags: BTreeSet<&'a str>,
+    links: BTreeSet<&'a str>,
+    errors: usize,
+    date_first: Option<time::Date>,
+    date_last: Option<time::Date>,
+}
+
+impl<'a> LedgerData<'a> {
+    fn new(ledger: &'a FavaLedger) -> Self {
+        let mut accounts: Vec<&str> = ledger.accounts.accounts().collect();
+        accounts.sort_unstable();
+
+        let mut currencies = BTreeSet::new();
+        let mut payees = BTreeSet::new();
+        let mut tags = BTreeSet::new();
+        let mut links = BTreeSet::new();
+        for entry in &ledger.entries {
+            match entry {
+                Directive::Commodity(commodity) => {
+                    currencies.insert(commodity.currency.as_str());
+                }
+                Directive::Transactions(transaction) => {
+                    payees.extend(transaction.payee.as_deref());
+                    tags.extend(transaction.tags.iter().map(String::as_str));
+                    links.extend(transaction.links.iter().map(String::as_str));
+                    currencies.extend(transaction.postings.iter().map(|posting| posting.units.1.as_str()));
+                }
+                Directive::Document(document) => {
+                    tags.extend(document.tags.iter().map(String::as_str));
+                    links.extend(document.links.iter().map(String::as_str));
+                }
+                _ => {}
+            }
+        }
+
+        Self {
+            title: &ledger.options.title,
+            operating_currency: &ledger.options.operating_currency,
+            accounts,
+            currencies,
+            payees,
+            tags,
+            links,
+            errors: ledger.errors.len(),
+            date_first: ledger.entries.first().map(Entry::get_date),
+            date_last: ledger.entries.last().map(Entry::get_date),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::LedgerCache;
+    use crate::storage::{MemoryStorage, block_on};
+
+    const SOURCE: &str = r#"option "title" "Example"
+2020-01-01 open Assets:Cash
+2020-01-01 open Expenses:Food
+2020-01-02 * "Shop" "Groceries"
+  Expenses:Food  20 USD
+  Assets:Cash
+2020-01-03 balance Assets:Cash  0 USD
+"#;
+
+    fn storage() -> MemoryStorage {
+        MemoryStorage::new([("main.beancount", SOURCE), ("other.beancount", "")])
+    }
+
+    /// Load the ledger and handle the request, like the Worker does
+    fn call(storage: &MemoryStorage, method: Method, path: &str, params: &[(&str, &str)], body: &str) -> ApiResponse {
+        let now = time::macros::datetime!(2020-01-01 0:00 UTC);
+        let registry = block_on(LedgerRegistry::load(storage, &LedgerCache::default(), &["main.beancount".to_string()], now));
+        let request = ApiRequest {
+            method,
+            path,
+            params: params.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect(),
+            body: body.as_bytes().to_vec(),
+            upload: is_upload_path(path).then(|| Upload::Bytes(body.as_bytes().to_vec())),
+            role: Some(Role::ReadWrite),
+        };
+        block_on(route(&registry, storage, &request))
+    }
+
+    fn get(storage: &MemoryStorage, path: &str) -> ApiResponse {
+        call(storage, Method::Get, path, &[], "")
+    }
+
+    #[test]
+    fn paths() {
+        assert_eq!(parse_path("/api/ledgers"), Some((None, "ledgers")));
+        assert_eq!(parse_path("/example/api/errors/"), Some((Some("example"), "errors")));
+        assert_eq!(parse_path("/"), None);
+        assert_eq!(parse_path("/example/income_statement"), None);
+        assert!(is_api_path("/api/ledgers") && is_api_path("/example/api/errors"));
+        assert!(!is_api_path("/example/account/Assets:api/"));
+        assert!(is_upload_path("/example/api/add_document") && !is_upload_path("/example/api/add_entry"));
+    }
+
+    #[test]
+    fn ledger_data() {
+        let response = get(&storage(), "/example/api/ledger_data");
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body["success"], true);
+        let data = &response.body["data"];
+        assert_eq!(data["title"], "Example");
+        assert_eq!(data["accounts"], serde_json::json!(["Assets:Cash", "Expenses:Food"]));
+        assert_eq!(data["payees"], serde_json::json!(["Shop"]));
+        assert_eq!(data["errors"], 1);
+        assert_eq!(data["date_first"], "2020-01-01");
+    }
+
+    #[test]
+    fn roles() {
+        let storage = storage();
+        let request = |method, role| {
+            let registry = block_on(LedgerRegistry::load(&storage, &LedgerCache::default(), &["main.beancount".to_string()], time::OffsetDateTime::UNIX_EPOCH));
+            let request = ApiRequest {
+                method,
+                path: "/example/api/add_entry",
+                params: Params::new(),
+                body: Vec::new(),
+                upload: None,
+                role,
+            };
+            block_on(route(&registry, &storage, &request)).status
+        };
+        assert_eq!(request(Method::Post, None), 401);
+        assert_eq!(request(Method::Post, Some(Role::ReadOnly)), 403);
+        // the role is checked before the body
+        assert_eq!(request(Method::Post, Some(Role::ReadWrite)), 400);
+        assert_eq!(request(Method::Get, None), 401);
+        assert_eq!(request(Method::Get, Some(Role::ReadOnly)), 405);
+    }
+
+    #[test]
+    fn ledgers() {
+        let response = get(&storage(), "/api/ledgers");
+        assert_eq!(response.body["data"], serde_json::json!([{ "slug": "example", "title": "Example", "errors": 1, "loaded_at": "2020-01-01T00:00:00Z", "cached": false }]));
+    }
+
+    #[test]
+    fn reports() {
+        let storage = storage();
+        let response = get(&storage, "/example/api/errors");
+        assert_eq!(response.body["data"][0]["type"], "BeancountError");
+
+        let response = get(&storage, "/example/api/income_statement");
+        assert_eq!(response.body["data"]["expenses"]["balance_children"]["USD"], -20.);
+    }
+
+    #[test]
+    fn parameters() {
+        let storage = storage();
+        let response = get(&storage, "/example/api/account_journal");
+        assert_eq!(response.status, 400);
+        assert_eq!(response.body, serde_json::json!({ "success": false, "error": "Missing parameter: a" }));
+
+        let response = call(&storage, Method::Get, "/example/api/account_journal", &[("a", "Assets"), ("with_children", "true")], "");
+        assert_eq!(response.body["data"].as_array().unwrap().len(), 3);
+
+        assert_eq!(call(&storage, Method::Get, "/example/api/holdings", &[("group_by", "payee")], "").status, 400);
+    }
+
+    #[test]
+    fn events_tags_and_links() {
+        let source = format!("{SOURCE}2020-01-05 event \"location\" \"Berlin\"\n2020-01-06 * \"Invoice\" #work ^invoice-1\n  Expenses:Food  1 USD\n  Assets:Cash\n");
+        let storage = MemoryStorage::new([("main.beancount", source.as_str())]);
+        let get = |path, params: &[(&str, &str)]| call(&storage, Method::Get, path, params, "").body["data"].clone();
+        assert_eq!(get("/example/api/events", &[])[0]["latest"]["description"], "Berlin");
+        assert_eq!(get("/example/api/tags", &[]), serde_json::json!({ "work": 1 }));
+        assert_eq!(get("/example/api/links", &[]), serde_json::json!({ "invoice-1": 1 }));
+        assert_eq!(get("/example/api/linked_entries", &[("link", "invoice-1")])[0]["narration"], "Invoice");
+        assert_eq!(get("/example/api/linked_entries", &[("link", "invoice-2")]), serde_json::json!([]));
+    }
+
+    #[test]
+    fn autocomplete() {
+        let storage = storage();
+        let complete = |params: &[(&str, &str)]| call(&storage, Method::Get, "/example/api/autocomplete", params, "");
+        assert_eq!(complete(&[("attribute", "accounts"), ("q", "food")]).body["data"], serde_json::json!(["Expenses:Food"]));
+        assert_eq!(complete(&[("attribute", "payees")]).body["data"], serde_json::json!(["Shop"]));
+        assert_eq!(complete(&[("attribute", "accounts"), ("limit", "1")]).body["data"].as_array().unwrap().len(), 1);
+        assert_eq!(complete(&[("attribute", "dates")]).status, 400);
+        assert_eq!(complete(&[("attribute", "tags"), ("limit", "all")]).status, 400);
+
+        let response = call(&storage, Method::Get, "/example/api/payee_accounts", &[("payee", "Shop")], "");
+        assert_eq!(response.body["data"], serde_json::json!(["Assets:Cash", "Expenses:Food"]));
+        let response = call(&storage, Method::Get, "/example/api/payee_transaction", &[("payee", "Shop")], "");
+        assert_eq!(response.body["data"]["narration"], "Groceries");
+        assert_eq!(response.body["data"]["postings"][1], serde_json::json!({ "account": "Assets:Cash", "amount": "-20 USD" }));
+        assert_eq!(call(&storage, Method::Get, "/example/api/payee_transaction", &[("payee", "Nobody")], "").status, 404);
+    }
+
+    #[test]
+    fn charts() {
+        let storage = storage();
+        let response = call(&storage, Method::Get, "/example/api/net_worth", &[("interval", "year"), ("time", "2020")], "");
+        assert_eq!(
+            response.body["data"],
+            serde_json::json!([{ "currency": "USD", "values": [{ "date": "2020-12-31", "value": -20. }] }])
+        );
+
+        let params = [("a", "Expenses"), ("conversion", "units"), ("account", "Expenses")];
+        let response = call(&storage, Method::Get, "/example/api/account_balance", &params, "");
+        assert_eq!(response.body["data"][0]["values"][0]["value"], 20.);
+        assert_eq!(call(&storage, Method::Get, "/example/api/net_worth", &[("conversion", "usd")], "").status, 400);
+        assert_eq!(call(&storage, Method::Get, "/example/api/net_worth", &[("time", "soon")], "").status, 400);
+
+        let params = [("a", "Expenses"), ("interval", "year"), ("invert", "true")];
+        let response = call(&storage, Method::Get, "/example/api/interval_totals", &params, "");
+        assert_eq!(
+            response.body["data"],
+            serde_json::json!([{ "date": "2020-01-01", "balance": { "USD": -20. }, "account_balances": { "Expenses:Food": { "USD": -20. } } }])
+        );
+
+        let prices = MemoryStorage::new([("main.beancount", format!("{SOURCE}2020-01-05 price EUR 1.1 USD\n").as_str())]);
+        let response = call(&prices, Method::Get, "/example/api/commodities", &[("time", "2020")], "");
+        assert_eq!(
+            response.body["data"],
+            serde_json::json!([{ "base": "EUR", "quote": "USD", "prices": [{ "date": "2020-01-05", "value": 1.1f32 }] }])
+        );
+
+        let params = [("a", "Expenses"), ("currency", "USD"), ("depth", "1"), ("prune_zero", "true")];
+        let response = call(&storage, Method::Get, "/example/api/hierarchy", &params, "");
+        assert_eq!(
+            response.body["data"],
+            serde_json::json!({ "account": "Expenses", "balance": {}, "balance_children": { "USD": 20. }, "children": [] })
+        );
+        assert_eq!(call(&storage, Method::Get, "/example/api/hierarchy", &[("a", "Income")], "").body["data"], serde_json::Value::Null);
+        assert_eq!(call(&storage, Method::Get, "/example/api/hierarchy", &[("a", "Expenses"), ("depth", "deep")], "").status, 400);
+    }
+
+    #[test]
+    fn errors() {
+        let storage = storage();
+        assert_eq!(get(&storage, "/").status, 404);
+        assert_eq!(get(&storage, "/example/api/unknown").status, 404);
+        assert_eq!(get(&storage, "/other/api/errors").status, 404);
+        assert_eq!(get(&storage, "/api/errors").status, 404);
+        assert_eq!(get(&storage, "/example/api/ledgers").status, 404);
+
+        let response = call(&storage, Method::Post, "/example/api/errors", &[], "");
+        assert_eq!(response.status, 405);
+        assert_eq!(response.body["success"], false);
+    }
+
+    #[test]
+    fn source() {
+        let storage = storage();
+        assert_eq!(get(&storage, "/example/api/source_files").body["data"], serde_json::json!(["main.beancount"]));
+
+        let response = get(&storage, "/example/api/source");
+        let data = &response.body["data"];
+        assert_eq!(data["file_path"], "main.beancount");
+        assert_eq!(data["source"], SOURCE);
+        let sha256sum = data["sha256sum"].as_str().unwrap().to_string();
+        assert_eq!(call(&storage, Method::Get, "/example/api/source", &[("filename", "other.beancount")], "").status, 404);
+
+        let fixed = SOURCE.replace("  0 USD", "  -20 USD");
+        let body = serde_json::json!({ "file_path": "main.beancount", "source": fixed, "sha256sum": sha256sum }).to_string();
+        let response = call(&storage, Method::Put, "/example/api/source", &[], &body);
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body["data"]["sha256sum"], file::sha256_str(&fixed));
+        assert_eq!(response.body["data"]["errors"], serde_json::json!([]));
+
+        // saving again from the first version conflicts
+        let response = call(&storage, Method::Put, "/example/api/source", &[], &body);
+        assert_eq!(response.status, 409);
+        assert_eq!(call(&storage, Method::Put, "/example/api/source", &[], "{}").status, 400);
+
+        let body = serde_json::json!({ "file_path": "main.beancount", "sha256sum": file::sha256_str(&fixed) }).to_string();
+        let response = call(&storage, Method::Put, "/example/api/format_source", &[], &body);
+        assert_eq!(response.status, 200);
+        assert!(block_on(storage.read("main.beancount")).unwrap().contains("  Expenses:Food                  20 USD\n"));
+    }
+
+    #[test]
+    fn source_slice() {
+        let storage = storage();
+        let ledger = block_on(FavaLedger::load(&storage, "main.beancount"));
+        let entry_hash = hash_entry(ledger.entries.iter().find(|entry| matches!(entry, Directive::Balance(_))).unwrap());
+
+        let response = call(&storage, Method::Get, "/example/api/source_slice", &[("entry_hash", &entry_hash)], "");
+        let data = &response.body["data"];
+        assert_eq!(data["source"], "2020-01-03 balance Assets:Cash  0 USD");
+        assert_eq!(call(&storage, Method::Get, "/example/api/source_slice", &[("entry_hash", "missing")], "").status, 404);
+
+        let response = call(&storage, Method::Get, "/example/api/context", &[("entry_hash", &entry_hash)], "");
+        let context = &response.body["data"];
+        assert_eq!(context["sha256sum"], data["sha256sum"]);
+        assert_eq!(context["balances_after"]["Assets:Cash"], serde_json::json!([{ "units": { "number": -20., "currency": "USD" }, "cost": null }]));
+        assert_eq!(context["errors"].as_array().unwrap().len(), 1);
+        assert_eq!(call(&storage, Method::Get, "/example/api/context", &[("entry_hash", "missing")], "").status, 404);
+
+        let body = serde_json::json!({ "entry_hash": entry_hash, "source": "2020-01-03 balance Assets:Cash  -20 USD", "sha256sum": data["sha256sum"] });
+        let response = call(&storage, Method::Put, "/example/api/source_slice", &[], &body.to_string());
+        assert_eq!(response.body["data"]["errors"], serde_json::json!([]));
+        assert_eq!(block_on(storage.read("main.beancount")).unwrap(), SOURCE.replace("  0 USD", "  -20 USD"));
+    }
+
+    #[test]
+    fn documents() {
+        let storage = MemoryStorage::new([("main.beancount", &format!("option \"documents\" \"documents\"\n{SOURCE}")[..])]);
+        let ledger = block_on(FavaLedger::load(&storage, "main.beancount"));
+        let transaction = ledger.entries.iter().find(|entry| matches!(entry, Directive::Transactions(_))).unwrap();
+        let entry_hash = hash_entry(transaction);
+
+        let params = [("account", "Expenses:Food"), ("date", "2020-01-02"), ("filename", "receipt.pdf"), ("hash", &entry_hash)];
+        let response = call(&storage, Method::Put, "/example/api/add_document", &params, "%PDF");
+        assert_eq!(response.status, 200);
+        let data = &response.body["data"];
+        assert_eq!(data["t"], "Document");
+        assert_eq!(data["filename"], "documents/Expenses/Food/2020-01-02 receipt.pdf");
+        assert!(block_on(storage.read("main.beancount")).unwrap().contains("\"Groceries\"\n  document: \"2020-01-02 receipt.pdf\"\n"));
+
+        let response = call(&storage, Method::Get, "/example/api/document", &[("filename", "documents/Expenses/Food/2020-01-02 receipt.pdf")], "");
+        let file = response.file.unwrap();
+        assert_eq!((file.filename.as_str(), file.content_type, file.inline), ("2020-01-02 receipt.pdf", "application/pdf", true));
+        assert_eq!(block_on(storage.get(&file.path)).unwrap().unwrap().contents, b"%PDF");
+        assert_eq!(call(&storage, Method::Get, "/example/api/document", &[("filename", "main.beancount")], "").status, 404);
+
+        // the entry changed, so it has a new hash
+        let ledger = block_on(FavaLedger::load(&storage, "main.beancount"));
+        let transaction = ledger.entries.iter().find(|entry| matches!(entry, Directive::Transactions(_))).unwrap();
+        let params = [("entry_hash", &hash_entry(transaction)[..]), ("key", "document")];
+        assert_eq!(call(&storage, Method::Get, "/example/api/statement", &params, "").file.unwrap().path, "documents/Expenses/Food/2020-01-02 receipt.pdf");
+        let params = [("entry_hash", &hash_entry(transaction)[..]), ("key", "missing")];
+        assert_eq!(call(&storage, Method::Get, "/example/api/statement", &params, "").status, 404);
+
+        // an unknown entry is rejected before anything is stored
+        let params = [("account", "Expenses:Food"), ("date", "2020-01-03"), ("filename", "other.pdf"), ("hash", "missing")];
+        assert_eq!(call(&storage, Method::Put, "/example/api/add_document", &params, "").status, 404);
+        assert_eq!(block_on(storage.get("documents/Expenses/Food/2020-01-03 other.pdf")).unwrap(), None);
+    }
+
+    #[test]
+    fn add_entry() {
+        let storage = storage();
+        let body = r#"{"t": "Transaction", "date": "2020-01-04", "flag": "*", "narration": "Coffee",
+            "postings": [{"account": "Expenses:Food", "amount": "3 USD"}, {"account": "Assets:Cash"}]}"#;
+        let response = call(&storage, Method::Post, "/example/api/add_entry", &[], body);
+        assert_eq!(response.status, 200);
+
+        let ledger = block_on(FavaLedger::load(&storage, "main.beancount"));
+        let entry = ledger.get_entry(response.body["data"]["entry_hash"].as_str().unwrap()).unwrap();
+        assert_eq!(entry.get_meta().lineno, 9);
+        // the amount is aligned with the balance in the file
+        assert!(
+            block_on(storage.read("main.beancount"))
+                .unwrap()
+                .ends_with("\n\n2020-01-04 * \"Coffee\"\n  Expenses:Food                  3 USD\n  Assets:Cash\n")
+        );
+
+        let response = call(&storage, Method::Post, "/example/api/add_entry", &[], r#"{"t": "Note"}"#);
+        assert_eq!(response.status, 400);
+    }
+}
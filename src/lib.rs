@@ -3,23 +3,145 @@ use worker::*;
 /// Exceptions
 ///
 /// see: https://github.com/beancount/fava/blob/main/src/fava/helpers.py
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, serde::Serialize)]
+#[serde(tag = "type", content = "message")]
+#[allow(clippy::enum_variant_names)] // named after Fava's exceptions
 enum Helpers {
     /// TODO: Option<Directive>, Option<Source>
     BeancountError(String),
-    FavaError(String)
+    FavaError(String),
+    /// the file is not one of the ledger's source files
+    NonSourceFileError(String),
+    /// the file changed since it was read
+    ExternallyChangedError(String),
+    /// no entry has the given hash
+    EntryNotFoundForHashError(String),
+    /// the metadata of the entry doesn't link to a document
+    StatementNotFoundError(String),
 }
 
+impl std::fmt::Display for Helpers {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Helpers::BeancountError(message)
+            | Helpers::FavaError(message)
+            | Helpers::NonSourceFileError(message)
+            | Helpers::ExternallyChangedError(message)
+            | Helpers::EntryNotFoundForHashError(message)
+            | Helpers::StatementNotFoundError(message) => f.write_str(message),
+        }
+    }
+}
+
+mod auth;
 mod beans;
+mod cache;
 mod core;
+mod html;
+mod json_api;
+mod registry;
+mod serialisation;
+mod storage;
 mod util;
 
+/// Main file of the ledger, unless set by the `LEDGER_FILES` or `LEDGER_FILE` variables
+const DEFAULT_FILE: &str = "main.beancount";
+
+/// Main files of the ledgers: `LEDGER_FILES`, separated by commas, or the single `LEDGER_FILE`
+fn ledger_files(env: &Env) -> Vec<String> {
+    let files = env.var("LEDGER_FILES").map(|var| registry::parse_ledger_files(&var.to_string())).unwrap_or_default();
+    if !files.is_empty() {
+        return files;
+    }
+    vec![env.var("LEDGER_FILE").map(|var| var.to_string()).unwrap_or_else(|_| DEFAULT_FILE.into())]
+}
+
+thread_local! {
+    /// Files of a Worker without a storage binding, as in `wrangler dev`
+    static MEMORY: storage::MemoryStorage = storage::MemoryStorage::new([(DEFAULT_FILE, "")]);
+    /// Ledgers loaded by earlier requests to this isolate
+    static CACHE: cache::LedgerCache = cache::LedgerCache::default();
+}
+
 #[event(fetch)]
 async fn fetch(
-    _req: Request,
-    _env: Env,
+    mut req: Request,
+    env: Env,
     _ctx: Context,
 ) -> Result<Response> {
     console_error_panic_hook::set_once();
-    Response::ok("Hello World!")
+    let url = req.url()?;
+    let params = url.query_pairs().into_owned().collect();
+
+    let credentials = auth::Credentials::from_secrets(|name| env.secret(name).ok().map(|secret| secret.to_string()));
+    let role = credentials.role(req.headers().get("Authorization")?.as_deref());
+    if role.is_none() && !credentials.is_configured() {
+        console_warn!("No credentials are set, set {} to \"true\" to allow access without them", auth::AUTH_DISABLED);
+    }
+    let storage = storage::Backend::from_env(&env, MEMORY.with(Clone::clone));
+    let now = time::OffsetDateTime::from_unix_timestamp_nanos(i128::from(Date::now().as_millis()) * 1_000_000).unwrap_or(time::OffsetDateTime::UNIX_EPOCH);
+    let cache = CACHE.with(Clone::clone);
+    // unauthenticated requests are denied without loading any ledger
+    let files = if role.is_some() { ledger_files(&env) } else { Vec::new() };
+    let registry = registry::LedgerRegistry::load(&storage, &cache, &files, now).await;
+
+    // documents are streamed to the storage, which needs to know their length
+    let upload = if json_api::is_upload_path(url.path()) {
+        match req.inner().body() {
+            Some(stream) if req.headers().has("Content-Length")? => Some(storage::Upload::Stream(stream)),
+            _ => Some(storage::Upload::Bytes(req.bytes().await?)),
+        }
+    } else {
+        None
+    };
+    let body = if upload.is_some() { Vec::new() } else { req.bytes().await? };
+    let request = json_api::ApiRequest {
+        method: req.method(),
+        path: url.path(),
+        params,
+        body,
+        upload,
+        role,
+    };
+    if !json_api::is_api_path(request.path) {
+        let page = html::route(&registry, &storage, &request).await;
+        if matches!(page.status, 401 | 403) {
+            console_warn!("Denied {} {}", request.method, request.path);
+        }
+        let mut response = Response::from_html(page.body)?.with_status(page.status);
+        if page.status == 401 {
+            response.headers_mut().set("WWW-Authenticate", credentials.challenge())?;
+        }
+        if let Some(location) = page.location {
+            response.headers_mut().set("Location", &location)?;
+        }
+        return Ok(response);
+    }
+    let response = json_api::route(&registry, &storage, &request).await;
+    if matches!(response.status, 401 | 403) {
+        console_warn!("Denied {} {}: {}", request.method, request.path, response.body["error"]);
+    }
+    if response.status == 401 {
+        let headers = Headers::new();
+        headers.set("WWW-Authenticate", credentials.challenge())?;
+        return Ok(Response::from_json(&response.body)?.with_headers(headers).with_status(response.status));
+    }
+    if let Some(file) = response.file {
+        let error = match storage.body(&file.path).await {
+            Ok(Some(body)) => {
+                let headers = Headers::new();
+                headers.set("Content-Type", file.content_type)?;
+                headers.set("X-Content-Type-Options", "nosniff")?;
+                // active content like HTML and SVG is only downloaded, so it can't run in the origin of the Worker
+                let disposition = if file.inline { "inline" } else { "attachment" };
+                headers.set("Content-Disposition", &format!("{disposition}; filename=\"{}\"", file.filename.replace(|c: char| c == '"' || c.is_control(), "")))?;
+                return Ok(Response::from_body(body)?.with_headers(headers).with_status(response.status));
+            }
+            Ok(None) => json_api::ApiError::not_found(format!("Document not found: {}", file.path)),
+            Err(error) => error.into(),
+        };
+        let response = json_api::error_response(error);
+        return Ok(Response::from_json(&response.body)?.with_status(response.status));
+    }
+    Ok(Response::from_json(&response.body)?.with_status(response.status))
 }
\ No newline at end of file
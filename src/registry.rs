@@ -0,0 +1,144 @@
+//! The ledgers served by the Worker, by URL slug
+//!
+//! see: https://github.com/beancount/fava/blob/main/src/fava/application.py
+
+use std::rc::Rc;
+
+use serde::Serialize;
+use time::OffsetDateTime;
+
+use crate::cache::{LedgerCache, LoadInfo};
+use crate::core::FavaLedger;
+use crate::storage::Storage;
+use crate::util::slugify;
+
+/// Main files of the ledgers, from the `LEDGER_FILES` variable
+///
+/// The files are separated by commas or newlines, empty entries are skipped.
+pub(crate) fn parse_ledger_files(value: &str) -> Vec<String> {
+    value
+        .split([',', '\n'])
+        .map(str::trim)
+        .filter(|file| !file.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// A ledger in the index
+#[derive(Debug, PartialEq, Serialize)]
+pub(crate) struct LedgerSummary<'a> {
+    pub slug: &'a str,
+    pub title: &'a str,
+    pub errors: usize,
+    #[serde(flatten)]
+    pub load_info: LoadInfo,
+}
+
+/// The loaded ledgers, in the order of their files
+#[derive(Default)]
+pub(crate) struct LedgerRegistry {
+    ledgers: Vec<(String, Rc<FavaLedger>, LoadInfo)>,
+}
+
+impl LedgerRegistry {
+    /// Load every ledger, from the cache unless its files changed, slugged by its title
+    ///
+    /// The storage is listed once for all ledgers. A ledger whose title has no
+    /// letters or digits is slugged by its file name, and repeated slugs get a
+    /// number, like `personal-2`.
+    pub async fn load(storage: &impl Storage, cache: &LedgerCache, filenames: &[String], now: OffsetDateTime) -> Self {
+        let mut registry = Self::default();
+        if filenames.is_empty() {
+            return registry;
+        }
+        let files = storage.list().await.ok();
+        for filename in filenames {
+            let (ledger, load_info) = cache.load(storage, files.as_deref(), filename, now).await;
+            registry.insert(ledger, load_info);
+        }
+        registry
+    }
+
+    fn insert(&mut self, ledger: Rc<FavaLedger>, load_info: LoadInfo) {
+        let mut base = ledger.slug();
+        if base.is_empty() {
+            base = slugify(ledger.filename.trim_end_matches(".beancount"));
+        }
+        let mut slug = base.clone();
+        let mut index = 2;
+        while self.get(&slug).is_some() {
+            slug = format!("{base}-{index}");
+            index += 1;
+        }
+        self.ledgers.push((slug, ledger, load_info));
+    }
+
+    /// The ledger with the given slug
+    pub fn get(&self, slug: &str) -> Option<&FavaLedger> {
+        self.ledgers.iter().find(|(other, ..)| other == slug).map(|(_, ledger, _)| ledger.as_ref())
+    }
+
+    /// All ledgers, with their slug, title, number of errors and when they were loaded
+    pub fn index(&self) -> Vec<LedgerSummary<'_>> {
+        self.ledgers
+            .iter()
+            .map(|(slug, ledger, load_info)| LedgerSummary {
+                slug,
+                title: &ledger.options.title,
+                errors: ledger.errors.len(),
+                load_info: *load_info,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{MemoryStorage, block_on};
+
+    #[test]
+    fn files() {
+        assert_eq!(parse_ledger_files("personal.beancount, household.beancount,\n"), vec!["personal.beancount", "household.beancount"]);
+        assert_eq!(parse_ledger_files(""), Vec::<String>::new());
+    }
+
+    #[test]
+    fn slugs() {
+        let storage = MemoryStorage::new([
+            ("personal.beancount", "option \"title\" \"Personal\"\n"),
+            ("copy.beancount", "option \"title\" \"Personal\"\n"),
+            ("books/business.beancount", "option \"title\" \"!!!\"\n2020-01-01 close Assets:Cash\n"),
+        ]);
+        let files = parse_ledger_files("personal.beancount,copy.beancount,books/business.beancount");
+        let now = time::macros::datetime!(2020-01-01 0:00 UTC);
+        let registry = block_on(LedgerRegistry::load(&storage, &LedgerCache::default(), &files, now));
+        let load_info = LoadInfo { loaded_at: now, cached: false };
+
+        assert_eq!(
+            registry.index(),
+            vec![
+                LedgerSummary {
+                    slug: "personal",
+                    title: "Personal",
+                    errors: 0,
+                    load_info
+                },
+                LedgerSummary {
+                    slug: "personal-2",
+                    title: "Personal",
+                    errors: 0,
+                    load_info
+                },
+                LedgerSummary {
+                    slug: "books-business",
+                    title: "!!!",
+                    errors: 1,
+                    load_info
+                },
+            ]
+        );
+        assert_eq!(registry.get("personal-2").unwrap().filename, "copy.beancount");
+        assert!(registry.get("missing").is_none());
+    }
+}
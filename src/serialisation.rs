@@ -0,0 +1,276 @@
+//! Entries sent as JSON by the frontend
+//!
+//! see: https://github.com/beancount/fava/blob/main/src/fava/serialisation.py
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::Helpers;
+use crate::beans::abc::{AAmount, Balance, Directive, Meta, MetaValue, Note, Transaction};
+use crate::beans::str::posting_amount_to_string;
+use crate::beans::flags::Flags;
+use crate::beans::parser::{self, RawPosting, is_currency, parse_number};
+
+/// A number, as JSON number or string
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+enum JsonNumber {
+    Number(f32),
+    String(String),
+}
+
+impl JsonNumber {
+    fn value(&self) -> Result<f32, Helpers> {
+        match self {
+            Self::Number(number) => Ok(*number),
+            Self::String(string) => parse_number(string.trim()).ok_or_else(|| Helpers::FavaError(format!("Invalid number: {string}"))),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct JsonAmount {
+    number: JsonNumber,
+    currency: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct JsonPosting {
+    account: String,
+    /// units with an optional cost and price, like `10 STOCK {100 USD} @ 110 USD`, empty to be interpolated
+    #[serde(default)]
+    amount: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "t")]
+enum JsonEntry {
+    Transaction {
+        date: time::Date,
+        flag: String,
+        #[serde(default)]
+        payee: Option<String>,
+        #[serde(default)]
+        narration: String,
+        #[serde(default)]
+        tags: BTreeSet<String>,
+        #[serde(default)]
+        links: BTreeSet<String>,
+        #[serde(default)]
+        meta: BTreeMap<String, serde_json::Value>,
+        postings: Vec<JsonPosting>,
+    },
+    Balance {
+        date: time::Date,
+        account: String,
+        amount: JsonAmount,
+        #[serde(default)]
+        meta: BTreeMap<String, serde_json::Value>,
+    },
+    Note {
+        date: time::Date,
+        account: String,
+        comment: String,
+        #[serde(default)]
+        meta: BTreeMap<String, serde_json::Value>,
+    },
+}
+
+fn check_account(account: &str) -> Result<String, Helpers> {
+    if parser::is_account(account) {
+        Ok(account.to_string())
+    } else {
+        Err(Helpers::FavaError(format!("Invalid account: {account}")))
+    }
+}
+
+fn check_currency(currency: &str) -> Result<String, Helpers> {
+    if is_currency(currency) {
+        Ok(currency.to_string())
+    } else {
+        Err(Helpers::FavaError(format!("Invalid currency: {currency}")))
+    }
+}
+
+/// Strings are written on a single line, so they cannot contain line breaks
+fn check_text(text: String) -> Result<String, Helpers> {
+    if text.contains(char::is_control) {
+        Err(Helpers::FavaError(format!("Invalid text: {text:?}")))
+    } else {
+        Ok(text)
+    }
+}
+
+fn check_tags_or_links(values: BTreeSet<String>) -> Result<BTreeSet<String>, Helpers> {
+    match values.iter().find(|value| !parser::is_tag_or_link(value)) {
+        Some(value) => Err(Helpers::FavaError(format!("Invalid tag or link: {value}"))),
+        None => Ok(values),
+    }
+}
+
+#[allow(dead_code)]
+fn parse_amount(number: &str, currency: &str) -> Result<AAmount, Helpers> {
+    let number = parse_number(number).ok_or_else(|| Helpers::FavaError(format!("Invalid number: {number}")))?;
+    Ok(AAmount(number, check_currency(currency)?))
+}
+
+fn deserialise_meta(values: BTreeMap<String, serde_json::Value>) -> Result<Meta, Helpers> {
+    let mut meta = Meta::default();
+    for (key, value) in values {
+        if !parser::is_key(&key) {
+            return Err(Helpers::FavaError(format!("Invalid metadata key: {key}")));
+        }
+        let value = match value {
+            serde_json::Value::String(string) => MetaValue::String(check_text(string)?),
+            serde_json::Value::Bool(bool) => MetaValue::Bool(bool),
+            serde_json::Value::Number(number) => MetaValue::Number(number.as_f64().unwrap_or_default() as f32),
+            _ => return Err(Helpers::FavaError(format!("Invalid metadata value for {key}"))),
+        };
+        meta.values.insert(key, value);
+    }
+    Ok(meta)
+}
+
+/// A posting, with empty units if they are left out
+fn deserialise_posting(posting: JsonPosting) -> Result<RawPosting, Helpers> {
+    let account = check_account(&posting.account)?;
+    let raw = parser::parse_posting(account, &posting.amount).map_err(|error| Helpers::FavaError(format!("Invalid amount: {}: {error}", posting.amount)))?;
+    if let Some(label) = raw.posting.cost.as_ref().and_then(|cost| cost.label.clone()) {
+        check_text(label)?;
+    }
+    Ok(raw)
+}
+
+/// An entry sent by the frontend
+///
+/// Postings of transactions are kept as sent, a left out amount has empty
+/// units, but they have to balance.
+pub(crate) fn deserialise(json: &str) -> Result<Directive, Helpers> {
+    let entry: JsonEntry = serde_json::from_str(json).map_err(|error| Helpers::FavaError(format!("Invalid entry: {error}")))?;
+    let entry = match entry {
+        JsonEntry::Transaction {
+            date,
+            flag,
+            payee,
+            narration,
+            tags,
+            links,
+            meta,
+            postings,
+        } => {
+            let postings = postings.into_iter().map(deserialise_posting).collect::<Result<Vec<_>, _>>()?;
+            parser::book(postings.clone()).map_err(Helpers::FavaError)?;
+            Directive::Transactions(Transaction {
+                date,
+                flag: flag.parse::<Flags>()?,
+                payee: payee.filter(|payee| !payee.is_empty()).map(check_text).transpose()?,
+                narration: check_text(narration)?,
+                tags: check_tags_or_links(tags)?,
+                links: check_tags_or_links(links)?,
+                postings: postings.into_iter().map(|raw| raw.posting).collect(),
+                meta: deserialise_meta(meta)?,
+            })
+        }
+        JsonEntry::Balance { date, account, amount, meta } => Directive::Balance(Balance {
+            meta: deserialise_meta(meta)?,
+            ..Balance::new(date, check_account(&account)?, AAmount(amount.number.value()?, check_currency(&amount.currency)?))
+        }),
+        JsonEntry::Note { date, account, comment, meta } => Directive::Note(Note {
+            date,
+            account: check_account(&account)?,
+            comment: check_text(comment)?,
+            meta: deserialise_meta(meta)?,
+        }),
+    };
+    Ok(entry)
+}
+
+/// A transaction to edit and send back, without its metadata
+pub(crate) fn transaction_template(transaction: &Transaction) -> serde_json::Value {
+    let postings = transaction
+        .postings
+        .iter()
+        .map(|posting| JsonPosting {
+            account: posting.account.clone(),
+            amount: posting_amount_to_string(posting),
+        })
+        .collect();
+    let entry = JsonEntry::Transaction {
+        date: transaction.date,
+        flag: transaction.flag.as_str().to_string(),
+        payee: transaction.payee.clone(),
+        narration: transaction.narration.clone(),
+        tags: transaction.tags.clone(),
+        links: transaction.links.clone(),
+        meta: BTreeMap::new(),
+        postings,
+    };
+    serde_json::to_value(entry).expect("entries serialise to JSON")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::macros::date;
+
+    #[test]
+    fn transaction() {
+        let entry = deserialise(
+            r#"{"t": "Transaction", "date": "2020-01-02", "flag": "*", "payee": "", "narration": "Lunch", "tags": ["work"],
+                "meta": {"receipt": "yes"},
+                "postings": [{"account": "Expenses:Food", "amount": "12.50 EUR @ 1.2 USD"}, {"account": "Assets:Cash", "amount": ""}]}"#,
+        )
+        .unwrap();
+        let Directive::Transactions(transaction) = entry else { panic!("expected a transaction") };
+        assert_eq!(transaction.date, date!(2020-01-02));
+        assert_eq!(transaction.payee, None);
+        assert_eq!(transaction.meta.get("receipt"), Some(&MetaValue::String("yes".into())));
+        assert_eq!(transaction.postings[0].price, Some(AAmount(1.2, "USD".into())));
+        assert_eq!(transaction.postings[1].units, AAmount(0., String::new()));
+    }
+
+    #[test]
+    fn invalid() {
+        let unbalanced = r#"{"t": "Transaction", "date": "2020-01-02", "flag": "*", "postings": [{"account": "Expenses:Food", "amount": "10 USD"}]}"#;
+        assert_eq!(deserialise(unbalanced), Err(Helpers::FavaError("Transaction does not balance: 10 USD".into())));
+        assert!(deserialise(r#"{"t": "Price", "date": "2020-01-02"}"#).is_err());
+        assert!(deserialise(r#"{"t": "Note", "date": "2020-01-02", "account": "cash", "comment": ""}"#).is_err());
+        // nothing can break out of the entry's lines
+        let injected = r#"{"t": "Balance", "date": "2020-01-02", "account": "Assets:Cash", "amount": {"number": "1", "currency": "USD\n2020-01-03 close Assets:Cash"}}"#;
+        assert!(matches!(deserialise(injected), Err(Helpers::FavaError(error)) if error.starts_with("Invalid currency")));
+        assert!(deserialise(r#"{"t": "Note", "date": "2020-01-02", "account": "Assets:Cash", "comment": "a\nb"}"#).is_err());
+        assert!(deserialise(r#"{"t": "Note", "date": "2020-01-02", "account": "Assets:Cash", "comment": "", "meta": {"a b": 1}}"#).is_err());
+        let tag = r#"{"t": "Transaction", "date": "2020-01-02", "flag": "*", "tags": ["a b"], "postings": []}"#;
+        assert_eq!(deserialise(tag), Err(Helpers::FavaError("Invalid tag or link: a b".into())));
+    }
+
+    #[test]
+    fn balance() {
+        let entry = deserialise(r#"{"t": "Balance", "date": "2020-01-02", "account": "Assets:Cash", "amount": {"number": "100", "currency": "USD"}}"#).unwrap();
+        assert_eq!(entry, Directive::Balance(Balance::new(date!(2020-01-02), "Assets:Cash", AAmount(100., "USD".into()))));
+    }
+
+    #[test]
+    fn template() {
+        let json = r#"{"t": "Transaction", "date": "2020-01-02", "flag": "!", "payee": "Broker", "narration": "Buy", "links": ["trade"],
+            "postings": [{"account": "Assets:Broker", "amount": "2 STOCK {100 USD, 2020-01-02, \"lot\"} @ 110 USD"}, {"account": "Assets:Cash", "amount": "-200 USD"}]}"#;
+        let Directive::Transactions(transaction) = deserialise(json).unwrap() else { panic!("expected a transaction") };
+        let cost = transaction.postings[0].cost.as_ref().unwrap();
+        assert_eq!((cost.number, cost.date, cost.label.as_deref()), (100., Some(date!(2020-01-02)), Some("lot")));
+
+        // the cost is kept as written
+        let template = transaction_template(&transaction);
+        assert_eq!(template["t"], "Transaction");
+        assert_eq!(template["flag"], "!");
+        assert_eq!(template["postings"][0]["amount"], "2 STOCK {100 USD, 2020-01-02, \"lot\"} @ 110 USD");
+        assert_eq!(template["meta"], serde_json::json!({}));
+        let Directive::Transactions(round_trip) = deserialise(&template.to_string()).unwrap() else { panic!("expected a transaction") };
+        assert_eq!(round_trip.links, transaction.links);
+        assert_eq!(round_trip.postings, transaction.postings);
+
+        // costs that are left out need the lots of the ledger
+        let sale = json.replace(r#"2 STOCK {100 USD, 2020-01-02, \"lot\"} @ 110 USD"#, "-2 STOCK {}");
+        assert!(matches!(deserialise(&sale), Err(Helpers::FavaError(error)) if error.starts_with("Costs without")));
+    }
+}
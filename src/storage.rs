@@ -0,0 +1,403 @@
+//! Storage of the ledger files
+//!
+//! Files live in an R2 bucket or a KV namespace bound to the Worker, see
+//! `wrangler.toml`. Without either binding, an in-memory store is used.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+use sha2::{Digest, Sha256};
+use worker::worker_sys::web_sys::ReadableStream;
+
+use crate::Helpers;
+use crate::beans::funcs::hex;
+
+/// Binding of the R2 bucket
+pub(crate) const R2_BINDING: &str = "LEDGER_BUCKET";
+/// Binding of the KV namespace
+pub(crate) const KV_BINDING: &str = "LEDGER_KV";
+
+/// A stored file and its ETag
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct StoredFile {
+    pub contents: Vec<u8>,
+    pub etag: String,
+}
+
+impl StoredFile {
+    pub fn text(self) -> Result<String, Helpers> {
+        String::from_utf8(self.contents).map_err(|error| Helpers::FavaError(format!("File is not valid UTF-8: {error}")))
+    }
+}
+
+/// Path and ETag of a stored file
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct FileInfo {
+    pub path: String,
+    /// changes with every write, empty if the store doesn't know it
+    pub etag: String,
+}
+
+/// The contents of an uploaded file
+#[derive(Debug)]
+pub(crate) enum Upload {
+    Bytes(Vec<u8>),
+    /// body of a request with a `Content-Length`, which R2 stores as it arrives
+    Stream(ReadableStream),
+}
+
+impl Upload {
+    /// The contents, read into memory
+    pub async fn bytes(&self) -> Result<Vec<u8>, Helpers> {
+        use worker::js_sys::Uint8Array;
+        use worker::wasm_bindgen::JsValue;
+        use worker::worker_sys::web_sys::Response;
+
+        match self {
+            Self::Bytes(contents) => Ok(contents.clone()),
+            Self::Stream(stream) => {
+                let js_error = |error: JsValue| storage_error(format!("{error:?}"));
+                let response = Response::new_with_opt_readable_stream(Some(stream)).map_err(js_error)?;
+                let buffer = worker::wasm_bindgen_futures::JsFuture::from(response.array_buffer().map_err(js_error)?).await.map_err(js_error)?;
+                Ok(Uint8Array::new(&buffer).to_vec())
+            }
+        }
+    }
+}
+
+/// List, read and write files
+pub(crate) trait Storage {
+    /// All files, sorted by path
+    async fn list(&self) -> Result<Vec<FileInfo>, Helpers>;
+    async fn get(&self, path: &str) -> Result<Option<StoredFile>, Helpers>;
+    /// Write the file, returning its new ETag
+    async fn put(&self, path: &str, contents: &[u8]) -> Result<String, Helpers>;
+    /// Write the file only if its ETag is still `etag`, returning its new ETag, `None` if it changed
+    async fn put_if_match(&self, path: &str, contents: &[u8], etag: &str) -> Result<Option<String>, Helpers>;
+
+    /// Write an uploaded file, returning its new ETag
+    async fn put_upload(&self, path: &str, upload: &Upload) -> Result<String, Helpers> {
+        match upload {
+            Upload::Bytes(contents) => self.put(path, contents).await,
+            Upload::Stream(_) => self.put(path, &upload.bytes().await?).await,
+        }
+    }
+
+    /// Contents of a text file, an error if it does not exist
+    async fn read(&self, path: &str) -> Result<String, Helpers> {
+        self.get(path).await?.ok_or_else(|| Helpers::FavaError(format!("File not found: {path}")))?.text()
+    }
+}
+
+/// ETag of stores that don't compute one themselves
+pub(crate) fn content_etag(contents: &[u8]) -> String {
+    hex(&Sha256::digest(contents))
+}
+
+fn storage_error(error: impl std::fmt::Display) -> Helpers {
+    Helpers::FavaError(format!("Storage error: {error}"))
+}
+
+/// Files in an R2 bucket
+pub(crate) struct R2Storage(pub worker::Bucket);
+
+impl Storage for R2Storage {
+    async fn list(&self) -> Result<Vec<FileInfo>, Helpers> {
+        let mut files = Vec::new();
+        let mut cursor = None;
+        loop {
+            let mut list = self.0.list();
+            if let Some(cursor) = cursor {
+                list = list.cursor(cursor);
+            }
+            let objects = list.execute().await.map_err(storage_error)?;
+            files.extend(objects.objects().iter().map(|object| FileInfo {
+                path: object.key(),
+                etag: object.etag(),
+            }));
+            if !objects.truncated() {
+                break;
+            }
+            cursor = objects.cursor();
+        }
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(files)
+    }
+
+    async fn get(&self, path: &str) -> Result<Option<StoredFile>, Helpers> {
+        let Some(object) = self.0.get(path).execute().await.map_err(storage_error)? else {
+            return Ok(None);
+        };
+        let etag = object.etag();
+        let contents = match object.body() {
+            Some(body) => body.bytes().await.map_err(storage_error)?,
+            None => Vec::new(),
+        };
+        Ok(Some(StoredFile { contents, etag }))
+    }
+
+    async fn put(&self, path: &str, contents: &[u8]) -> Result<String, Helpers> {
+        let object = self.0.put(path, contents.to_vec()).execute().await.map_err(storage_error)?;
+        Ok(object.etag())
+    }
+
+    /// With R2's `onlyIf`, which the bindings of `worker` don't expose for writes
+    async fn put_if_match(&self, path: &str, contents: &[u8], etag: &str) -> Result<Option<String>, Helpers> {
+        use worker::js_sys::{Object, Reflect, Uint8Array};
+        use worker::wasm_bindgen::{JsCast, JsValue};
+        use worker::worker_sys::{R2Bucket, R2Object};
+
+        let js_error = |error: JsValue| storage_error(format!("{error:?}"));
+        let condition = Object::new();
+        Reflect::set(&condition, &"etagMatches".into(), &etag.into()).map_err(js_error)?;
+        let options = Object::new();
+        Reflect::set(&options, &"onlyIf".into(), &condition).map_err(js_error)?;
+
+        let bucket: &R2Bucket = self.0.as_ref().unchecked_ref();
+        let promise = bucket.put(path.to_string(), Uint8Array::from(contents).into(), options.into()).map_err(js_error)?;
+        let object = worker::wasm_bindgen_futures::JsFuture::from(promise).await.map_err(js_error)?;
+        // the object is null if the condition failed
+        if object.is_null() || object.is_undefined() {
+            return Ok(None);
+        }
+        object.unchecked_into::<R2Object>().etag().map(Some).map_err(js_error)
+    }
+
+    /// Without reading streamed uploads into memory
+    async fn put_upload(&self, path: &str, upload: &Upload) -> Result<String, Helpers> {
+        match upload {
+            Upload::Bytes(contents) => self.put(path, contents).await,
+            Upload::Stream(stream) => {
+                let object = self.0.put(path, stream.clone()).execute().await.map_err(storage_error)?;
+                Ok(object.etag())
+            }
+        }
+    }
+}
+
+/// Files in a KV namespace, with their ETag as the metadata of each key
+///
+/// Keys written from outside of the Worker have no metadata, they are listed
+/// with an empty ETag.
+pub(crate) struct KvStorage(pub worker::kv::KvStore);
+
+impl Storage for KvStorage {
+    async fn list(&self) -> Result<Vec<FileInfo>, Helpers> {
+        let mut files = Vec::new();
+        let mut cursor = None;
+        loop {
+            let mut list = self.0.list();
+            if let Some(cursor) = cursor {
+                list = list.cursor(cursor);
+            }
+            let keys = list.execute().await.map_err(storage_error)?;
+            files.extend(keys.keys.into_iter().map(|key| FileInfo {
+                etag: key.metadata.as_ref().and_then(|etag| etag.as_str()).unwrap_or_default().to_string(),
+                path: key.name,
+            }));
+            if keys.list_complete {
+                break;
+            }
+            cursor = keys.cursor;
+        }
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(files)
+    }
+
+    async fn get(&self, path: &str) -> Result<Option<StoredFile>, Helpers> {
+        let contents = self.0.get(path).bytes().await.map_err(storage_error)?;
+        Ok(contents.map(|contents| StoredFile {
+            etag: content_etag(&contents),
+            contents,
+        }))
+    }
+
+    async fn put(&self, path: &str, contents: &[u8]) -> Result<String, Helpers> {
+        let etag = content_etag(contents);
+        self.0
+            .put_bytes(path, contents)
+            .map_err(storage_error)?
+            .metadata(&etag)
+            .map_err(storage_error)?
+            .execute()
+            .await
+            .map_err(storage_error)?;
+        Ok(etag)
+    }
+
+    /// KV has no conditional writes, a write between the check and this one is lost
+    async fn put_if_match(&self, path: &str, contents: &[u8], etag: &str) -> Result<Option<String>, Helpers> {
+        match self.get(path).await? {
+            Some(file) if file.etag == etag => self.put(path, contents).await.map(Some),
+            _ => Ok(None),
+        }
+    }
+}
+
+/// Files in memory, for tests and local development
+///
+/// Clones share the same files.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct MemoryStorage(Rc<RefCell<BTreeMap<String, Vec<u8>>>>);
+
+impl MemoryStorage {
+    pub fn new<'a>(files: impl IntoIterator<Item = (&'a str, &'a str)>) -> Self {
+        let files = files.into_iter().map(|(path, contents)| (path.to_string(), contents.as_bytes().to_vec())).collect();
+        Self(Rc::new(RefCell::new(files)))
+    }
+}
+
+impl Storage for MemoryStorage {
+    async fn list(&self) -> Result<Vec<FileInfo>, Helpers> {
+        Ok(self
+            .0
+            .borrow()
+            .iter()
+            .map(|(path, contents)| FileInfo {
+                path: path.clone(),
+                etag: content_etag(contents),
+            })
+            .collect())
+    }
+
+    async fn get(&self, path: &str) -> Result<Option<StoredFile>, Helpers> {
+        Ok(self.0.borrow().get(path).map(|contents| StoredFile {
+            contents: contents.clone(),
+            etag: content_etag(contents),
+        }))
+    }
+
+    async fn put(&self, path: &str, contents: &[u8]) -> Result<String, Helpers> {
+        self.0.borrow_mut().insert(path.to_string(), contents.to_vec());
+        Ok(content_etag(contents))
+    }
+
+    async fn put_if_match(&self, path: &str, contents: &[u8], etag: &str) -> Result<Option<String>, Helpers> {
+        let mut files = self.0.borrow_mut();
+        match files.get_mut(path) {
+            Some(current) if content_etag(current) == etag => {
+                *current = contents.to_vec();
+                Ok(Some(content_etag(contents)))
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+/// The storage bound to the Worker
+pub(crate) enum Backend {
+    R2(R2Storage),
+    Kv(KvStorage),
+    Memory(MemoryStorage),
+}
+
+impl Backend {
+    /// The R2 bucket or KV namespace of `env`, or else `memory`
+    pub fn from_env(env: &worker::Env, memory: MemoryStorage) -> Self {
+        if let Ok(bucket) = env.bucket(R2_BINDING) {
+            Self::R2(R2Storage(bucket))
+        } else if let Ok(kv) = env.kv(KV_BINDING) {
+            Self::Kv(KvStorage(kv))
+        } else {
+            Self::Memory(memory)
+        }
+    }
+
+    /// Body of the file to send in a response, streamed from R2, `None` if it does not exist
+    pub async fn body(&self, path: &str) -> Result<Option<worker::ResponseBody>, Helpers> {
+        let Self::R2(storage) = self else {
+            return Ok(self.get(path).await?.map(|file| worker::ResponseBody::Body(file.contents)));
+        };
+        let Some(object) = storage.0.get(path).execute().await.map_err(storage_error)? else {
+            return Ok(None);
+        };
+        match object.body() {
+            Some(body) => body.response_body().map(Some).map_err(storage_error),
+            None => Ok(Some(worker::ResponseBody::Empty)),
+        }
+    }
+}
+
+impl Storage for Backend {
+    async fn list(&self) -> Result<Vec<FileInfo>, Helpers> {
+        match self {
+            Self::R2(storage) => storage.list().await,
+            Self::Kv(storage) => storage.list().await,
+            Self::Memory(storage) => storage.list().await,
+        }
+    }
+
+    async fn get(&self, path: &str) -> Result<Option<StoredFile>, Helpers> {
+        match self {
+            Self::R2(storage) => storage.get(path).await,
+            Self::Kv(storage) => storage.get(path).await,
+            Self::Memory(storage) => storage.get(path).await,
+        }
+    }
+
+    async fn put(&self, path: &str, contents: &[u8]) -> Result<String, Helpers> {
+        match self {
+            Self::R2(storage) => storage.put(path, contents).await,
+            Self::Kv(storage) => storage.put(path, contents).await,
+            Self::Memory(storage) => storage.put(path, contents).await,
+        }
+    }
+
+    async fn put_if_match(&self, path: &str, contents: &[u8], etag: &str) -> Result<Option<String>, Helpers> {
+        match self {
+            Self::R2(storage) => storage.put_if_match(path, contents, etag).await,
+            Self::Kv(storage) => storage.put_if_match(path, contents, etag).await,
+            Self::Memory(storage) => storage.put_if_match(path, contents, etag).await,
+        }
+    }
+
+    async fn put_upload(&self, path: &str, upload: &Upload) -> Result<String, Helpers> {
+        match self {
+            Self::R2(storage) => storage.put_upload(path, upload).await,
+            Self::Kv(storage) => storage.put_upload(path, upload).await,
+            Self::Memory(storage) => storage.put_upload(path, upload).await,
+        }
+    }
+}
+
+/// Run a future that never waits, as the in-memory storage doesn't
+#[cfg(test)]
+pub(crate) fn block_on<F: std::future::Future>(future: F) -> F::Output {
+    let mut context = std::task::Context::from_waker(std::task::Waker::noop());
+    let mut future = std::pin::pin!(future);
+    match future.as_mut().poll(&mut context) {
+        std::task::Poll::Ready(output) => output,
+        std::task::Poll::Pending => panic!("future is pending"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn memory() {
+        let storage = MemoryStorage::new([("main.beancount", "include \"other.beancount\"\n")]);
+        let shared = storage.clone();
+
+        let etag = block_on(storage.put("other.beancount", b"; other\n")).unwrap();
+        let files = block_on(shared.list()).unwrap();
+        assert_eq!(files.iter().map(|file| file.path.as_str()).collect::<Vec<_>>(), vec!["main.beancount", "other.beancount"]);
+        assert_eq!(files[1].etag, etag);
+
+        let file = block_on(shared.get("other.beancount")).unwrap().unwrap();
+        assert_eq!(file.etag, etag);
+        assert_eq!(file.text().unwrap(), "; other\n");
+
+        assert_ne!(block_on(storage.put("other.beancount", b"; changed\n")).unwrap(), etag);
+        // a write with the ETag from before the change is refused
+        assert_eq!(block_on(storage.put_if_match("other.beancount", b"; stale\n", &etag)).unwrap(), None);
+        assert_eq!(block_on(shared.read("other.beancount")).unwrap(), "; changed\n");
+        let current = block_on(shared.get("other.beancount")).unwrap().unwrap().etag;
+        assert!(block_on(storage.put_if_match("other.beancount", b"; fresh\n", &current)).unwrap().is_some());
+        assert_eq!(block_on(storage.put_if_match("missing.beancount", b"", &current)).unwrap(), None);
+        assert_eq!(block_on(shared.get("missing.beancount")).unwrap(), None);
+        assert!(block_on(shared.read("missing.beancount")).is_err());
+    }
+}
@@ -0,0 +1,353 @@
+//! Date-related functionality
+//!
+//! see: https://github.com/beancount/fava/blob/main/src/fava/util/date.py
+
+use time::{Date, Duration, Month};
+
+use crate::Helpers;
+
+/// The possible intervals
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum Interval {
+    Year,
+    Quarter,
+    Month,
+    Week,
+    Day,
+}
+
+impl std::str::FromStr for Interval {
+    type Err = Helpers;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "year" => Ok(Self::Year),
+            "quarter" => Ok(Self::Quarter),
+            "month" => Ok(Self::Month),
+            "week" => Ok(Self::Week),
+            "day" => Ok(Self::Day),
+            invalid_str => Err(Helpers::FavaError(format!("Invalid interval: {invalid_str}"))),
+        }
+    }
+}
+
+impl Interval {
+    #[allow(dead_code)]
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Year => "year",
+            Self::Quarter => "quarter",
+            Self::Month => "month",
+            Self::Week => "week",
+            Self::Day => "day",
+        }
+    }
+
+    /// Start of the interval that contains `date`
+    ///
+    /// Dates before the first representable interval boundary start at [`Date::MIN`].
+    pub fn start(&self, date: Date, fye: &FiscalYearEnd) -> Date {
+        match self {
+            Self::Year | Self::Quarter => {
+                let step = if *self == Self::Year { 12 } else { 3 };
+                fye.boundaries(date.year() - 1, step)
+                    .chain(fye.boundaries(date.year(), step))
+                    .filter(|boundary| *boundary <= date)
+                    .max()
+                    .unwrap_or(Date::MIN)
+            }
+            Self::Month => date.replace_day(1).expect("every month has a first day"),
+            Self::Week => date.checked_sub(Duration::days(date.weekday().number_days_from_monday().into())).unwrap_or(Date::MIN),
+            Self::Day => date,
+        }
+    }
+
+    /// Start of the interval following the one that contains `date`, `None` if it is not representable
+    pub fn next(&self, date: Date, fye: &FiscalYearEnd) -> Option<Date> {
+        match self {
+            Self::Year | Self::Quarter => {
+                let step = if *self == Self::Year { 12 } else { 3 };
+                (date.year() - 1..=date.year() + 1).flat_map(|year| fye.boundaries(year, step)).filter(|boundary| *boundary > date).min()
+            }
+            Self::Month => {
+                let first = self.start(date, fye);
+                first.checked_add(Duration::days(days_in_month(first.year(), first.month()).into()))
+            }
+            Self::Week => self.start(date, fye).checked_add(Duration::weeks(1)),
+            Self::Day => date.next_day(),
+        }
+    }
+
+    /// Format `date`, the start of an interval, as a label for that interval
+    ///
+    /// Years and quarters are labelled by fiscal year (`FY2020Q1`) unless the
+    /// fiscal year is the calendar year.
+    #[allow(dead_code)]
+    pub fn format_date(&self, date: Date, fye: &FiscalYearEnd) -> String {
+        let prefix = if fye.is_calendar_year() { "" } else { "FY" };
+        match self {
+            Self::Year => format!("{prefix}{}", fye.fiscal_year(date)),
+            Self::Quarter => format!("{prefix}{}Q{}", fye.fiscal_year(date), fye.fiscal_quarter(date)),
+            Self::Month => format!("{} {}", &format!("{:?}", date.month())[..3], date.year()),
+            Self::Week => format!("{}W{:02}", date.year(), date.monday_based_week()),
+            Self::Day => date.to_string(),
+        }
+    }
+}
+
+/// Month and day of the last day of the fiscal year
+///
+/// The fiscal year is named by the calendar year it ends in, so with an end of
+/// `03-31`, FY2018 runs from 2017-04-01 to 2018-03-31.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct FiscalYearEnd {
+    pub month: Month,
+    pub day: u8,
+}
+
+impl Default for FiscalYearEnd {
+    fn default() -> Self {
+        Self {
+            month: Month::December,
+            day: 31,
+        }
+    }
+}
+
+impl std::str::FromStr for FiscalYearEnd {
+    type Err = Helpers;
+
+    /// Parse a `MM-DD` string
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || Helpers::FavaError(format!("Invalid fiscal year end: {s}"));
+
+        let (month, day) = s.split_once('-').ok_or_else(invalid)?;
+        let month = month.parse::<u8>().ok().and_then(|m| Month::try_from(m).ok()).ok_or_else(invalid)?;
+        let day = day.parse::<u8>().map_err(|_| invalid())?;
+        // a non-leap year, as the fiscal year has to end on the same day every year
+        Date::from_calendar_date(2001, month, day).map_err(|_| invalid())?;
+
+        Ok(Self { month, day })
+    }
+}
+
+impl FiscalYearEnd {
+    pub fn is_calendar_year(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Month and day on which every fiscal year starts
+    fn start(&self) -> (Month, u8) {
+        let end = Date::from_calendar_date(2001, self.month, self.day).expect("validated on parse");
+        let start = end.next_day().expect("2001 is not the last representable year");
+        (start.month(), start.day())
+    }
+
+    /// Interval boundaries every `step` months starting with the fiscal year that starts in `year`
+    ///
+    /// Boundaries outside of the representable years are left out.
+    fn boundaries(&self, year: i32, step: u8) -> impl Iterator<Item = Date> {
+        let (month, day) = self.start();
+        (0..12 / step).filter_map(move |i| {
+            let months = u8::from(month) - 1 + i * step;
+            let (year, month) = (year + i32::from(months / 12), Month::try_from(months % 12 + 1).expect("in range"));
+            let day = day.min(days_in_month(year, month));
+            Date::from_calendar_date(year, month, day).ok()
+        })
+    }
+
+    /// The fiscal year containing `date`
+    pub fn fiscal_year(&self, date: Date) -> i32 {
+        let start = Interval::Year.start(date, self);
+        if start.month() == Month::January && start.day() == 1 { start.year() } else { start.year() + 1 }
+    }
+
+    /// The quarter (1 to 4) of the fiscal year containing `date`
+    pub fn fiscal_quarter(&self, date: Date) -> u8 {
+        let year_start = Interval::Year.start(date, self);
+        let quarter_start = Interval::Quarter.start(date, self);
+        let months = (quarter_start.year() - year_start.year()) * 12 + i32::from(u8::from(quarter_start.month()))
+            - i32::from(u8::from(year_start.month()));
+        (months / 3 + 1) as u8
+    }
+}
+
+fn days_in_month(year: i32, month: Month) -> u8 {
+    time::util::days_in_month(month, year)
+}
+
+/// A range of dates, including `begin` but excluding `end`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct DateRange {
+    pub begin: Date,
+    pub end: Date,
+}
+
+impl DateRange {
+    pub fn contains(&self, date: Date) -> bool {
+        self.begin <= date && date < self.end
+    }
+}
+
+/// An interval, from its first through its last day
+///
+/// Unlike a [`DateRange`], it can include the last representable date.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Period {
+    pub begin: Date,
+    pub last: Date,
+}
+
+/// Iterator over the intervals covering the dates from `begin` through `last`
+///
+/// The first period starts at the beginning of the interval containing `begin`,
+/// so every period spans a whole interval.
+pub(crate) struct DateRanges {
+    /// start of the next period, `None` after the last representable one
+    next: Option<Date>,
+    last: Date,
+    interval: Interval,
+    fye: FiscalYearEnd,
+}
+
+impl Iterator for DateRanges {
+    type Item = Period;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let begin = self.next.filter(|next| *next <= self.last)?;
+        self.next = self.interval.next(begin, &self.fye);
+        let last = self.next.and_then(Date::previous_day).unwrap_or(Date::MAX);
+        Some(Period { begin, last })
+    }
+}
+
+/// Periods for all intervals between `begin` and `last`, including both
+pub(crate) fn dateranges(begin: Date, last: Date, interval: Interval, fye: &FiscalYearEnd) -> DateRanges {
+    DateRanges {
+        next: Some(interval.start(begin, fye)),
+        last,
+        interval,
+        fye: *fye,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+    use time::macros::date;
+
+    #[test]
+    fn intervals_from_str() {
+        assert_eq!(Interval::from_str("year"), Ok(Interval::Year));
+        assert_eq!(Interval::from_str("quarter"), Ok(Interval::Quarter));
+        assert_eq!(Interval::from_str("month"), Ok(Interval::Month));
+        assert_eq!(Interval::from_str("week"), Ok(Interval::Week));
+        assert_eq!(Interval::from_str("day"), Ok(Interval::Day));
+        assert!(Interval::from_str("decade").is_err());
+    }
+
+    #[test]
+    fn next_interval() {
+        let fye = FiscalYearEnd::default();
+        assert_eq!(Interval::Year.next(date!(2020-05-17), &fye), Some(date!(2021-01-01)));
+        assert_eq!(Interval::Quarter.next(date!(2020-05-17), &fye), Some(date!(2020-07-01)));
+        assert_eq!(Interval::Quarter.next(date!(2020-12-31), &fye), Some(date!(2021-01-01)));
+        assert_eq!(Interval::Month.next(date!(2020-12-31), &fye), Some(date!(2021-01-01)));
+        assert_eq!(Interval::Month.next(date!(2020-02-01), &fye), Some(date!(2020-03-01)));
+        // 2020-05-17 is a Sunday
+        assert_eq!(Interval::Week.next(date!(2020-05-17), &fye), Some(date!(2020-05-18)));
+        assert_eq!(Interval::Week.next(date!(2020-05-18), &fye), Some(date!(2020-05-25)));
+        assert_eq!(Interval::Day.next(date!(2020-05-17), &fye), Some(date!(2020-05-18)));
+
+        // there is no interval after the last representable date
+        for interval in [Interval::Year, Interval::Quarter, Interval::Month, Interval::Week, Interval::Day] {
+            assert_eq!(interval.next(Date::MAX, &FiscalYearEnd::from_str("03-31").unwrap()), None);
+            assert_eq!(interval.next(Date::MAX, &fye), None);
+            assert!(interval.start(Date::MIN, &fye) <= Date::MIN);
+        }
+    }
+
+    #[test]
+    fn interval_start() {
+        let fye = FiscalYearEnd::default();
+        assert_eq!(Interval::Year.start(date!(2020-05-17), &fye), date!(2020-01-01));
+        assert_eq!(Interval::Quarter.start(date!(2020-05-17), &fye), date!(2020-04-01));
+        assert_eq!(Interval::Month.start(date!(2020-05-17), &fye), date!(2020-05-01));
+        assert_eq!(Interval::Week.start(date!(2020-05-17), &fye), date!(2020-05-11));
+        assert_eq!(Interval::Day.start(date!(2020-05-17), &fye), date!(2020-05-17));
+    }
+
+    #[test]
+    fn fiscal_year_end() {
+        assert_eq!(FiscalYearEnd::from_str("03-31"), Ok(FiscalYearEnd { month: Month::March, day: 31 }));
+        assert!(FiscalYearEnd::from_str("02-29").is_err());
+        assert!(FiscalYearEnd::from_str("13-01").is_err());
+        assert!(FiscalYearEnd::from_str("0331").is_err());
+
+        let fye = FiscalYearEnd::from_str("03-31").unwrap();
+        assert_eq!(Interval::Year.start(date!(2018-03-31), &fye), date!(2017-04-01));
+        assert_eq!(Interval::Year.next(date!(2018-03-31), &fye), Some(date!(2018-04-01)));
+        assert_eq!(Interval::Quarter.start(date!(2018-02-10), &fye), date!(2018-01-01));
+        assert_eq!(fye.fiscal_year(date!(2018-03-31)), 2018);
+        assert_eq!(fye.fiscal_year(date!(2018-04-01)), 2019);
+        assert_eq!(fye.fiscal_quarter(date!(2018-04-01)), 1);
+        assert_eq!(fye.fiscal_quarter(date!(2018-02-10)), 4);
+    }
+
+    #[test]
+    fn fiscal_year_end_mid_month() {
+        let fye = FiscalYearEnd::from_str("01-30").unwrap();
+        // quarters start on the 31st, clamped to the end of shorter months
+        assert_eq!(Interval::Quarter.next(date!(2020-02-01), &fye), Some(date!(2020-04-30)));
+        assert_eq!(Interval::Quarter.next(date!(2020-04-30), &fye), Some(date!(2020-07-31)));
+    }
+
+    #[test]
+    fn format_dates() {
+        let fye = FiscalYearEnd::default();
+        assert_eq!(Interval::Year.format_date(date!(2020-01-01), &fye), "2020");
+        assert_eq!(Interval::Quarter.format_date(date!(2020-04-01), &fye), "2020Q2");
+        assert_eq!(Interval::Month.format_date(date!(2020-04-01), &fye), "Apr 2020");
+        assert_eq!(Interval::Week.format_date(date!(2020-01-06), &fye), "2020W01");
+        assert_eq!(Interval::Day.format_date(date!(2020-01-06), &fye), "2020-01-06");
+
+        let fye = FiscalYearEnd::from_str("03-31").unwrap();
+        assert_eq!(Interval::Year.format_date(date!(2017-04-01), &fye), "FY2018");
+        assert_eq!(Interval::Quarter.format_date(date!(2018-01-01), &fye), "FY2018Q4");
+    }
+
+    #[test]
+    fn test_dateranges() {
+        let fye = FiscalYearEnd::default();
+        let periods: Vec<Period> = dateranges(date!(2020-01-15), date!(2020-02-29), Interval::Month, &fye).collect();
+        assert_eq!(
+            periods,
+            vec![
+                Period { begin: date!(2020-01-01), last: date!(2020-01-31) },
+                Period { begin: date!(2020-02-01), last: date!(2020-02-29) },
+            ]
+        );
+
+        let fye = FiscalYearEnd::from_str("03-31").unwrap();
+        let periods: Vec<Period> = dateranges(date!(2020-01-15), date!(2020-04-30), Interval::Year, &fye).collect();
+        assert_eq!(
+            periods,
+            vec![
+                Period { begin: date!(2019-04-01), last: date!(2020-03-31) },
+                Period { begin: date!(2020-04-01), last: date!(2021-03-31) },
+            ]
+        );
+
+        assert_eq!(dateranges(date!(2020-01-15), date!(2020-01-14), Interval::Day, &fye).count(), 0);
+        assert_eq!(dateranges(date!(2020-01-15), date!(2020-01-15), Interval::Day, &fye).count(), 1);
+
+        // the last representable date is in the last period
+        for interval in [Interval::Year, Interval::Quarter, Interval::Month, Interval::Week, Interval::Day] {
+            let periods: Vec<Period> = dateranges(date!(9999-12-30), Date::MAX, interval, &fye).collect();
+            assert_eq!(periods.last().unwrap().last, Date::MAX);
+        }
+        let periods: Vec<Period> = dateranges(date!(9999-12-30), Date::MAX, Interval::Day, &fye).collect();
+        assert_eq!(periods, vec![Period { begin: date!(9999-12-30), last: date!(9999-12-30) }, Period { begin: Date::MAX, last: Date::MAX }]);
+    }
+}
@@ -1 +1,17 @@
-mod ranking;
\ No newline at end of file
+pub(crate) mod date;
+pub(crate) mod ranking;
+
+/// Lowercase alphanumeric words of `text`, joined by dashes
+///
+/// see: https://github.com/beancount/fava/blob/main/src/fava/util/__init__.py
+pub(crate) fn slugify(text: &str) -> String {
+    let mut slug = String::new();
+    for c in text.chars() {
+        if c.is_alphanumeric() {
+            slug.extend(c.to_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    slug.trim_end_matches('-').to_string()
+}
@@ -1,8 +1,15 @@
 //! Ranking utilities
 
+use std::borrow::Borrow;
+use std::cmp::Ordering;
 use std::collections::HashMap;
+use std::hash::Hash;
+
+use serde::{Deserialize, Serialize};
 use time::Date;
 
+use crate::Helpers;
+
 const ZERO: f64 = 0.0;
 const DEFAULT_RATE: f64 = std::f64::consts::LN_2 / 365.0;
 
@@ -24,6 +31,9 @@ const DEFAULT_RATE: f64 = std::f64::consts::LN_2 / 365.0;
 /// To avoid huge numbers, we actually compute and store the logarithm of that
 /// sum.
 ///
+/// As the score is a sum, likes can be added in any order: a ranker restored
+/// with serde only needs the likes of entries added since it was saved.
+///
 /// # Arguments
 ///
 /// * `list` - If given, this list is ranked by `.sort()` otherwise all
@@ -33,35 +43,29 @@ const DEFAULT_RATE: f64 = std::f64::consts::LN_2 / 365.0;
 ///   `1/e`. The default rate is set to `ln(2) / 365` so
 ///   that a 'like' from a year ago will count half as much as one from
 ///   today.
-pub struct ExponentialDecayRanker {
-    list: Option<Vec<String>>,
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(bound(serialize = "K: Serialize", deserialize = "K: Deserialize<'de> + Eq + Hash"))]
+pub struct ExponentialDecayRanker<K = String> {
+    list: Option<Vec<K>>,
     rate: f64,
-    scores: HashMap<String, f64>,
+    scores: HashMap<K, f64>,
 }
 
-impl Default for ExponentialDecayRanker {
+impl<K: Clone + Eq + Hash> Default for ExponentialDecayRanker<K> {
     fn default() -> Self {
         Self::new()
     }
 }
 
-impl ExponentialDecayRanker {
+impl<K: Clone + Eq + Hash> ExponentialDecayRanker<K> {
     /// Create a new ExponentialDecayRanker with default rate
     pub fn new() -> Self {
-        Self {
-            list: None,
-            rate: DEFAULT_RATE,
-            scores: HashMap::new(),
-        }
+        Self::new_with_rate(DEFAULT_RATE)
     }
 
     /// Create a new ExponentialDecayRanker with a specific list
-    pub fn new_with_list(list: Vec<String>) -> Self {
-        Self {
-            list: Some(list),
-            rate: DEFAULT_RATE,
-            scores: HashMap::new(),
-        }
+    pub fn new_with_list(list: Vec<K>) -> Self {
+        Self::new_with_list_and_rate(list, DEFAULT_RATE)
     }
 
     /// Create a new ExponentialDecayRanker with a specific rate
@@ -74,7 +78,7 @@ impl ExponentialDecayRanker {
     }
 
     /// Create a new ExponentialDecayRanker with both list and rate
-    pub fn new_with_list_and_rate(list: Vec<String>, rate: f64) -> Self {
+    pub fn new_with_list_and_rate(list: Vec<K>, rate: f64) -> Self {
         Self {
             list: Some(list),
             rate,
@@ -88,17 +92,36 @@ impl ExponentialDecayRanker {
     ///
     /// * `item` - An item in the list that is being ranked.
     /// * `date` - The date on which the item has been liked.
-    pub fn update(&mut self, item: &str, date: Date) {
-        let score = self.get(item);
+    #[allow(dead_code)]
+    pub fn update<Q>(&mut self, item: &Q, date: Date)
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        self.update_weighted(item, date, 1.0);
+    }
+
+    /// Add a 'like' that counts `weight` times as much as a plain one.
+    ///
+    /// Weights that are not positive are ignored.
+    pub fn update_weighted<Q>(&mut self, item: &Q, date: Date, weight: f64)
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        if weight <= 0.0 {
+            return;
+        }
         // Convert to ordinal day count to match Python's date.toordinal()
         // Python's toordinal() counts days since January 1, year 1
-        let time = self.date_to_ordinal(date) as f64;
-        let higher = score.max(time * self.rate);
-        let lower = score.min(time * self.rate);
-        self.scores.insert(
-            item.to_string(),
-            higher + (lower - higher).exp().ln_1p(),
-        );
+        let like = self.date_to_ordinal(date) as f64 * self.rate + weight.ln();
+        let score = log_add(self.get(item), like);
+        match self.scores.get_mut(item) {
+            Some(current) => *current = score,
+            None => {
+                self.scores.insert(item.to_owned(), score);
+            }
+        }
     }
 
     /// Convert a Date to ordinal days (matching Python's date.toordinal())
@@ -109,50 +132,88 @@ impl ExponentialDecayRanker {
         (date - base_date).whole_days() as i32 + 1
     }
 
+    /// Add the likes of `other`, which must have the same rate.
+    ///
+    /// Items of the list of `other` are appended to the list.
+    pub fn merge(&mut self, other: &Self) -> Result<(), Helpers> {
+        if self.rate != other.rate {
+            return Err(Helpers::FavaError(format!("Can't merge rankers with the rates {} and {}", self.rate, other.rate)));
+        }
+        for (item, score) in &other.scores {
+            let merged = log_add(self.get(item), *score);
+            self.scores.insert(item.clone(), merged);
+        }
+        if let (Some(list), Some(other)) = (&mut self.list, &other.list) {
+            for item in other {
+                if !list.contains(item) {
+                    list.push(item.clone());
+                }
+            }
+        }
+        Ok(())
+    }
+
     /// Get the current score for an item, or zero.
-    pub fn get(&self, item: &str) -> f64 {
+    pub fn get<Q>(&self, item: &Q) -> f64
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
         self.scores.get(item).copied().unwrap_or(ZERO)
     }
 
-    /// Return items sorted by rank.
-    pub fn sort(&self) -> Vec<String> {
-        if let Some(ref list) = self.list {
-            let mut sorted_list = list.clone();
-            sorted_list.sort_by(|a, b| {
-                self.get(b).partial_cmp(&self.get(a)).unwrap_or(std::cmp::Ordering::Equal)
-            });
-            sorted_list
-        } else {
-            let mut items: Vec<String> = self.scores.keys().cloned().collect();
-            items.sort_by(|a, b| {
-                self.get(b).partial_cmp(&self.get(a)).unwrap_or(std::cmp::Ordering::Equal)
-            });
-            items
+    /// The ranked items, the list or all liked items, with their scores
+    fn scored_items(&self) -> Vec<(K, f64)> {
+        match &self.list {
+            Some(list) => list.iter().map(|item| (item.clone(), self.get(item))).collect(),
+            None => self.scores.iter().map(|(item, score)| (item.clone(), *score)).collect(),
         }
     }
 
+    /// Return items sorted by rank.
+    pub fn sort(&self) -> Vec<K> {
+        self.sort_with_scores().into_iter().map(|(item, _)| item).collect()
+    }
+
     /// Return items with their scores, sorted by rank.
-    pub fn sort_with_scores(&self) -> Vec<(String, f64)> {
-        let items = if let Some(ref list) = self.list {
-            list.clone()
-        } else {
-            self.scores.keys().cloned().collect()
-        };
-
-        let mut scored_items: Vec<(String, f64)> = items
+    pub fn sort_with_scores(&self) -> Vec<(K, f64)> {
+        let mut scored_items = self.scored_items();
+        // stable, so that ties keep the order of the list
+        scored_items.sort_by(|a, b| by_score(a.1, b.1));
+        scored_items
+    }
+
+    /// The `k` highest ranked items, in order, without sorting all items.
+    ///
+    /// Ties are broken like by `.sort()`.
+    #[allow(dead_code)]
+    pub fn top(&self, k: usize) -> Vec<K> {
+        let mut scored_items: Vec<(usize, K, f64)> = self
+            .scored_items()
             .into_iter()
-            .map(|item| {
-                let score = self.get(&item);
-                (item, score)
-            })
+            .enumerate()
+            .map(|(index, (item, score))| (index, item, score))
             .collect();
+        let compare = |a: &(usize, K, f64), b: &(usize, K, f64)| by_score(a.2, b.2).then(a.0.cmp(&b.0));
+        if k < scored_items.len() {
+            scored_items.select_nth_unstable_by(k, compare);
+            scored_items.truncate(k);
+        }
+        scored_items.sort_by(compare);
+        scored_items.into_iter().map(|(_, item, _)| item).collect()
+    }
+}
 
-        scored_items.sort_by(|a, b| {
-            b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal)
-        });
+/// The logarithm of `exp(left) + exp(right)`
+fn log_add(left: f64, right: f64) -> f64 {
+    let higher = left.max(right);
+    let lower = left.min(right);
+    higher + (lower - higher).exp().ln_1p()
+}
 
-        scored_items
-    }
+/// Higher scores first
+fn by_score(a: f64, b: f64) -> Ordering {
+    b.partial_cmp(&a).unwrap_or(Ordering::Equal)
 }
 
 #[cfg(test)]
@@ -284,7 +345,7 @@ mod tests {
         let mut slow_decay_ranker = ExponentialDecayRanker::new_with_rate(0.001);
         
         let old_date = date!(2020-01-01);
-        let new_date = date!(2023-01-01);
+        let _new_date = date!(2023-01-01);
         
         fast_decay_ranker.update("item", old_date);
         slow_decay_ranker.update("item", old_date);
@@ -323,4 +384,73 @@ mod tests {
             assert!(score > 0.0);
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_weighted_likes() {
+        let mut ranker = ExponentialDecayRanker::new();
+        ranker.update_weighted("heavy", date!(2023-01-01), 3.0);
+        ranker.update("light", date!(2023-01-01));
+        ranker.update("light", date!(2023-01-01));
+        ranker.update_weighted("ignored", date!(2023-01-01), 0.0);
+        assert_eq!(ranker.sort(), vec!["heavy", "light"]);
+
+        // a weight of two is the same as two likes
+        let mut doubled = ExponentialDecayRanker::<String>::new();
+        doubled.update_weighted("light", date!(2023-01-01), 2.0);
+        assert!((doubled.get("light") - ranker.get("light")).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_generic_keys() {
+        let mut ranker = ExponentialDecayRanker::new_with_list(vec![1_u32, 2, 3]);
+        ranker.update(&3, date!(2023-01-01));
+        ranker.update(&2, date!(2022-01-01));
+        assert_eq!(ranker.sort(), vec![3, 2, 1]);
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut first = ExponentialDecayRanker::new_with_list(vec!["a".to_string(), "b".to_string()]);
+        first.update("a", date!(2023-01-01));
+        let mut second = ExponentialDecayRanker::new_with_list(vec!["b".to_string(), "c".to_string()]);
+        second.update("b", date!(2023-01-01));
+        second.update("b", date!(2023-01-02));
+        second.update("c", date!(2024-06-01));
+
+        first.merge(&second).unwrap();
+        assert_eq!(first.sort(), vec!["c", "b", "a"]);
+        assert!(first.merge(&ExponentialDecayRanker::new_with_rate(1.0)).is_err());
+    }
+
+    #[test]
+    fn test_top() {
+        let list: Vec<String> = ["a", "b", "c", "d", "e"].map(String::from).to_vec();
+        let mut ranker = ExponentialDecayRanker::new_with_list(list);
+        ranker.update("d", date!(2023-03-01));
+        ranker.update("b", date!(2023-02-01));
+        assert_eq!(ranker.top(3), vec!["d", "b", "a"]);
+        assert_eq!(ranker.top(10), ranker.sort());
+        assert!(ranker.top(0).is_empty());
+    }
+
+    #[test]
+    fn test_serde_and_incremental_updates() {
+        let likes = [("a", date!(2023-01-01)), ("b", date!(2023-02-01)), ("a", date!(2023-03-01))];
+        let mut full = ExponentialDecayRanker::new();
+        for (item, date) in likes {
+            full.update(item, date);
+        }
+
+        // save after the first like, restore and add the rest
+        let mut saved = ExponentialDecayRanker::new();
+        saved.update(likes[0].0, likes[0].1);
+        let json = serde_json::to_string(&saved).unwrap();
+        let mut restored: ExponentialDecayRanker = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored.get("a"), saved.get("a"));
+        for (item, date) in &likes[1..] {
+            restored.update(*item, *date);
+        }
+        assert_eq!(restored.sort(), full.sort());
+        assert!((restored.get("a") - full.get("a")).abs() < 1e-9);
+    }
+}